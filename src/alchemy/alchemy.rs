@@ -3,7 +3,8 @@ use std::sync::Arc;
 use serde::Serialize;
 use serde_json::Value;
 
-use crate::{constants::Constants, error::ServerError};
+use crate::constants::Constants;
+use crate::error::{ServerError, UpstreamService};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -20,6 +21,7 @@ pub struct AlchemyNftSalesEndpointQueryParams {
 }
 
 pub async fn alchemy_nft_sales_request(
+    http_client: &reqwest::Client,
     query: AlchemyNftSalesEndpointQueryParams,
     config: Arc<Constants>,
 ) -> Result<Value, ServerError> {
@@ -30,15 +32,14 @@ pub async fn alchemy_nft_sales_request(
         alchemy_chain, config.alchemy_api_key
     );
 
-    let client = reqwest::Client::new();
-    let res = client
+    let res = http_client
         .get(endpoint_url)
         .query(&query)
         .send()
         .await
-        .map_err(|e| ServerError::from(e))?
+        .map_err(|e| ServerError::from_reqwest(UpstreamService::Alchemy, e))?
         .json::<Value>()
         .await
-        .map_err(|e| ServerError::from(e))?;
+        .map_err(|e| ServerError::from_reqwest(UpstreamService::Alchemy, e))?;
     Ok(res)
 }
@@ -1,85 +1,266 @@
-use anyhow::anyhow;
-use chrono::prelude::*;
+use chrono::{DateTime as ChronoDateTime, Utc};
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use warp::{
     http::header::{HeaderMap, HeaderValue, AUTHORIZATION},
     reject, Filter,
 };
 
-use crate::{constants, error::ServerError};
+use ethers::core::rand::{thread_rng, RngCore};
+use ethers::utils::{hex, keccak256};
+use std::sync::Arc;
+
+use crate::db::api_keys::{find_api_key_by_hash, touch_api_key};
+use crate::error::ServerError;
+use crate::state::{with_state, AppState};
 
 const BEARER: &str = "Bearer ";
+const API_KEY_PREFIX: &str = "snp_";
+
+/// Well-known key for the sandbox; mints made with it return mock responses.
+/// Only accepted with `SANDBOX_API_KEY_ENABLED=true`.
+pub const SANDBOX_API_KEY: &str = "APITEST";
+
+/// Lifetime of a session issued through Sign-In With Ethereum.
+pub const SIWE_SESSION_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Mint,
+    Admin,
+    /// Read access plus mocked mints, for integration testing.
+    Sandbox,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Mint => "mint",
+            Scope::Admin => "admin",
+            Scope::Sandbox => "sandbox",
+        }
+    }
+
+    /// Whether holding `self` allows access to a route requiring `required`.
+    fn grants(&self, required: Scope) -> bool {
+        match self {
+            Scope::Admin => true,
+            Scope::Sandbox => matches!(required, Scope::Read | Scope::Mint | Scope::Sandbox),
+            scope => *scope == required,
+        }
+    }
+}
+
+/// The authenticated caller of a request.
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    /// API key id or JWT subject.
+    pub subject: String,
+    pub scopes: Vec<Scope>,
+    /// Checksummed wallet address for callers signed in with Ethereum.
+    pub wallet_address: Option<String>,
+    /// Slugs of the collections the caller may access, all of them when `None`.
+    pub collections: Option<Vec<String>>,
+}
+
+impl AuthContext {
+    /// Sandbox callers get mocked responses from state-changing endpoints.
+    pub fn is_sandbox(&self) -> bool {
+        self.scopes.contains(&Scope::Sandbox)
+            && !self
+                .scopes
+                .iter()
+                .any(|s| matches!(s, Scope::Mint | Scope::Admin))
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.scopes.contains(&Scope::Admin)
+    }
+
+    fn has_scope(&self, required: Scope) -> bool {
+        self.scopes.iter().any(|s| s.grants(required))
+    }
+
+    /// Fails with 403 when the caller is limited to other collections.
+    pub fn ensure_collection(&self, slug: &str) -> Result<(), ServerError> {
+        match &self.collections {
+            Some(collections) if !collections.iter().any(|c| c == slug) => Err(
+                ServerError::Forbidden(format!("no access to collection {}", slug)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Claims {
     sub: String,
     exp: usize,
+    /// Tokens issued before scopes existed carry none and get `read` and `mint`.
+    #[serde(default)]
+    scopes: Option<Vec<Scope>>,
+    /// Set on sessions issued through Sign-In With Ethereum.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wallet: Option<String>,
 }
 
-pub fn with_auth() -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
-    warp::header::headers_cloned()
-        .map(move |headers: HeaderMap| headers)
+pub fn with_auth(
+    state: Arc<AppState>,
+    required: Scope,
+) -> impl Filter<Extract = (AuthContext,), Error = warp::Rejection> + Clone {
+    with_state(state)
+        .and(warp::header::headers_cloned())
+        .and(warp::any().map(move || required))
         .and_then(authorize)
 }
 
-pub fn _create_jwt(uid: &str) -> Result<String, ServerError> {
-    let config = constants::Constants::new();
-    // let config = Arc::new(config);
-
-    let jwt_secret = config.jwt_secret.as_bytes();
-    let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::days(365))
-        .expect("valid timestamp")
-        .timestamp();
-
+/// Issues a read-only session token for a wallet verified through SIWE.
+pub fn create_session_jwt(
+    address: &str,
+    expires_at: ChronoDateTime<Utc>,
+    jwt_secret: &str,
+) -> Result<String, ServerError> {
     let claims = Claims {
-        sub: uid.to_owned(),
-        exp: expiration as usize,
+        sub: address.to_string(),
+        exp: expires_at.timestamp() as usize,
+        scopes: Some(vec![Scope::Read]),
+        wallet: Some(address.to_string()),
     };
     let header = Header::new(Algorithm::HS512);
-    let jwt = encode(&header, &claims, &EncodingKey::from_secret(jwt_secret))
-        .map_err(|_| ServerError::from(anyhow!("jwt token creation error")))?;
+    encode(
+        &header,
+        &claims,
+        &EncodingKey::from_secret(jwt_secret.as_bytes()),
+    )
+    .map_err(|_| ServerError::Internal("jwt token creation error".to_string()))
+}
+
+/// Generates a new API key secret and returns it with the hash to store.
+pub fn generate_api_key() -> (String, String) {
+    let mut secret = [0u8; 32];
+    thread_rng().fill_bytes(&mut secret);
+    let api_key = format!("{}{}", API_KEY_PREFIX, hex::encode(secret));
+    let key_hash = hash_api_key(&api_key);
+    (api_key, key_hash)
+}
 
-    println!("JWT: {}", jwt);
-    Ok(jwt)
+pub fn hash_api_key(api_key: &str) -> String {
+    hex::encode(keccak256(api_key.as_bytes()))
 }
 
-async fn authorize(headers: HeaderMap<HeaderValue>) -> Result<String, warp::Rejection> {
-    let config = constants::Constants::new();
-    // let config = Arc::new(config);
-
-    let jwt_secret = config.jwt_secret.as_bytes();
-
-    match jwt_from_header(&headers) {
-        Ok(jwt) => {
-            if jwt == "APITEST" {
-                return Ok("test".to_string());
-            }
-            let decoded = decode::<Claims>(
-                &jwt,
-                &DecodingKey::from_secret(jwt_secret),
-                &Validation::new(Algorithm::HS512),
-            )
-            .map_err(|_| reject::custom(ServerError::from(anyhow!("jwt token not valid"))))?;
-
-            Ok(decoded.claims.sub)
+async fn authorize(
+    state: Arc<AppState>,
+    headers: HeaderMap<HeaderValue>,
+    required: Scope,
+) -> Result<AuthContext, warp::Rejection> {
+    let token = token_from_header(&headers).map_err(reject::custom)?;
+
+    let auth = if token == SANDBOX_API_KEY {
+        if !state.config.sandbox_api_key_enabled {
+            return Err(reject::custom(ServerError::Unauthorized(
+                "sandbox key is disabled".to_string(),
+            )));
+        }
+        AuthContext {
+            subject: "sandbox".to_string(),
+            scopes: vec![Scope::Sandbox],
+            wallet_address: None,
+            collections: None,
         }
-        Err(e) => return Err(reject::custom(ServerError::from(e))),
+    } else if token.starts_with(API_KEY_PREFIX) {
+        authorize_api_key(state, &token).await?
+    } else {
+        authorize_jwt(&state, &token)?
+    };
+
+    if !auth.has_scope(required) {
+        return Err(reject::custom(ServerError::Forbidden(format!(
+            "missing required scope: {}",
+            required.as_str()
+        ))));
+    }
+
+    Ok(auth)
+}
+
+async fn authorize_api_key(
+    state: Arc<AppState>,
+    api_key: &str,
+) -> Result<AuthContext, warp::Rejection> {
+    let key_hash = hash_api_key(api_key);
+    let key = find_api_key_by_hash(state.mongo_client.clone(), &key_hash)
+        .await
+        .map_err(|e| reject::custom(ServerError::from(e)))?
+        .ok_or_else(|| {
+            reject::custom(ServerError::Unauthorized("api key not valid".to_string()))
+        })?;
+
+    if key.revoked_at.is_some() {
+        return Err(reject::custom(ServerError::Unauthorized(
+            "api key has been revoked".to_string(),
+        )));
+    }
+    if matches!(key.expires_at, Some(expires_at) if expires_at <= DateTime::now()) {
+        return Err(reject::custom(ServerError::Unauthorized(
+            "api key has expired".to_string(),
+        )));
     }
+
+    // Recording usage must not slow down or fail the request.
+    let mongo_client = state.mongo_client.clone();
+    let key_id = key.key_id.clone();
+    tokio::spawn(async move {
+        if let Err(e) = touch_api_key(mongo_client, &key_id).await {
+            eprintln!(
+                "Failed to update last_used_at for api key {}: {}",
+                key_id, e
+            );
+        }
+    });
+
+    Ok(AuthContext {
+        subject: key.key_id,
+        scopes: key.scopes,
+        wallet_address: None,
+        collections: key.collections,
+    })
+}
+
+fn authorize_jwt(state: &AppState, jwt: &str) -> Result<AuthContext, warp::Rejection> {
+    let decoded = decode::<Claims>(
+        jwt,
+        &DecodingKey::from_secret(state.config.jwt_secret.as_bytes()),
+        &Validation::new(Algorithm::HS512),
+    )
+    .map_err(|_| reject::custom(ServerError::Unauthorized("jwt token not valid".to_string())))?;
+
+    Ok(AuthContext {
+        subject: decoded.claims.sub,
+        scopes: decoded
+            .claims
+            .scopes
+            .unwrap_or_else(|| vec![Scope::Read, Scope::Mint]),
+        wallet_address: decoded.claims.wallet,
+        collections: None,
+    })
 }
 
-fn jwt_from_header(headers: &HeaderMap<HeaderValue>) -> Result<String, ServerError> {
+fn token_from_header(headers: &HeaderMap<HeaderValue>) -> Result<String, ServerError> {
     let header = match headers.get(AUTHORIZATION) {
         Some(v) => v,
-        None => return Err(ServerError::from(anyhow!("no auth header"))), //Err(Error::NoAuthHeaderError),
+        None => return Err(ServerError::Unauthorized("no auth header".to_string())),
     };
     let auth_header = match std::str::from_utf8(header.as_bytes()) {
         Ok(v) => v,
-        Err(_) => return Err(ServerError::from(anyhow!("no auth header"))), // Err(Error::NoAuthHeaderError),
+        Err(_) => return Err(ServerError::Unauthorized("no auth header".to_string())),
     };
     if !auth_header.starts_with(BEARER) {
-        return Err(ServerError::from(anyhow!("invalid auth header")));
+        return Err(ServerError::Unauthorized("invalid auth header".to_string()));
     }
     Ok(auth_header.trim_start_matches(BEARER).to_owned())
 }
@@ -1,123 +1,474 @@
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result; // Simplified error handling with anyhow
 
-use ethers::contract::FunctionCall;
-use ethers::core::k256::ecdsa::SigningKey;
-use ethers::middleware::gas_escalator::{Frequency, GeometricGasPrice};
+use ethers::contract::{ContractError, FunctionCall};
+use ethers::middleware::gas_escalator::{GasEscalator, GeometricGasPrice};
 // use ethers::middleware::gas_oracle::{
 //     EthGasStation, Etherchain, Etherscan, GasCategory, GasNow, GasOracleMiddleware,
 // };
-use ethers::middleware::{GasEscalatorMiddleware, MiddlewareBuilder, NonceManagerMiddleware};
+use ethers::middleware::{MiddlewareBuilder, NonceManagerMiddleware};
 
-use ethers::signers::{LocalWallet, Signer};
+use ethers::signers::Signer;
 
-use ethers::types::H256;
-use ethers::{
-    middleware::SignerMiddleware,
-    providers::{Http, Provider},
-    signers::Wallet,
+use ethers::middleware::SignerMiddleware;
+use ethers::providers::{Middleware, Provider, RpcError};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{
+    BlockNumber, Bytes, Eip1559TransactionRequest, Transaction, TransactionReceipt,
+    TransactionRequest, H256, U256,
 };
+use ethers::utils::keccak256;
+use ethers::utils::rlp::{Decodable, Rlp};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
-use warp::Filter;
-
-use crate::constants::Constants;
-use crate::error::ServerError;
-
-// type EthersClient = NonceManagerMiddleware<
-//     SignerMiddleware<
-//         GasOracleMiddleware<GasEscalatorMiddleware<Provider<Http>>, GasNow>,
-//         Wallet<SigningKey>,
-//     >,
-// >;
-
-// type EthersContract = NonceManagerMiddleware<
-//     SignerMiddleware<
-//         GasOracleMiddleware<GasEscalatorMiddleware<Provider<Http>>, GasNow>,
-//         Wallet<SigningKey>,
-//     >,
-// >;
-
-type EthersClient = NonceManagerMiddleware<
-    SignerMiddleware<
-        //GasOracleMiddleware<
-        GasEscalatorMiddleware<Provider<Http>>,
-        //GasNow>,
-        Wallet<SigningKey>,
-    >,
->;
 
-type EthersContract = NonceManagerMiddleware<
-    SignerMiddleware<
-        // GasOracleMiddleware <
-        GasEscalatorMiddleware<Provider<Http>>,
-        //>, GasNow>,
-        Wallet<SigningKey>,
-    >,
->;
+use super::rpc::{RpcClient, RpcProvider};
+use super::signer::AppSigner;
+use crate::constants::{ChainConfig, FeeCapAction, GasPolicy};
+use crate::error::{ServerError, UpstreamService};
 
-pub type EthersProvider = Arc<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>;
+pub type EthersClient = NonceManagerMiddleware<SigningClient>;
 
-// type EthersContractInstance = ContractInstance<Arc<EthersContract>, EthersContract>;
+type SigningClient = SignerMiddleware<
+    //GasOracleMiddleware<
+    RpcProvider,
+    //GasNow>,
+    AppSigner,
+>;
 
-type EthersContractCall = FunctionCall<Arc<EthersContract>, EthersContract, H256>;
+// type EthersContractInstance = ContractInstance<Arc<EthersClient>, EthersClient>;
 
-pub async fn get_ethers_client() -> Result<Arc<EthersClient>> {
-    let config = Constants::new();
+type EthersContractCall = FunctionCall<Arc<EthersClient>, EthersClient, H256>;
 
-    let escalator = GeometricGasPrice::new(1.125, 60u64, None::<u64>);
-    let signer = config.private_key.parse::<LocalWallet>().unwrap();
-    let provider = Provider::<Http>::try_from(config.chain_url.as_str()).unwrap();
+/// Builds the client of one signer once at startup. The nonce manager lives as long as
+/// the returned client, so every transaction of that wallet shares it. Stuck
+/// transactions are bumped by the transaction monitor, see [`replace_transaction`].
+/// `provider` is the NFT chain's, so the signers share its endpoint health.
+pub async fn get_ethers_client(
+    provider: &RpcProvider,
+    signer: AppSigner,
+) -> Result<Arc<EthersClient>> {
+    let provider = provider.clone();
 
     let signer_address = signer.address();
 
     // let category = GasCategory::Standard;
     // let oracle = GasNow::new().category(category);
 
-    let provider =
-        provider.wrap_into(|p| GasEscalatorMiddleware::new(p, escalator, Frequency::PerBlock));
     // .gas_oracle(oracle);
-    let provider = SignerMiddleware::new_with_provider_chain(provider, signer)
-        .await
-        .unwrap();
+    let provider = SignerMiddleware::new(provider, signer);
     let provider = provider.nonce_manager(signer_address);
     Ok(Arc::new(provider))
 }
 
-pub fn with_ethers_client(
-    client: Arc<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>,
-) -> impl Filter<
-    Extract = (Arc<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>,),
-    Error = std::convert::Infallible,
-> + Clone {
-    warp::any().map(move || client.clone())
+/// Signer-less client of `chain` for reads, available on read-only servers too. It fails
+/// over between the chain's RPC endpoints under its RPC policy.
+pub fn get_provider(chain: &ChainConfig) -> Result<RpcProvider> {
+    let client = RpcClient::new(&chain.name, &chain.rpc_urls, &chain.rpc_policy)?;
+    Ok(Provider::new(client))
+}
+
+/// A transaction handed to the node, with the nonce and fees the middleware picked.
+pub struct SentTransaction {
+    pub hash: H256,
+    /// As signed, or as reported back by the node; `None` if it could not be read.
+    pub transaction: Option<Transaction>,
+}
+
+/// Why [`send_transaction`] has no sent transaction to return.
+pub enum SendError {
+    /// The transaction never reached the node, or the node refused it, so its nonce is
+    /// free for the next one.
+    NotSent(ServerError),
+    /// The request failed on its way to or back from the node, which may have the
+    /// transaction. It keeps its nonce until the chain shows what became of it.
+    Unclear(Box<SentTransaction>, ServerError),
+}
+
+/// Fees picked under the gas policy, in wei.
+#[derive(Debug, Clone, Copy)]
+pub struct Fees {
+    /// The node's gas price on chains without EIP-1559.
+    pub base_fee_per_gas: U256,
+    /// The gas price of legacy transactions.
+    pub max_fee_per_gas: U256,
+    /// Zero for legacy transactions.
+    pub max_priority_fee_per_gas: U256,
+    /// Set on chains without a base fee, which take legacy transactions with a
+    /// `gasPrice` of `max_fee_per_gas`.
+    pub legacy: bool,
+}
+
+impl Fees {
+    /// What a transaction pays per gas if it is mined in the next block.
+    pub fn network_fee(&self) -> U256 {
+        self.base_fee_per_gas + self.max_priority_fee_per_gas
+    }
+
+    /// Fails when the network fee is above the cap of `policy`.
+    pub fn check_cap(&self, policy: &GasPolicy) -> Result<(), ServerError> {
+        match policy.max_fee_per_gas {
+            Some(cap) if self.network_fee() > cap => Err(ServerError::FeeAboveCap {
+                fee_per_gas: self.network_fee(),
+                cap,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Current fees with the configured tip, and `maxFeePerGas` clamped to the cap. Chains
+/// without a base fee get the node's gas price instead, clamped to the same cap.
+pub async fn suggest_fees(provider: &RpcProvider, policy: &GasPolicy) -> Result<Fees, ServerError> {
+    let base_fee_per_gas = provider
+        .get_block(BlockNumber::Latest)
+        .await?
+        .ok_or_else(|| ServerError::upstream(UpstreamService::Rpc, "latest block not found"))?
+        .base_fee_per_gas;
+    let Some(base_fee_per_gas) = base_fee_per_gas else {
+        let gas_price = provider.get_gas_price().await?;
+        return Ok(Fees {
+            base_fee_per_gas: gas_price,
+            max_fee_per_gas: policy
+                .max_fee_per_gas
+                .map_or(gas_price, |cap| gas_price.min(cap)),
+            max_priority_fee_per_gas: U256::zero(),
+            legacy: true,
+        });
+    };
+    let (estimated_max_fee, estimated_priority_fee) = provider.estimate_eip1559_fees(None).await?;
+
+    let max_priority_fee_per_gas = policy
+        .max_priority_fee_per_gas
+        .unwrap_or(estimated_priority_fee);
+    // The estimate is a base fee buffer plus the estimated tip; swap in the tip we use.
+    let mut max_fee_per_gas =
+        estimated_max_fee.saturating_sub(estimated_priority_fee) + max_priority_fee_per_gas;
+    if let Some(cap) = policy.max_fee_per_gas {
+        max_fee_per_gas = max_fee_per_gas.min(cap);
+    }
+
+    Ok(Fees {
+        base_fee_per_gas,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        legacy: false,
+    })
+}
+
+/// Rejects new transactions while the network fee is above the cap, when the policy
+/// refuses them instead of queueing.
+pub async fn refuse_if_fee_above_cap(
+    provider: &RpcProvider,
+    policy: &GasPolicy,
+) -> Result<(), ServerError> {
+    if policy.on_fee_above_cap != FeeCapAction::Refuse || policy.max_fee_per_gas.is_none() {
+        return Ok(());
+    }
+    suggest_fees(provider, policy).await?.check_cap(policy)
 }
 
+/// Sends the call with `fees` without waiting for it to be mined. The transaction is
+/// signed here and handed to the node as raw bytes, so a failed request is never resent
+/// under another nonce, and the nonce manager only gives up the nonce once the node may
+/// have the transaction. Sends of one wallet must not overlap; the mint worker makes
+/// them one at a time.
 pub async fn send_transaction(
+    ethers_client: &EthersClient,
     contract_call: EthersContractCall,
-    wait_confirmation: bool,
-) -> Result<SendTransactionResult, ServerError> {
-    // Try directly calling the method and setting the nonce without intermediate binding
-    match contract_call.send().await {
-        Ok(pending_tx) => {
-            let tx_result = pending_tx;
-            if wait_confirmation {
-                let tx_result = tx_result.await?.unwrap();
-                return Ok(SendTransactionResult::Receipt(tx_result.into()));
+    fees: &Fees,
+) -> Result<SentTransaction, SendError> {
+    let mut tx = contract_call.tx;
+    if fees.legacy {
+        if let TypedTransaction::Eip1559(request) = tx {
+            tx = TypedTransaction::Legacy(request.into());
+        }
+    }
+    match tx.as_eip1559_mut() {
+        Some(tx) => {
+            tx.max_fee_per_gas = Some(fees.max_fee_per_gas);
+            tx.max_priority_fee_per_gas = Some(fees.max_priority_fee_per_gas);
+        }
+        None => {
+            tx.set_gas_price(fees.max_fee_per_gas);
+        }
+    }
+
+    let nonce = ethers_client
+        .initialize_nonce(None)
+        .await
+        .map_err(|e| SendError::NotSent(ServerError::upstream(UpstreamService::Rpc, e)))?;
+    tx.set_nonce(nonce);
+    // Fills in the chain id and the gas; a call that would revert fails here.
+    let signing_client = ethers_client.inner();
+    signing_client
+        .fill_transaction(&mut tx, None)
+        .await
+        .map_err(|e| {
+            SendError::NotSent(ContractError::<SigningClient>::from_middleware_error(e).into())
+        })?;
+    let signature = signing_client
+        .signer()
+        .sign_transaction(&tx)
+        .await
+        .map_err(|e| {
+            SendError::NotSent(ServerError::Internal(format!(
+                "Failed to sign transaction: {}",
+                e
+            )))
+        })?;
+    let raw = tx.rlp_signed(&signature);
+    let sent = signed_transaction(&raw);
+
+    match ethers_client.provider().send_raw_transaction(raw).await {
+        Ok(_) => {
+            take_nonce(ethers_client, nonce);
+            Ok(sent)
+        }
+        // The node answered, so it has not taken the transaction.
+        Err(e) if e.as_error_response().is_some() => Err(SendError::NotSent(e.into())),
+        Err(e) => {
+            take_nonce(ethers_client, nonce);
+            Err(SendError::Unclear(Box::new(sent), e.into()))
+        }
+    }
+}
+
+/// Moves the nonce manager past `nonce`, which it handed out last.
+fn take_nonce(ethers_client: &EthersClient, nonce: U256) {
+    while ethers_client.next() < nonce {}
+}
+
+/// The hash and fields of a signed raw transaction, as the node will report them.
+fn signed_transaction(raw: &Bytes) -> SentTransaction {
+    let transaction = Transaction::decode(&Rlp::new(raw))
+        .map(|mut transaction| {
+            // Decoding leaves the sender to be recovered from the signature.
+            let _ = transaction.recover_from_mut();
+            transaction
+        })
+        .ok();
+    SentTransaction {
+        hash: H256(keccak256(raw)),
+        transaction,
+    }
+}
+
+/// Nonce and fees are filled in by the middleware stack, so read them back.
+async fn fetch_sent_transaction(ethers_client: &EthersClient, hash: H256) -> SentTransaction {
+    let transaction = match ethers_client.provider().get_transaction(hash).await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            eprintln!("Failed to fetch sent transaction {:?}: {}", hash, e);
+            None
+        }
+    };
+    SentTransaction { hash, transaction }
+}
+
+/// How a stuck transaction is replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// Resend the same call with higher fees.
+    SpeedUp,
+    /// Send a zero-value transfer to the signer itself with higher fees.
+    Cancel,
+}
+
+/// The escalated fee, at least one step above the original and never below the
+/// current one. Nodes only accept a replacement that pays at least 10% more, so the
+/// coefficient should stay above 1.1.
+fn bump_fee(policy: &GasPolicy, original: U256, current: U256, pending_secs: u64) -> U256 {
+    let escalator = GeometricGasPrice::new(
+        policy.escalation_coefficient,
+        policy.escalation_interval_secs,
+        None::<u64>,
+    );
+    let escalated = escalator
+        .get_gas_price(original, pending_secs)
+        .max(escalator.get_gas_price(original, policy.escalation_interval_secs));
+    escalated.max(current)
+}
+
+fn check_replacement_cap(policy: &GasPolicy, fee_per_gas: U256) -> Result<(), ServerError> {
+    match policy.max_fee_per_gas {
+        Some(cap) if fee_per_gas > cap => Err(ServerError::FeeAboveCap { fee_per_gas, cap }),
+        _ => Ok(()),
+    }
+}
+
+/// Sends a transaction with the nonce of `original` and higher fees, so at most one of
+/// them is mined. It bypasses the nonce manager, which would move a rejected
+/// replacement to a fresh nonce.
+pub async fn replace_transaction(
+    ethers_client: &EthersClient,
+    policy: &GasPolicy,
+    original: &Transaction,
+    replacement: Replacement,
+    pending_secs: u64,
+) -> Result<SentTransaction, ServerError> {
+    let (to, value, data, gas) = match replacement {
+        Replacement::SpeedUp => (
+            original.to,
+            original.value,
+            original.input.clone(),
+            original.gas,
+        ),
+        Replacement::Cancel => (
+            Some(original.from),
+            U256::zero(),
+            Default::default(),
+            policy.cancel_gas_limit,
+        ),
+    };
+
+    let provider = ethers_client.provider();
+    let tx: TypedTransaction = match (original.max_fee_per_gas, original.max_priority_fee_per_gas) {
+        (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) => {
+            let current = suggest_fees(provider, policy).await?;
+            let max_priority_fee_per_gas = bump_fee(
+                policy,
+                max_priority_fee_per_gas,
+                current.max_priority_fee_per_gas,
+                pending_secs,
+            );
+            let max_fee_per_gas = bump_fee(
+                policy,
+                max_fee_per_gas,
+                current.max_fee_per_gas,
+                pending_secs,
+            )
+            .max(max_priority_fee_per_gas);
+            check_replacement_cap(policy, max_fee_per_gas)?;
+            let mut tx = Eip1559TransactionRequest::new()
+                .from(original.from)
+                .nonce(original.nonce)
+                .value(value)
+                .data(data)
+                .gas(gas)
+                .max_fee_per_gas(max_fee_per_gas)
+                .max_priority_fee_per_gas(max_priority_fee_per_gas);
+            if let Some(to) = to {
+                tx = tx.to(to);
             }
+            tx.into()
+        }
+        _ => {
+            let current_gas_price = provider.get_gas_price().await?;
+            let gas_price = bump_fee(
+                policy,
+                original.gas_price.unwrap_or_default(),
+                current_gas_price,
+                pending_secs,
+            );
+            check_replacement_cap(policy, gas_price)?;
+            let mut tx = TransactionRequest::new()
+                .from(original.from)
+                .nonce(original.nonce)
+                .value(value)
+                .data(data)
+                .gas(gas)
+                .gas_price(gas_price);
+            if let Some(to) = to {
+                tx = tx.to(to);
+            }
+            tx.into()
+        }
+    };
+
+    let pending_tx = ethers_client
+        .inner()
+        .send_transaction(tx, None)
+        .await
+        .map_err(|e| ServerError::upstream(UpstreamService::Rpc, e))?;
+    Ok(fetch_sent_transaction(ethers_client, pending_tx.tx_hash()).await)
+}
+
+/// Number of blocks on top of and including the one that mined the transaction.
+pub async fn confirmations(
+    provider: &RpcProvider,
+    receipt: &TransactionReceipt,
+) -> Result<u64, ServerError> {
+    let Some(block_number) = receipt.block_number else {
+        return Ok(0);
+    };
+    let head = provider.get_block_number().await?;
+    Ok(head.saturating_sub(block_number).as_u64() + 1)
+}
 
-            // pending_tx.
-            Ok(SendTransactionResult::Hash(tx_result.tx_hash().into()))
+/// How long the node may keep reporting a sent transaction as unknown before it is
+/// taken as dropped. Nodes that just got a transaction do not always know it yet.
+const DROPPED_AFTER: Duration = Duration::from_secs(180);
+
+/// What became of a transaction that was waited for.
+#[derive(Debug)]
+pub enum TxOutcome {
+    /// Mined under the requested number of confirmations.
+    Mined(Box<TransactionReceipt>),
+    /// Neither mined nor known to the node for [`DROPPED_AFTER`].
+    Dropped,
+}
+
+/// A single look at a transaction.
+enum TxPoll {
+    Mined(Box<TransactionReceipt>),
+    /// In the mempool, or mined with too few confirmations so far.
+    Pending,
+    /// Unknown to the node.
+    Missing,
+}
+
+async fn poll_transaction(
+    provider: &RpcProvider,
+    tx_hash: H256,
+    wanted: usize,
+) -> Result<TxPoll, ServerError> {
+    if let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? {
+        if confirmations(provider, &receipt).await? >= wanted as u64 {
+            return Ok(TxPoll::Mined(Box::new(receipt)));
         }
-        Err(e) => Err(ServerError::from(e)),
+        return Ok(TxPoll::Pending);
+    }
+    match provider.get_transaction(tx_hash).await? {
+        Some(_) => Ok(TxPoll::Pending),
+        None => Ok(TxPoll::Missing),
     }
 }
 
-#[derive(Serialize, ToSchema)]
-pub enum SendTransactionResult {
-    Receipt(TransactionReceiptSchema),
-    Hash(TxHashSchema),
+/// Waits until `tx_hash` is mined under `confirmations` blocks. Works for
+/// transactions sent by a previous process too. Failed lookups are retried rather
+/// than taken as an outcome, so an RPC outage only delays the answer.
+pub async fn wait_for_receipt(
+    provider: &RpcProvider,
+    tx_hash: H256,
+    confirmations: usize,
+) -> TxOutcome {
+    wait_for_receipt_until(provider, tx_hash, confirmations, DROPPED_AFTER).await
+}
+
+async fn wait_for_receipt_until(
+    provider: &RpcProvider,
+    tx_hash: H256,
+    confirmations: usize,
+    dropped_after: Duration,
+) -> TxOutcome {
+    let mut missing_since: Option<Instant> = None;
+    loop {
+        match poll_transaction(provider, tx_hash, confirmations).await {
+            Ok(TxPoll::Mined(receipt)) => return TxOutcome::Mined(receipt),
+            Ok(TxPoll::Pending) => missing_since = None,
+            Ok(TxPoll::Missing) => {
+                let since = *missing_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= dropped_after {
+                    return TxOutcome::Dropped;
+                }
+            }
+            Err(e) => eprintln!("Failed to look up transaction {:?}: {}", tx_hash, e),
+        }
+        tokio::time::sleep(provider.get_interval()).await;
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
@@ -162,22 +513,27 @@ pub struct TransactionReceiptSchema {
 impl From<ethers::types::TransactionReceipt> for TransactionReceiptSchema {
     fn from(receipt: ethers::types::TransactionReceipt) -> Self {
         TransactionReceiptSchema {
-            transaction_hash: receipt.transaction_hash.to_string(),
+            // `{:?}` because `Display` abbreviates hashes and addresses.
+            transaction_hash: format!("{:?}", receipt.transaction_hash),
             transaction_index: receipt.transaction_index.as_u64(),
-            block_hash: receipt.block_hash.map(|hash| hash.to_string()),
+            block_hash: receipt.block_hash.map(|hash| format!("{:?}", hash)),
             block_number: receipt.block_number.map(|num| num.as_u64()),
-            from: receipt.from.to_string(),
-            to: receipt.to.map(|to| to.to_string()),
+            from: format!("{:?}", receipt.from),
+            to: receipt.to.map(|to| format!("{:?}", to)),
             cumulative_gas_used: receipt.cumulative_gas_used.to_string(),
             gas_used: receipt.gas_used.map(|gas| gas.to_string()),
-            contract_address: receipt.contract_address.map(|address| address.to_string()),
+            contract_address: receipt
+                .contract_address
+                .map(|address| format!("{:?}", address)),
             status: receipt.status.map(|status| status.as_u64()),
             effective_gas_price: receipt.effective_gas_price.map(|price| price.to_string()),
         }
     }
 }
 
-#[derive(Serialize, ToSchema)]
+/// A transaction hash, serialized as 0x-prefixed hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[schema(value_type = String, example = "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060")]
 pub struct TxHashSchema(pub [u8; 32]);
 
 impl From<H256> for TxHashSchema {
@@ -185,3 +541,287 @@ impl From<H256> for TxHashSchema {
         TxHashSchema(hash.0)
     }
 }
+
+impl Serialize for TxHashSchema {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", H256(self.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::RpcPolicy;
+    use ethers::abi::{Abi, Address};
+    use ethers::contract::Contract;
+    use ethers::signers::LocalWallet;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use warp::Filter;
+
+    #[derive(Clone, Copy)]
+    enum Node {
+        /// Fails receipt lookups like a gateway for the first calls, then finds the
+        /// transaction mined in block 10.
+        DownThenMined(usize),
+        /// Does not know the transaction.
+        Unknown,
+        /// Rejects raw transactions with a JSON-RPC error.
+        Refuses,
+        /// Fails raw transactions like a gateway, after they may have been taken.
+        SendFails,
+        /// A chain without EIP-1559, whose gas price is 50 wei.
+        Legacy,
+    }
+
+    /// Block 10, with a base fee of 10 wei unless the chain is `Legacy`.
+    fn block(node: Node) -> Value {
+        let mut block = json!({
+            "hash": format!("{:?}", H256::repeat_byte(2)),
+            "parentHash": format!("{:?}", H256::zero()),
+            "sha3Uncles": format!("{:?}", H256::zero()),
+            "miner": format!("{:?}", Address::zero()),
+            "stateRoot": format!("{:?}", H256::zero()),
+            "transactionsRoot": format!("{:?}", H256::zero()),
+            "receiptsRoot": format!("{:?}", H256::zero()),
+            "number": "0xa",
+            "gasUsed": "0x0",
+            "gasLimit": "0x1c9c380",
+            "extraData": "0x",
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "timestamp": "0x0",
+            "difficulty": "0x0",
+            "uncles": [],
+            "transactions": [],
+        });
+        if !matches!(node, Node::Legacy) {
+            block["baseFeePerGas"] = json!("0xa");
+        }
+        block
+    }
+
+    fn receipt() -> Value {
+        json!({
+            "transactionHash": format!("{:?}", H256::repeat_byte(1)),
+            "transactionIndex": "0x0",
+            "blockHash": format!("{:?}", H256::repeat_byte(2)),
+            "blockNumber": "0xa",
+            "from": format!("{:?}", ethers::types::Address::repeat_byte(3)),
+            "to": format!("{:?}", ethers::types::Address::repeat_byte(4)),
+            "cumulativeGasUsed": "0x5208",
+            "gasUsed": "0x5208",
+            "contractAddress": null,
+            "logs": [],
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "status": "0x1",
+            "type": "0x2",
+        })
+    }
+
+    /// Mock JSON-RPC endpoint whose head is block 10 and where the wallet has sent 5
+    /// transactions. Returns its URL and the number of receipt lookups and raw
+    /// transactions it got.
+    async fn serve(node: Node) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let route = warp::post()
+            .and(warp::body::json())
+            .map(move |request: Value| {
+                let result = match request["method"].as_str() {
+                    Some("eth_getTransactionReceipt") => {
+                        let call = counter.fetch_add(1, Ordering::SeqCst);
+                        match node {
+                            Node::DownThenMined(failures) if call < failures => {
+                                return "<html>502 Bad Gateway</html>".to_string();
+                            }
+                            Node::DownThenMined(_) => receipt(),
+                            _ => Value::Null,
+                        }
+                    }
+                    Some("eth_sendRawTransaction") => {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        match node {
+                            Node::Refuses => {
+                                return json!({
+                                    "jsonrpc": "2.0",
+                                    "id": request["id"],
+                                    "error": { "code": -32000, "message": "insufficient funds" },
+                                })
+                                .to_string();
+                            }
+                            Node::SendFails => return "<html>502 Bad Gateway</html>".to_string(),
+                            _ => json!(format!("{:?}", H256::repeat_byte(1))),
+                        }
+                    }
+                    Some("eth_blockNumber") => json!("0xa"),
+                    Some("eth_getBlockByNumber") => block(node),
+                    Some("eth_gasPrice") => json!("0x32"),
+                    Some("eth_getTransactionCount") => json!("0x5"),
+                    Some("eth_estimateGas") => json!("0x5208"),
+                    _ => Value::Null,
+                };
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string()
+            });
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}", address), calls)
+    }
+
+    fn provider(url: String) -> RpcProvider {
+        let policy = RpcPolicy {
+            timeout_secs: 1,
+            retries: 0,
+            quorum: 1,
+        };
+        let client = RpcClient::new("test", &[url], &policy).unwrap();
+        Provider::new(client).interval(Duration::from_millis(10))
+    }
+
+    async fn client(url: String) -> Arc<EthersClient> {
+        let wallet: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+                .parse()
+                .unwrap();
+        get_ethers_client(&provider(url), AppSigner::Local(wallet.with_chain_id(1u64)))
+            .await
+            .unwrap()
+    }
+
+    fn mint_call(client: &Arc<EthersClient>) -> EthersContractCall {
+        let abi: Abi = serde_json::from_slice(include_bytes!("../abi/SnapitNFT.json")).unwrap();
+        Contract::new(Address::repeat_byte(4), abi, client.clone())
+            .method("mint", (Address::repeat_byte(3), U256::from(7)))
+            .unwrap()
+    }
+
+    fn fees() -> Fees {
+        Fees {
+            base_fee_per_gas: U256::from(10),
+            max_fee_per_gas: U256::from(30),
+            max_priority_fee_per_gas: U256::from(2),
+            legacy: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn send_transaction_takes_the_nonce_once_sent() {
+        let (url, _) = serve(Node::Unknown).await;
+        let client = client(url).await;
+
+        let sent = send_transaction(&client, mint_call(&client), &fees())
+            .await
+            .ok()
+            .unwrap();
+        let transaction = sent.transaction.unwrap();
+
+        assert_eq!(transaction.hash, sent.hash);
+        assert_eq!(transaction.nonce, U256::from(5));
+        assert_eq!(transaction.from, client.inner().address());
+        assert_eq!(transaction.max_fee_per_gas, Some(U256::from(30)));
+        assert_eq!(client.next(), U256::from(6));
+    }
+
+    #[tokio::test]
+    async fn send_transaction_keeps_the_nonce_when_the_node_refuses() {
+        let (url, sends) = serve(Node::Refuses).await;
+        let client = client(url).await;
+
+        let result = send_transaction(&client, mint_call(&client), &fees()).await;
+
+        assert!(matches!(result, Err(SendError::NotSent(_))));
+        assert_eq!(sends.load(Ordering::SeqCst), 1);
+        assert_eq!(client.next(), U256::from(5));
+    }
+
+    #[tokio::test]
+    async fn send_transaction_reports_a_failed_request_as_unclear() {
+        let (url, sends) = serve(Node::SendFails).await;
+        let client = client(url).await;
+
+        let result = send_transaction(&client, mint_call(&client), &fees()).await;
+
+        let Err(SendError::Unclear(sent, _)) = result else {
+            panic!("expected an unclear send");
+        };
+        assert_eq!(sent.transaction.unwrap().nonce, U256::from(5));
+        // Not resent under another nonce, which could mint twice.
+        assert_eq!(sends.load(Ordering::SeqCst), 1);
+        assert_eq!(client.next(), U256::from(6));
+    }
+
+    fn policy(max_fee_per_gas: Option<u64>) -> GasPolicy {
+        GasPolicy {
+            max_fee_per_gas: max_fee_per_gas.map(U256::from),
+            max_priority_fee_per_gas: None,
+            on_fee_above_cap: FeeCapAction::Queue,
+            escalation_coefficient: 1.125,
+            escalation_interval_secs: 60,
+            stuck_after_minutes: 10,
+            mint_gas_limit: None,
+            cancel_gas_limit: U256::from(21_000),
+        }
+    }
+
+    #[tokio::test]
+    async fn suggest_fees_falls_back_to_the_gas_price_without_a_base_fee() {
+        let (url, _) = serve(Node::Legacy).await;
+        let policy = policy(Some(40));
+
+        let fees = suggest_fees(&provider(url), &policy).await.unwrap();
+
+        assert!(fees.legacy);
+        assert_eq!(fees.network_fee(), U256::from(50));
+        assert_eq!(fees.max_fee_per_gas, U256::from(40));
+        assert!(matches!(
+            fees.check_cap(&policy),
+            Err(ServerError::FeeAboveCap { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_transaction_sends_legacy_fees_as_a_gas_price() {
+        let (url, _) = serve(Node::Legacy).await;
+        let client = client(url).await;
+        let fees = Fees {
+            base_fee_per_gas: U256::from(50),
+            max_fee_per_gas: U256::from(50),
+            max_priority_fee_per_gas: U256::zero(),
+            legacy: true,
+        };
+
+        let sent = send_transaction(&client, mint_call(&client), &fees)
+            .await
+            .ok()
+            .unwrap();
+        let transaction = sent.transaction.unwrap();
+
+        assert_eq!(transaction.transaction_type, None);
+        assert_eq!(transaction.gas_price, Some(U256::from(50)));
+        assert_eq!(transaction.max_fee_per_gas, None);
+    }
+
+    #[tokio::test]
+    async fn wait_for_receipt_retries_failed_lookups() {
+        let (url, lookups) = serve(Node::DownThenMined(3)).await;
+        let outcome = wait_for_receipt(&provider(url), H256::repeat_byte(1), 1).await;
+
+        assert!(matches!(outcome, TxOutcome::Mined(_)));
+        assert_eq!(lookups.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn wait_for_receipt_drops_transaction_missing_past_deadline() {
+        let (url, lookups) = serve(Node::Unknown).await;
+        let outcome = wait_for_receipt_until(
+            &provider(url),
+            H256::repeat_byte(1),
+            1,
+            Duration::from_millis(50),
+        )
+        .await;
+
+        assert!(matches!(outcome, TxOutcome::Dropped));
+        // The first miss only starts the deadline.
+        assert!(lookups.load(Ordering::SeqCst) > 1);
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use ethers::abi::RawLog;
+use ethers::contract::{abigen, EthEvent, EthLogDecode};
+use ethers::types::{Address, Filter, Log, H256};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::auth::AuthContext;
+use crate::handlers::get_auction::{
+    AuctionClaimedFilter, AuctionContractEvents, AuctionStartedFilter, BidFilter,
+};
+use crate::state::AppState;
+
+abigen!(SnapitNftContract, "src/abi/SnapitNFT.json");
+
+/// An event of a watched contract, as handed to subscribers.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChainEvent {
+    pub chain: String,
+    pub block_number: u64,
+    /// Tells the block apart from the one that replaced it after a reorg.
+    pub block_hash: String,
+    pub transaction_hash: String,
+    pub log_index: u64,
+    /// The log was dropped by a reorg after it had been reported.
+    pub removed: bool,
+    pub event: ChainEventKind,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainEventKind {
+    /// Mints come from the zero address.
+    Transfer {
+        collection: String,
+        from: String,
+        to: String,
+        token_id: String,
+    },
+    AuctionStarted {
+        token_id: String,
+        start_time: String,
+        end_time: String,
+    },
+    Bid {
+        token_id: String,
+        bidder: String,
+        price_wei: String,
+    },
+    AuctionClaimed {
+        token_id: String,
+        winner: String,
+        price_wei: String,
+    },
+}
+
+impl ChainEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChainEventKind::Transfer { .. } => "transfer",
+            ChainEventKind::AuctionStarted { .. } => "auction_started",
+            ChainEventKind::Bid { .. } => "bid",
+            ChainEventKind::AuctionClaimed { .. } => "auction_claimed",
+        }
+    }
+}
+
+impl ChainEvent {
+    /// Transfers are only shown to callers with access to their collection.
+    pub fn visible_to(&self, auth: &AuthContext) -> bool {
+        match &self.event {
+            ChainEventKind::Transfer { collection, .. } => {
+                auth.ensure_collection(collection).is_ok()
+            }
+            _ => true,
+        }
+    }
+}
+
+/// The contracts of one chain whose events are watched: the NFT contract of every
+/// collection on it, and the auction if it lives there.
+pub struct WatchedContracts {
+    /// Collection slugs by NFT address.
+    collections: HashMap<Address, String>,
+    auction: Option<Address>,
+}
+
+impl WatchedContracts {
+    pub fn new(collections: HashMap<Address, String>, auction: Option<Address>) -> Self {
+        WatchedContracts {
+            collections,
+            auction,
+        }
+    }
+
+    pub fn for_chain(state: &AppState, chain: &str) -> Self {
+        let collections = state
+            .collections
+            .all()
+            .filter(|collection| collection.config.chain == chain)
+            .filter_map(|collection| {
+                Some((
+                    collection.config.nft_address?,
+                    collection.slug().to_string(),
+                ))
+            })
+            .collect();
+        let auction = (state.config.auction_chain == chain).then_some(state.config.auction_address);
+        WatchedContracts::new(collections, auction)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.collections.is_empty() && self.auction.is_none()
+    }
+
+    /// Logs of the watched events of the watched contracts.
+    pub fn filter(&self) -> Filter {
+        let addresses: Vec<Address> = self
+            .collections
+            .keys()
+            .copied()
+            .chain(self.auction)
+            .collect();
+        let topics: Vec<H256> = vec![
+            TransferFilter::signature(),
+            AuctionStartedFilter::signature(),
+            BidFilter::signature(),
+            AuctionClaimedFilter::signature(),
+        ];
+        Filter::new().address(addresses).topic0(topics)
+    }
+
+    /// The event of `log`, or `None` for logs of other contracts or events, and for
+    /// pending logs.
+    pub fn decode(&self, chain: &str, log: &Log) -> Option<ChainEvent> {
+        let raw = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+        let event = if let Some(collection) = self.collections.get(&log.address) {
+            match SnapitNftContractEvents::decode_log(&raw).ok()? {
+                SnapitNftContractEvents::TransferFilter(transfer) => ChainEventKind::Transfer {
+                    collection: collection.clone(),
+                    from: format!("{:?}", transfer.from),
+                    to: format!("{:?}", transfer.to),
+                    token_id: transfer.token_id.to_string(),
+                },
+                _ => return None,
+            }
+        } else if self.auction == Some(log.address) {
+            match AuctionContractEvents::decode_log(&raw).ok()? {
+                AuctionContractEvents::AuctionStartedFilter(started) => {
+                    ChainEventKind::AuctionStarted {
+                        token_id: started.token_id.to_string(),
+                        start_time: started.start_time.to_string(),
+                        end_time: started.end_time.to_string(),
+                    }
+                }
+                AuctionContractEvents::BidFilter(bid) => ChainEventKind::Bid {
+                    token_id: bid.token_id.to_string(),
+                    bidder: format!("{:?}", bid.bidder),
+                    price_wei: bid.price.to_string(),
+                },
+                AuctionContractEvents::AuctionClaimedFilter(claimed) => {
+                    ChainEventKind::AuctionClaimed {
+                        token_id: claimed.token_id.to_string(),
+                        winner: format!("{:?}", claimed.winner),
+                        price_wei: claimed.price.to_string(),
+                    }
+                }
+            }
+        } else {
+            return None;
+        };
+        Some(ChainEvent {
+            chain: chain.to_string(),
+            block_number: log.block_number?.as_u64(),
+            block_hash: format!("{:?}", log.block_hash?),
+            transaction_hash: format!("{:?}", log.transaction_hash?),
+            log_index: log.log_index?.as_u64(),
+            removed: log.removed == Some(true),
+            event,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{encode, Token};
+    use ethers::types::{U256, U64};
+
+    fn nft() -> Address {
+        Address::from_low_u64_be(0xaa)
+    }
+
+    fn auction() -> Address {
+        Address::from_low_u64_be(0xcc)
+    }
+
+    fn contracts() -> WatchedContracts {
+        WatchedContracts::new(
+            HashMap::from([(nft(), "snapit".to_string())]),
+            Some(auction()),
+        )
+    }
+
+    fn log(address: Address, topics: Vec<H256>, data: Vec<u8>) -> Log {
+        Log {
+            address,
+            topics,
+            data: data.into(),
+            block_number: Some(U64::from(7)),
+            block_hash: Some(H256::from_low_u64_be(0x77)),
+            transaction_hash: Some(H256::from_low_u64_be(0x1234)),
+            log_index: Some(U256::from(3)),
+            ..Default::default()
+        }
+    }
+
+    fn mint(address: Address) -> Log {
+        let mut token_id = [0u8; 32];
+        U256::from(42).to_big_endian(&mut token_id);
+        log(
+            address,
+            vec![
+                TransferFilter::signature(),
+                H256::zero(),
+                H256::from(Address::from_low_u64_be(0xbb)),
+                H256::from(token_id),
+            ],
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn decodes_transfers_of_watched_collections() {
+        let event = contracts().decode("polygon", &mint(nft())).unwrap();
+        assert_eq!(event.chain, "polygon");
+        assert_eq!(event.block_number, 7);
+        assert_eq!(
+            event.block_hash,
+            format!("{:?}", H256::from_low_u64_be(0x77))
+        );
+        assert_eq!(event.log_index, 3);
+        assert!(!event.removed);
+        match event.event {
+            ChainEventKind::Transfer {
+                collection,
+                from,
+                to,
+                token_id,
+            } => {
+                assert_eq!(collection, "snapit");
+                assert_eq!(from, format!("{:?}", Address::zero()));
+                assert_eq!(to, format!("{:?}", Address::from_low_u64_be(0xbb)));
+                assert_eq!(token_id, "42");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_auction_bids() {
+        let bid = log(
+            auction(),
+            vec![
+                BidFilter::signature(),
+                H256::from(Address::from_low_u64_be(0xdd)),
+            ],
+            encode(&[Token::Uint(U256::from(5)), Token::Uint(U256::exp10(18))]),
+        );
+        match contracts().decode("polygon", &bid).unwrap().event {
+            ChainEventKind::Bid {
+                token_id,
+                bidder,
+                price_wei,
+            } => {
+                assert_eq!(token_id, "5");
+                assert_eq!(bidder, format!("{:?}", Address::from_low_u64_be(0xdd)));
+                assert_eq!(price_wei, "1000000000000000000");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ignores_logs_of_other_contracts() {
+        assert!(contracts()
+            .decode("polygon", &mint(Address::from_low_u64_be(0xee)))
+            .is_none());
+    }
+
+    #[test]
+    fn ignores_pending_logs() {
+        let mut pending = mint(nft());
+        pending.block_hash = None;
+        pending.block_number = None;
+        assert!(contracts().decode("polygon", &pending).is_none());
+    }
+
+    #[test]
+    fn marks_removed_logs() {
+        let mut removed = mint(nft());
+        removed.removed = Some(true);
+        assert!(contracts().decode("polygon", &removed).unwrap().removed);
+    }
+}
@@ -1,10 +1,12 @@
-// use ethers::{types::Bytes, utils::hex};
-// use serde::Serialize;
+use ethers::{types::Bytes, utils::hex};
+use serde::Serialize;
 
-// pub fn object_to_data_bytes<T: Serialize>(value: T) -> Bytes {
-//     let metadata_json = serde_json::to_string(&value).expect("Failed to serialize");
-//     // let metadata_json = serde_json::to_string(&value).unwrap();
-//     let metadata_hex = hex::encode(metadata_json);
+pub fn object_to_data_bytes<T: Serialize>(value: T) -> Result<Bytes, serde_json::Error> {
+    let metadata_json = serde_json::to_vec(&value)?;
+    Ok(Bytes::from(metadata_json))
+}
 
-//     Bytes::from(hex::decode(metadata_hex).expect("Invalid hex"))
-// }
+pub fn hex_to_data_bytes(value: &str) -> Result<Bytes, hex::FromHexError> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    Ok(Bytes::from(hex::decode(value)?))
+}
@@ -1,38 +1,217 @@
+use crate::collections::Collection;
 use crate::constants::Constants;
-use crate::error::ServerError;
-use crate::handlers::mint_nft::MintUniqueTokenRequest;
+use crate::error::{ProblemDetails, ServerError};
 
+use ethers::abi::Detokenize;
 use ethers::abi::{Abi, Address};
+use ethers::contract::FunctionCall;
 use ethers::prelude::*;
-use serde_json::{self};
+use ethers::utils::format_ether;
+use ethers::utils::id;
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Map, Value};
 use std::str::FromStr;
 use std::sync::Arc;
+use utoipa::ToSchema;
 
-use super::chain::{get_ethers_client, send_transaction, SendTransactionResult};
-// use super::helpers::object_to_data_bytes; // Import the `mongo` module
+use super::chain::{
+    send_transaction, suggest_fees, EthersClient, Fees, SendError, SentTransaction,
+};
+use super::helpers::{hex_to_data_bytes, object_to_data_bytes};
 
 const ABI_PATH: &[u8; 13447] = include_bytes!("../abi/SnapitNFT.json");
 
+fn nft_contract<M: Middleware>(
+    collection: &Collection,
+    client: Arc<M>,
+) -> Result<Contract<M>, ServerError> {
+    let abi: Abi = serde_json::from_slice(ABI_PATH).unwrap();
+
+    // Read-only servers may list collections that are not deployed yet.
+    let contract_address = collection.config.nft_address.ok_or_else(|| {
+        ServerError::Unavailable(format!(
+            "Collection {} has no deployed NFT contract",
+            collection.config.slug
+        ))
+    })?;
+    Ok(Contract::new(contract_address, abi, client))
+}
+
+/// Payload forwarded to the receiver's `onERC721Received`: a JSON string or object.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum MintData {
+    /// Any string is taken as hex, the `0x` prefix being optional, and sent as the
+    /// bytes it encodes. Strings that are not hex are rejected when the mint is requested.
+    Hex(String),
+    /// A JSON object, sent as its UTF-8 JSON encoding.
+    Json(Map<String, Value>),
+}
+
+impl MintData {
+    pub fn to_bytes(&self) -> Result<Bytes, ServerError> {
+        match self {
+            MintData::Hex(hex) => hex_to_data_bytes(hex)
+                .map_err(|_| ServerError::Validation(format!("Invalid hex data: {}", hex))),
+            MintData::Json(object) => {
+                object_to_data_bytes(object).map_err(|e| ServerError::Internal(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Builds a call to `mint(address,uint256)`, or to `mint(address,uint256,bytes)` when
+/// there is a data payload.
+fn mint_call<D: Detokenize>(
+    contract: &Contract<EthersClient>,
+    owner_address: &str,
+    token_id: u64,
+    data: Option<&MintData>,
+) -> Result<FunctionCall<Arc<EthersClient>, EthersClient, D>, ServerError> {
+    let owner_address = Address::from_str(owner_address).map_err(|_| {
+        ServerError::Validation(format!("Invalid owner address: {}", owner_address))
+    })?;
+    let token_id = U256::from(token_id);
+
+    let contract_call = match data {
+        // `method` would always pick the first overload, so address the other by selector.
+        Some(data) => contract.method_hash::<_, D>(
+            id("mint(address,uint256,bytes)"),
+            (owner_address, token_id, data.to_bytes()?),
+        )?,
+        None => contract.method::<_, D>("mint", (owner_address, token_id))?,
+    };
+    Ok(contract_call)
+}
+
+/// Sends the mint transaction without waiting for it to be mined.
 pub async fn mint_nft(
-    req: MintUniqueTokenRequest,
+    collection: &Collection,
+    owner_address: &str,
+    token_id: u64,
+    data: Option<&MintData>,
+    fees: &Fees,
     config: Arc<Constants>,
-) -> Result<SendTransactionResult, ServerError> {
-    let abi: Abi = serde_json::from_slice(ABI_PATH).unwrap();
-    let ethers_client = get_ethers_client().await?;
+    ethers_client: Arc<EthersClient>,
+) -> Result<SentTransaction, SendError> {
+    let contract = nft_contract(collection, ethers_client.clone()).map_err(SendError::NotSent)?;
 
-    let contract_address = Address::from_str(config.nft_address.as_str()).unwrap();
-    let contract = Contract::new(contract_address, abi, ethers_client);
+    let mut contract_call =
+        mint_call::<H256>(&contract, owner_address, token_id, data).map_err(SendError::NotSent)?;
+    if let Some(gas_limit) = config.gas_policy.mint_gas_limit {
+        contract_call = contract_call.gas(gas_limit);
+    }
 
-    // let data_bytes = object_to_data_bytes(req.metadata);
-    let owner_address = Address::from_str(&req.owner_address).unwrap();
-    let token_id = U256::from(req.token_id);
+    send_transaction(&ethers_client, contract_call, fees).await
+}
 
-    let contract_call = contract.method::<_, H256>("mint", (owner_address, token_id))?;
+/// Returns the owner of `token_id`, or `None` if the token has not been minted.
+pub async fn owner_of(
+    collection: &Collection,
+    token_id: u64,
+) -> Result<Option<Address>, ServerError> {
+    let contract = nft_contract(collection, collection.chain.quorum_provider.clone())?;
 
-    let wait_confirmation = req.wait_confirmation.unwrap_or(true);
+    let call = contract.method::<_, Address>("ownerOf", U256::from(token_id))?;
+    match call.call().await {
+        Ok(owner) => Ok(Some(owner)),
+        Err(e) => match ServerError::from(e) {
+            ServerError::ContractRevert(revert)
+                if revert
+                    .decoded
+                    .as_ref()
+                    .is_some_and(|d| d.name == "ERC721NonexistentToken") =>
+            {
+                Ok(None)
+            }
+            e => Err(e),
+        },
+    }
+}
+
+/// Outcome of running a mint against the current chain state without sending it.
+#[derive(Serialize, ToSchema)]
+pub struct MintSimulation {
+    pub will_succeed: bool,
+    /// Gas units, or the configured mint gas limit. Unset when the call reverts.
+    pub gas_estimate: Option<String>,
+    /// Fees in wei the mint would be sent with under the gas policy.
+    pub max_fee_per_gas: String,
+    pub max_priority_fee_per_gas: String,
+    /// `false` when the network fee is above the configured cap, in which case the mint
+    /// is refused or held in the queue.
+    pub within_fee_cap: bool,
+    /// `gas_estimate * max_fee_per_gas`, an upper bound of the cost in ETH.
+    pub estimated_cost_eth: Option<String>,
+    /// Why the mint would revert, decoded when the contract error is known.
+    pub revert: Option<ProblemDetails>,
+}
+
+/// Runs `eth_call` and `eth_estimateGas` for the mint from the signer's address.
+pub async fn simulate_mint(
+    collection: &Collection,
+    owner_address: &str,
+    token_id: u64,
+    data: Option<&MintData>,
+    config: Arc<Constants>,
+    ethers_client: Arc<EthersClient>,
+) -> Result<MintSimulation, ServerError> {
+    let contract = nft_contract(collection, ethers_client.clone())?;
+
+    let contract_call = mint_call::<()>(&contract, owner_address, token_id, data)?;
+
+    let fees = suggest_fees(ethers_client.provider(), &config.gas_policy).await?;
+
+    let mut simulation = MintSimulation {
+        will_succeed: false,
+        gas_estimate: None,
+        max_fee_per_gas: fees.max_fee_per_gas.to_string(),
+        max_priority_fee_per_gas: fees.max_priority_fee_per_gas.to_string(),
+        within_fee_cap: fees.check_cap(&config.gas_policy).is_ok(),
+        estimated_cost_eth: None,
+        revert: None,
+    };
+
+    if let Err(e) = contract_call.call().await {
+        return match ServerError::from(e) {
+            ServerError::ContractRevert(revert) => {
+                simulation.revert =
+                    Some(ProblemDetails::from(&ServerError::ContractRevert(revert)));
+                Ok(simulation)
+            }
+            e => Err(e),
+        };
+    }
+
+    let gas_estimate = match config.gas_policy.mint_gas_limit {
+        Some(gas_limit) => gas_limit,
+        None => contract_call.estimate_gas().await?,
+    };
+    simulation.will_succeed = true;
+    simulation.gas_estimate = Some(gas_estimate.to_string());
+    simulation.estimated_cost_eth = Some(format_ether(gas_estimate * fees.max_fee_per_gas));
+    Ok(simulation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_strings_as_hex_with_or_without_prefix() {
+        for value in ["\"0x01ff\"", "\"01ff\""] {
+            let data: MintData = serde_json::from_str(value).unwrap();
+            assert_eq!(data.to_bytes().unwrap(), Bytes::from(vec![0x01, 0xff]));
+        }
+        let data: MintData = serde_json::from_str("\"not hex\"").unwrap();
+        assert!(matches!(data.to_bytes(), Err(ServerError::Validation(_))));
+    }
 
-    match send_transaction(contract_call, wait_confirmation).await {
-        Ok(tx_receipt) => Ok(tx_receipt),
-        Err(e) => Err(ServerError::from(e)),
+    #[test]
+    fn takes_objects_as_json() {
+        let data: MintData = serde_json::from_str(r#"{"edition":1}"#).unwrap();
+        assert!(matches!(data, MintData::Json(_)));
+        assert!(serde_json::from_str::<MintData>("[1, 2]").is_err());
+        assert!(serde_json::from_str::<MintData>("12").is_err());
     }
 }
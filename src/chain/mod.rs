@@ -1,3 +1,10 @@
 pub mod chain;
+pub mod events;
 mod helpers;
 pub mod mint;
+pub mod registry;
+pub mod remote_signer;
+pub mod revert;
+pub mod rpc;
+pub mod signer;
+pub mod signer_pool;
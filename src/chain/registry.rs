@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use ethers::providers::Provider;
+use ethers::types::H256;
+
+use super::chain::get_provider;
+use super::rpc::{RpcClient, RpcProvider};
+use crate::constants::{ChainConfig, Constants};
+
+/// A configured network with its read clients.
+pub struct Chain {
+    pub config: ChainConfig,
+    pub provider: Arc<RpcProvider>,
+    /// Same endpoints as `provider`, but answers need the agreement of `rpc_quorum` of
+    /// them. For reads a wrong answer of one endpoint must not decide.
+    pub quorum_provider: Arc<RpcProvider>,
+}
+
+impl Chain {
+    /// Link to the transaction on the chain's block explorer, if one is configured.
+    pub fn tx_url(&self, tx_hash: H256) -> Option<String> {
+        let explorer_url = self.config.explorer_url.as_ref()?;
+        Some(format!("{}/tx/{:?}", explorer_url, tx_hash))
+    }
+
+    /// Whether each RPC endpoint, in `rpc_urls` order, is currently used.
+    pub fn endpoint_health(&self) -> Vec<bool> {
+        let client: &RpcClient = (*self.provider).as_ref();
+        client.endpoint_health()
+    }
+}
+
+/// Every configured chain, with each contract bound to the chain it is deployed on.
+pub struct ChainRegistry {
+    chains: HashMap<String, Arc<Chain>>,
+    nft_chain: String,
+    auction_chain: String,
+}
+
+impl ChainRegistry {
+    pub fn from_config(config: &Constants) -> Result<Self> {
+        let mut chains = HashMap::new();
+        for (name, chain_config) in &config.chains {
+            let provider = get_provider(chain_config)?;
+            let quorum_provider = Provider::new(
+                provider
+                    .as_ref()
+                    .with_quorum(chain_config.rpc_policy.quorum),
+            );
+            chains.insert(
+                name.clone(),
+                Arc::new(Chain {
+                    config: chain_config.clone(),
+                    provider: Arc::new(provider),
+                    quorum_provider: Arc::new(quorum_provider),
+                }),
+            );
+        }
+        Ok(ChainRegistry {
+            chains,
+            nft_chain: config.nft_chain.clone(),
+            auction_chain: config.auction_chain.clone(),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Chain>> {
+        self.chains.get(name)
+    }
+
+    /// Chain of the signer pool. Mints and their transactions live here.
+    pub fn nft(&self) -> &Arc<Chain> {
+        &self.chains[&self.nft_chain]
+    }
+
+    pub fn auction(&self) -> &Arc<Chain> {
+        &self.chains[&self.auction_chain]
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &Arc<Chain>> {
+        self.chains.values()
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ethers::signers::{to_eip155_v, Signer};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::transaction::eip712::Eip712;
+use ethers::types::{Address, Signature};
+use ethers::utils::{hex, raw_public_key_to_address};
+use serde::{Deserialize, Serialize};
+#[derive(Debug)]
+pub enum RemoteSignerError {
+    Http(reqwest::Error),
+    /// The signer answered with something that is not a usable key or signature.
+    InvalidResponse(String),
+    /// The data to sign could not be encoded.
+    Encoding(String),
+}
+
+impl fmt::Display for RemoteSignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteSignerError::Http(e) => write!(f, "Remote signer request failed: {}", e),
+            RemoteSignerError::InvalidResponse(message) => {
+                write!(f, "Invalid remote signer response: {}", message)
+            }
+            RemoteSignerError::Encoding(message) => {
+                write!(f, "Failed to encode the data to sign: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RemoteSignerError {}
+
+impl From<reqwest::Error> for RemoteSignerError {
+    fn from(e: reqwest::Error) -> Self {
+        RemoteSignerError::Http(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignRequest {
+    data: String,
+}
+
+/// A key held by a service speaking the Web3Signer eth1 API. The service signs
+/// `keccak256(data)` for the raw bytes it is sent, so every signature kind is reduced
+/// to the preimage of the hash a local wallet would sign.
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    http: reqwest::Client,
+    url: String,
+    /// Uncompressed secp256k1 key as the signer lists it, `0x` prefixed.
+    public_key: String,
+    address: Address,
+    chain_id: u64,
+}
+
+impl RemoteSigner {
+    pub fn new(
+        http: reqwest::Client,
+        url: &str,
+        public_key: &str,
+        chain_id: u64,
+    ) -> Result<Self, RemoteSignerError> {
+        Ok(RemoteSigner {
+            address: public_key_address(public_key)?,
+            http,
+            url: url.to_string(),
+            public_key: public_key.to_string(),
+            chain_id,
+        })
+    }
+
+    /// The keys the signer can sign with.
+    pub async fn public_keys(
+        http: &reqwest::Client,
+        url: &str,
+    ) -> Result<Vec<String>, RemoteSignerError> {
+        let keys = http
+            .get(format!("{}/api/v1/eth1/publicKeys", url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(keys)
+    }
+
+    async fn sign_data(&self, data: &[u8]) -> Result<Signature, RemoteSignerError> {
+        let signature = self
+            .http
+            .post(format!("{}/api/v1/eth1/sign/{}", self.url, self.public_key))
+            .json(&SignRequest {
+                data: format!("0x{}", hex::encode(data)),
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Signature::from_str(signature.trim())
+            .map_err(|e| RemoteSignerError::InvalidResponse(e.to_string()))
+    }
+}
+
+#[async_trait::async_trait]
+impl Signer for RemoteSigner {
+    type Error = RemoteSignerError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        let message = message.as_ref();
+        let mut data = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+        data.extend_from_slice(message);
+        self.sign_data(&data).await
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, Self::Error> {
+        // The rlp must carry the same chain id as `v`.
+        let chain_id = tx.chain_id().map(|id| id.as_u64()).unwrap_or(self.chain_id);
+        let mut tx = tx.clone();
+        tx.set_chain_id(chain_id);
+
+        let mut signature = self.sign_data(&tx.rlp()).await?;
+        let recovery_id = signature.v.checked_sub(27).unwrap_or(signature.v);
+        signature.v = to_eip155_v(recovery_id as u8, chain_id);
+        Ok(signature)
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        let domain_separator = payload
+            .domain_separator()
+            .map_err(|e| RemoteSignerError::Encoding(e.to_string()))?;
+        let struct_hash = payload
+            .struct_hash()
+            .map_err(|e| RemoteSignerError::Encoding(e.to_string()))?;
+        let mut data = vec![0x19, 0x01];
+        data.extend_from_slice(&domain_separator);
+        data.extend_from_slice(&struct_hash);
+        self.sign_data(&data).await
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn with_chain_id<T: Into<u64>>(mut self, chain_id: T) -> Self {
+        self.chain_id = chain_id.into();
+        self
+    }
+}
+
+/// Web3Signer lists keys as the 64 raw bytes of the point; the SEC1 form with its
+/// `0x04` tag is accepted as well.
+fn public_key_address(public_key: &str) -> Result<Address, RemoteSignerError> {
+    let bytes = hex::decode(public_key)
+        .map_err(|e| RemoteSignerError::InvalidResponse(format!("{}: {}", public_key, e)))?;
+    let raw = match bytes.as_slice() {
+        [0x04, raw @ ..] if raw.len() == 64 => raw,
+        raw if raw.len() == 64 => raw,
+        _ => {
+            return Err(RemoteSignerError::InvalidResponse(format!(
+                "{} is not an uncompressed public key",
+                public_key
+            )))
+        }
+    };
+    Ok(raw_public_key_to_address(raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use ethers::signers::LocalWallet;
+    use ethers::types::{Eip1559TransactionRequest, TransactionRequest, U256};
+    use ethers::utils::keccak256;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    fn wallet_public_key(wallet: &LocalWallet) -> String {
+        let point = wallet.signer().verifying_key().to_encoded_point(false);
+        format!("0x{}", hex::encode(&point.as_bytes()[1..]))
+    }
+
+    /// Web3Signer stand-in holding `wallets`. Returns its URL.
+    fn serve(wallets: Vec<LocalWallet>) -> String {
+        let wallets: Arc<HashMap<String, LocalWallet>> = Arc::new(
+            wallets
+                .into_iter()
+                .map(|wallet| (wallet_public_key(&wallet), wallet))
+                .collect(),
+        );
+        let public_keys = {
+            let wallets = wallets.clone();
+            warp::path!("api" / "v1" / "eth1" / "publicKeys")
+                .and(warp::get())
+                .map(move || warp::reply::json(&wallets.keys().collect::<Vec<_>>()))
+        };
+        let sign = warp::path!("api" / "v1" / "eth1" / "sign" / String)
+            .and(warp::post())
+            .and(warp::body::json())
+            .map(move |public_key: String, request: SignRequest| {
+                let Some(wallet) = wallets.get(&public_key) else {
+                    return warp::reply::with_status(
+                        "Unknown key".to_string(),
+                        StatusCode::NOT_FOUND,
+                    );
+                };
+                let data = hex::decode(&request.data).unwrap();
+                let signature = wallet.sign_hash(keccak256(data).into()).unwrap();
+                warp::reply::with_status(
+                    format!("0x{}", hex::encode(signature.to_vec())),
+                    StatusCode::OK,
+                )
+            });
+        let (address, server) =
+            warp::serve(public_keys.or(sign)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", address)
+    }
+
+    fn wallet() -> LocalWallet {
+        "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse()
+            .unwrap()
+    }
+
+    async fn remote_signer(chain_id: u64) -> RemoteSigner {
+        let url = serve(vec![wallet()]);
+        let http = reqwest::Client::new();
+        let public_keys = RemoteSigner::public_keys(&http, &url).await.unwrap();
+        assert_eq!(public_keys.len(), 1);
+        RemoteSigner::new(http, &url, &public_keys[0], chain_id).unwrap()
+    }
+
+    #[tokio::test]
+    async fn derives_the_address_of_the_listed_key() {
+        assert_eq!(remote_signer(137).await.address(), wallet().address());
+        let sec1 = format!("0x04{}", &wallet_public_key(&wallet())[2..]);
+        assert_eq!(public_key_address(&sec1).unwrap(), wallet().address());
+        assert!(public_key_address("0x1234").is_err());
+    }
+
+    #[tokio::test]
+    async fn signs_legacy_transactions_recoverable_to_the_sender() {
+        let signer = remote_signer(137).await;
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::from_low_u64_be(0xbb))
+            .value(1)
+            .nonce(4)
+            .gas(21_000)
+            .gas_price(30_000_000_000u64)
+            .into();
+
+        let signature = signer.sign_transaction(&tx).await.unwrap();
+        // EIP-155: v carries the chain id the rlp was signed with.
+        assert!(signature.v == 137 * 2 + 35 || signature.v == 137 * 2 + 36);
+        let mut signed = tx.clone();
+        signed.set_chain_id(137);
+        assert_eq!(
+            signature.recover(signed.sighash()).unwrap(),
+            wallet().address()
+        );
+        assert_eq!(
+            signature,
+            wallet()
+                .with_chain_id(137u64)
+                .sign_transaction(&tx)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn signs_eip1559_transactions_with_their_own_chain_id() {
+        let signer = remote_signer(137).await;
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(Address::from_low_u64_be(0xbb))
+            .value(U256::from(1))
+            .nonce(4)
+            .gas(21_000)
+            .max_fee_per_gas(30_000_000_000u64)
+            .max_priority_fee_per_gas(1_000_000_000u64)
+            .chain_id(80002u64)
+            .into();
+
+        let signature = signer.sign_transaction(&tx).await.unwrap();
+        assert_eq!(signature.recover(tx.sighash()).unwrap(), wallet().address());
+    }
+
+    #[tokio::test]
+    async fn signs_messages_recoverable_to_the_sender() {
+        let signer = remote_signer(137).await;
+        let signature = signer.sign_message("hello").await.unwrap();
+        assert_eq!(signature.recover("hello").unwrap(), wallet().address());
+    }
+}
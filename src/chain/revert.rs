@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use ethers::abi::ethabi::AbiError;
+use ethers::abi::{decode, Abi, ErrorExt, ParamType, Token};
+use ethers::types::{Bytes, I256};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+const NFT_ABI: &[u8] = include_bytes!("../abi/SnapitNFT.json");
+const AUCTION_ABI: &[u8] = include_bytes!("../abi/Auction.json");
+
+/// Selector of the builtin `Error(string)` revert.
+const ERROR_STRING_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of the builtin `Panic(uint256)` revert.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Custom errors that mean the call conflicts with the current on-chain state,
+/// as opposed to being invalid input.
+const CONFLICT_ERRORS: &[&str] = &[
+    "ERC721InvalidSender",
+    "ERC721IncorrectOwner",
+    "TokenAlreadyClaimed",
+    "AuctionHasNotBeenClaimed",
+    "AuctionHasNotFinished",
+    "BiddingHasFinished",
+    "BiddingHasNotStarted",
+    "ReentrancyGuardReentrantCall",
+];
+
+/// A revert payload decoded against the SnapitNFT and Auction ABIs.
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedRevert {
+    /// Solidity error name, e.g. `ERC721InvalidReceiver`.
+    pub name: String,
+    /// Stable API code derived from the name, e.g. `ERC721_INVALID_RECEIVER`.
+    pub code: String,
+    pub args: Map<String, Value>,
+    /// True when the revert reflects on-chain state rather than bad input.
+    pub conflict: bool,
+}
+
+fn known_errors() -> &'static HashMap<[u8; 4], AbiError> {
+    static ERRORS: OnceLock<HashMap<[u8; 4], AbiError>> = OnceLock::new();
+    ERRORS.get_or_init(|| {
+        let mut errors = HashMap::new();
+        for abi_bytes in [NFT_ABI, AUCTION_ABI] {
+            let abi: Abi = serde_json::from_slice(abi_bytes).expect("ABI should be valid JSON");
+            for error in abi.errors() {
+                errors.insert(error.selector(), error.clone());
+            }
+        }
+        errors
+    })
+}
+
+/// Decodes raw revert data into a named error with its arguments.
+/// Returns `None` when the selector is not known.
+pub fn decode_revert(data: &Bytes) -> Option<DecodedRevert> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (selector, payload) = data.split_at(4);
+    let selector: [u8; 4] = selector.try_into().ok()?;
+
+    if selector == ERROR_STRING_SELECTOR {
+        let tokens = decode(&[ParamType::String], payload).ok()?;
+        let mut args = Map::new();
+        args.insert("reason".to_string(), token_to_json(&tokens[0]));
+        return Some(DecodedRevert {
+            name: "Error".to_string(),
+            code: "REVERT".to_string(),
+            args,
+            conflict: false,
+        });
+    }
+
+    if selector == PANIC_SELECTOR {
+        let tokens = decode(&[ParamType::Uint(256)], payload).ok()?;
+        let mut args = Map::new();
+        args.insert("code".to_string(), token_to_json(&tokens[0]));
+        return Some(DecodedRevert {
+            name: "Panic".to_string(),
+            code: "PANIC".to_string(),
+            args,
+            conflict: false,
+        });
+    }
+
+    let error = known_errors().get(&selector)?;
+    let tokens = error.decode(payload).ok()?;
+
+    let args = error
+        .inputs
+        .iter()
+        .zip(tokens.iter())
+        .enumerate()
+        .map(|(i, (param, token))| {
+            let name = if param.name.is_empty() {
+                i.to_string()
+            } else {
+                param.name.clone()
+            };
+            (name, token_to_json(token))
+        })
+        .collect();
+
+    Some(DecodedRevert {
+        name: error.name.clone(),
+        code: to_screaming_snake_case(&error.name),
+        args,
+        conflict: CONFLICT_ERRORS.contains(&error.name.as_str()),
+    })
+}
+
+/// `ERC721InvalidReceiver` -> `ERC721_INVALID_RECEIVER`
+fn to_screaming_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if i > 0 && c.is_uppercase() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next_is_lower)
+            {
+                out.push('_');
+            }
+        }
+        out.push(c.to_ascii_uppercase());
+    }
+    out
+}
+
+fn token_to_json(token: &Token) -> Value {
+    match token {
+        Token::Address(address) => Value::String(format!("{:?}", address)),
+        Token::Uint(value) => Value::String(value.to_string()),
+        // ABI ints are two's complement.
+        Token::Int(value) => Value::String(I256::from_raw(*value).to_string()),
+        Token::Bool(value) => Value::Bool(*value),
+        Token::String(value) => Value::String(value.clone()),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => {
+            Value::String(format!("0x{}", ethers::utils::hex::encode(bytes)))
+        }
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+            Value::Array(tokens.iter().map(token_to_json).collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::encode;
+    use ethers::types::{Address, U256};
+    use ethers::utils::id;
+
+    fn revert_data(selector: [u8; 4], args: &[Token]) -> Bytes {
+        let mut data = selector.to_vec();
+        data.extend(encode(args));
+        data.into()
+    }
+
+    #[test]
+    fn decodes_error_strings() {
+        let data = revert_data(
+            ERROR_STRING_SELECTOR,
+            &[Token::String("Not allowed".to_string())],
+        );
+        let decoded = decode_revert(&data).unwrap();
+        assert_eq!(decoded.name, "Error");
+        assert_eq!(decoded.code, "REVERT");
+        assert_eq!(decoded.args["reason"], "Not allowed");
+        assert!(!decoded.conflict);
+    }
+
+    #[test]
+    fn decodes_panics() {
+        // 0x11: arithmetic overflow.
+        let data = revert_data(PANIC_SELECTOR, &[Token::Uint(U256::from(0x11))]);
+        let decoded = decode_revert(&data).unwrap();
+        assert_eq!(decoded.name, "Panic");
+        assert_eq!(decoded.code, "PANIC");
+        assert_eq!(decoded.args["code"], "17");
+    }
+
+    #[test]
+    fn decodes_custom_errors_with_named_args() {
+        let sender = Address::from_low_u64_be(1);
+        let owner = Address::from_low_u64_be(2);
+        let data = revert_data(
+            id("ERC721IncorrectOwner(address,uint256,address)"),
+            &[
+                Token::Address(sender),
+                Token::Uint(U256::from(7)),
+                Token::Address(owner),
+            ],
+        );
+        let decoded = decode_revert(&data).unwrap();
+        assert_eq!(decoded.name, "ERC721IncorrectOwner");
+        assert_eq!(decoded.code, "ERC721_INCORRECT_OWNER");
+        assert_eq!(decoded.args["sender"], format!("{:?}", sender));
+        assert_eq!(decoded.args["tokenId"], "7");
+        assert_eq!(decoded.args["owner"], format!("{:?}", owner));
+        assert!(decoded.conflict);
+    }
+
+    #[test]
+    fn decodes_auction_errors_without_args() {
+        let decoded = decode_revert(&revert_data(id("BidPriceTooLow()"), &[])).unwrap();
+        assert_eq!(decoded.code, "BID_PRICE_TOO_LOW");
+        assert!(decoded.args.is_empty());
+        assert!(!decoded.conflict);
+    }
+
+    #[test]
+    fn leaves_unknown_and_short_data_undecoded() {
+        assert!(decode_revert(&revert_data(id("Unknown()"), &[])).is_none());
+        assert!(decode_revert(&Bytes::from(vec![0x08, 0xc3])).is_none());
+        // A known selector with a payload that does not match its inputs.
+        assert!(decode_revert(&Bytes::from(ERROR_STRING_SELECTOR.to_vec())).is_none());
+    }
+
+    #[test]
+    fn renders_signed_ints_with_their_sign() {
+        let minus_one = I256::from(-1).into_raw();
+        assert_eq!(token_to_json(&Token::Int(minus_one)), "-1");
+        assert_eq!(token_to_json(&Token::Int(U256::from(5))), "5");
+        assert_eq!(
+            token_to_json(&Token::Uint(U256::MAX)),
+            U256::MAX.to_string().as_str()
+        );
+    }
+
+    #[test]
+    fn converts_error_names_to_screaming_snake_case() {
+        assert_eq!(
+            to_screaming_snake_case("ERC721InvalidReceiver"),
+            "ERC721_INVALID_RECEIVER"
+        );
+        assert_eq!(
+            to_screaming_snake_case("OwnableUnauthorizedAccount"),
+            "OWNABLE_UNAUTHORIZED_ACCOUNT"
+        );
+        assert_eq!(
+            to_screaming_snake_case("ERC2981InvalidDefaultRoyalty"),
+            "ERC2981_INVALID_DEFAULT_ROYALTY"
+        );
+        assert_eq!(to_screaming_snake_case("NFTNotFound"), "NFT_NOT_FOUND");
+        assert_eq!(to_screaming_snake_case("Error"), "ERROR");
+    }
+}
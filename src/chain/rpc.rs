@@ -0,0 +1,574 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, JsonRpcClient, JsonRpcError, Provider, ProviderError, RpcError,
+};
+use ethers::types::{Bytes, H256};
+use ethers::utils::keccak256;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::constants::RpcPolicy;
+
+pub type RpcProvider = Provider<RpcClient>;
+
+/// Wait before the first retry round; it doubles with every further round.
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+/// How long an endpoint is skipped after its first failure in a row. It doubles with
+/// every further failure, up to `MAX_COOLDOWN`.
+const COOLDOWN: Duration = Duration::from_secs(5);
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// JSON-RPC transport over the endpoints of one chain. Requests go to the first healthy
+/// endpoint and fail over to the next on transient errors (timeouts, connection errors,
+/// rate limits, garbled responses); endpoints that fail are skipped for a cooldown.
+/// Once every endpoint failed, the whole round is retried with exponential backoff.
+///
+/// With a quorum above 1, every endpoint is asked and the answer that `quorum` of them
+/// agree on is returned.
+#[derive(Debug, Clone)]
+pub struct RpcClient {
+    endpoints: Arc<Vec<Endpoint>>,
+    retries: u32,
+    quorum: usize,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    http: Http,
+    /// Position in the chain's `rpc_urls`. Logs and metrics name endpoints by it, as
+    /// URLs often carry an API key.
+    index: usize,
+    chain: String,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+impl RpcClient {
+    /// Client of `urls`, named `chain` in logs. Quorum reads go through
+    /// [`RpcClient::with_quorum`].
+    pub fn new(chain: &str, urls: &[String], policy: &RpcPolicy) -> Result<Self> {
+        anyhow::ensure!(!urls.is_empty(), "chain {} has no RPC URL", chain);
+        let timeout = Duration::from_secs(policy.timeout_secs);
+        let http_client = reqwest::Client::builder().timeout(timeout).build()?;
+        let endpoints = urls
+            .iter()
+            .enumerate()
+            .map(|(index, url)| {
+                Ok(Endpoint {
+                    http: Http::new_with_client(reqwest::Url::parse(url)?, http_client.clone()),
+                    index,
+                    chain: chain.to_string(),
+                    health: Mutex::new(Health::default()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RpcClient {
+            endpoints: Arc::new(endpoints),
+            retries: policy.retries,
+            quorum: 1,
+        })
+    }
+
+    /// The same endpoints, sharing their health, with answers needing `quorum` of them.
+    pub fn with_quorum(&self, quorum: usize) -> Self {
+        RpcClient {
+            quorum: quorum.clamp(1, self.endpoints.len()),
+            ..self.clone()
+        }
+    }
+
+    /// Whether each endpoint, in configuration order, is currently used.
+    pub fn endpoint_health(&self) -> Vec<bool> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.is_healthy())
+            .collect()
+    }
+
+    /// Healthy endpoints in configuration order, then the others by when they recover,
+    /// so a request still gets through when all of them are cooling down.
+    fn ordered_endpoints(&self) -> Vec<&Endpoint> {
+        let (mut healthy, mut unhealthy): (Vec<&Endpoint>, Vec<&Endpoint>) = self
+            .endpoints
+            .iter()
+            .partition(|endpoint| endpoint.is_healthy());
+        unhealthy.sort_by_key(|endpoint| endpoint.health.lock().unwrap().unhealthy_until);
+        healthy.append(&mut unhealthy);
+        healthy
+    }
+
+    async fn failover_round(&self, method: &str, params: &Value) -> Result<Value, RpcClientError> {
+        let mut last_error = None;
+        for endpoint in self.ordered_endpoints() {
+            match endpoint.send(method, params).await {
+                Ok(value) => return Ok(value),
+                Err(err) if is_transient(&err) => last_error = Some(err),
+                Err(err) => return Err(RpcClientError::Endpoint(err)),
+            }
+        }
+        Err(RpcClientError::Endpoint(
+            last_error.expect("a client has at least one endpoint"),
+        ))
+    }
+
+    async fn quorum_round(&self, method: &str, params: &Value) -> Result<Value, RpcClientError> {
+        let mut pending: FuturesUnordered<_> = self
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.send(method, params))
+            .collect();
+        // Distinct answers with how many endpoints gave them. Error responses count as
+        // answers too; a call that reverts reverts everywhere.
+        let mut answers: Vec<(Result<Value, JsonRpcError>, usize)> = Vec::new();
+        let mut answered = 0;
+        while let Some(result) = pending.next().await {
+            let answer = match result {
+                Ok(value) => Ok(value),
+                Err(HttpClientError::JsonRpcError(err)) if !is_transient_response(&err) => Err(err),
+                Err(_) => continue,
+            };
+            answered += 1;
+            let index = match answers
+                .iter()
+                .position(|(known, _)| same_answer(known, &answer))
+            {
+                Some(index) => index,
+                None => {
+                    answers.push((answer, 0));
+                    answers.len() - 1
+                }
+            };
+            answers[index].1 += 1;
+            if answers[index].1 >= self.quorum {
+                return answers
+                    .swap_remove(index)
+                    .0
+                    .map_err(|err| RpcClientError::Endpoint(HttpClientError::JsonRpcError(err)));
+            }
+        }
+        Err(RpcClientError::NoQuorum {
+            required: self.quorum,
+            answers: answered,
+        })
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for RpcClient {
+    type Error = RpcClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, RpcClientError>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = match serde_json::to_value(&params) {
+            // Methods without parameters serialize `()`, which nodes expect as `[]`.
+            Ok(Value::Null) => Value::Array(Vec::new()),
+            Ok(params) => params,
+            Err(err) => {
+                return Err(RpcClientError::Endpoint(HttpClientError::SerdeJson {
+                    err,
+                    text: format!("{:?}", params),
+                }))
+            }
+        };
+
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 0;
+        let result = loop {
+            let result = if self.quorum > 1 {
+                self.quorum_round(method, &params).await
+            } else {
+                self.failover_round(method, &params).await
+            };
+            match result {
+                Err(err) if err.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    eprintln!(
+                        "{} failed on the RPC endpoints ({}), retry {} of {} in {:?}",
+                        method, err, attempt, self.retries, backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => break result,
+            }
+        };
+
+        let value = match result {
+            Ok(value) => value,
+            // A send that timed out may have reached the node, which then rejects the
+            // retry. The transaction is in its pool either way.
+            Err(RpcClientError::Endpoint(HttpClientError::JsonRpcError(err)))
+                if method == "eth_sendRawTransaction" && is_already_known(&err) =>
+            {
+                match raw_transaction_hash(&params) {
+                    Some(hash) => serde_json::to_value(hash).expect("hashes serialize"),
+                    None => {
+                        return Err(RpcClientError::Endpoint(HttpClientError::JsonRpcError(err)))
+                    }
+                }
+            }
+            Err(err) => return Err(err),
+        };
+        serde_json::from_value(value.clone()).map_err(|err| {
+            RpcClientError::Endpoint(HttpClientError::SerdeJson {
+                err,
+                text: value.to_string(),
+            })
+        })
+    }
+}
+
+impl Endpoint {
+    fn is_healthy(&self) -> bool {
+        let health = self.health.lock().unwrap();
+        health
+            .unhealthy_until
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    async fn send(&self, method: &str, params: &Value) -> Result<Value, HttpClientError> {
+        let result = self.http.request::<_, Value>(method, params).await;
+        match &result {
+            Err(err) if is_transient(err) => self.record_failure(err),
+            _ => self.record_success(),
+        }
+        result
+    }
+
+    fn record_failure(&self, err: &HttpClientError) {
+        let mut health = self.health.lock().unwrap();
+        health.consecutive_failures += 1;
+        let cooldown = COOLDOWN
+            .saturating_mul(2u32.saturating_pow(health.consecutive_failures - 1))
+            .min(MAX_COOLDOWN);
+        health.unhealthy_until = Some(Instant::now() + cooldown);
+        eprintln!(
+            "RPC endpoint {} of chain {} failed ({}), skipping it for {:?}",
+            self.index, self.chain, err, cooldown
+        );
+    }
+
+    fn record_success(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.consecutive_failures > 0 {
+            println!(
+                "RPC endpoint {} of chain {} recovered",
+                self.index, self.chain
+            );
+        }
+        *health = Health::default();
+    }
+}
+
+/// Errors another endpoint, or the same one a little later, may not give.
+fn is_transient(err: &HttpClientError) -> bool {
+    match err {
+        // Timeouts, refused connections, and HTML error pages of gateways.
+        HttpClientError::ReqwestError(_) | HttpClientError::SerdeJson { .. } => true,
+        HttpClientError::JsonRpcError(err) => is_transient_response(err),
+    }
+}
+
+fn is_transient_response(err: &JsonRpcError) -> bool {
+    if err.is_revert() {
+        return false;
+    }
+    // 429 and -32005 are what providers answer when rate limiting.
+    if err.code == 429 || err.code == -32005 {
+        return true;
+    }
+    let message = err.message.to_lowercase();
+    [
+        "rate limit",
+        "too many requests",
+        "header not found",
+        "timeout",
+        "timed out",
+        "temporarily unavailable",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+fn is_already_known(err: &JsonRpcError) -> bool {
+    let message = err.message.to_lowercase();
+    message.contains("already known") || message.contains("known transaction")
+}
+
+fn raw_transaction_hash(params: &Value) -> Option<H256> {
+    let raw: Bytes = params.get(0)?.as_str()?.parse().ok()?;
+    Some(H256::from(keccak256(raw)))
+}
+
+fn same_answer(a: &Result<Value, JsonRpcError>, b: &Result<Value, JsonRpcError>) -> bool {
+    match (a, b) {
+        (Ok(a), Ok(b)) => a == b,
+        (Err(a), Err(b)) => a.code == b.code && a.message == b.message,
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum RpcClientError {
+    /// The error of the last endpoint tried, or the error response of a node.
+    Endpoint(HttpClientError),
+    /// Fewer than `required` endpoints agreed on an answer.
+    NoQuorum { required: usize, answers: usize },
+}
+
+impl RpcClientError {
+    fn is_transient(&self) -> bool {
+        match self {
+            RpcClientError::Endpoint(err) => is_transient(err),
+            RpcClientError::NoQuorum { .. } => true,
+        }
+    }
+}
+
+impl fmt::Display for RpcClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcClientError::Endpoint(err) => write!(f, "{}", err),
+            RpcClientError::NoQuorum { required, answers } => write!(
+                f,
+                "no {} RPC endpoints agreed on an answer ({} answered)",
+                required, answers
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RpcClientError {}
+
+impl RpcError for RpcClientError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            RpcClientError::Endpoint(err) => err.as_error_response(),
+            RpcClientError::NoQuorum { .. } => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            RpcClientError::Endpoint(err) => err.as_serde_error(),
+            RpcClientError::NoQuorum { .. } => None,
+        }
+    }
+}
+
+impl From<RpcClientError> for ProviderError {
+    fn from(err: RpcClientError) -> ProviderError {
+        ProviderError::JsonRpcClientError(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::Middleware;
+    use ethers::types::transaction::eip2718::TypedTransaction;
+    use ethers::types::TransactionRequest;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use warp::Filter;
+
+    #[derive(Clone, Copy)]
+    enum Mode {
+        /// Answers `eth_blockNumber` with this number.
+        Block(u64),
+        /// A gateway error page instead of JSON.
+        Html,
+        Revert,
+        /// Fails like `Html` for the first calls, then answers.
+        HtmlThenBlock(usize, u64),
+        /// Never answers within the client timeout.
+        Hang,
+        AlreadyKnown,
+    }
+
+    /// Mock JSON-RPC endpoint. Returns its URL and the number of requests it got.
+    async fn serve(mode: Mode) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let route = warp::post()
+            .and(warp::body::json())
+            .and_then(move |request: Value| {
+                let call = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    let id = request["id"].clone();
+                    let result = |result: Value| {
+                        serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result })
+                            .to_string()
+                    };
+                    let error = |code: i64, message: &str| {
+                        serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": { "code": code, "message": message, "data": "0x" },
+                        })
+                        .to_string()
+                    };
+                    let body = match mode {
+                        Mode::Block(number) => result(format!("0x{:x}", number).into()),
+                        Mode::Html => "<html>502 Bad Gateway</html>".to_string(),
+                        Mode::Revert => error(3, "execution reverted"),
+                        Mode::HtmlThenBlock(failures, _) if call < failures => {
+                            "<html>502 Bad Gateway</html>".to_string()
+                        }
+                        Mode::HtmlThenBlock(_, number) => result(format!("0x{:x}", number).into()),
+                        Mode::Hang => {
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                            String::new()
+                        }
+                        Mode::AlreadyKnown => error(-32000, "already known"),
+                    };
+                    Ok::<_, warp::Rejection>(body)
+                }
+            });
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}", address), calls)
+    }
+
+    fn policy(retries: u32) -> RpcPolicy {
+        RpcPolicy {
+            timeout_secs: 1,
+            retries,
+            quorum: 1,
+        }
+    }
+
+    fn provider(urls: &[String], retries: u32, quorum: usize) -> (RpcClient, RpcProvider) {
+        let client = RpcClient::new("test", urls, &policy(retries))
+            .unwrap()
+            .with_quorum(quorum);
+        (client.clone(), Provider::new(client))
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_endpoint_and_skips_the_failed_one() {
+        let (down, down_calls) = serve(Mode::Html).await;
+        let (up, up_calls) = serve(Mode::Block(16)).await;
+        let (client, provider) = provider(&[down, up], 0, 1);
+
+        assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 16);
+        assert_eq!(client.endpoint_health(), vec![false, true]);
+
+        // The failed endpoint cools down, so the next request goes straight to the other.
+        assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 16);
+        assert_eq!(down_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(up_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn unhealthy_endpoints_are_still_tried_last() {
+        let (flaky, calls) = serve(Mode::HtmlThenBlock(1, 7)).await;
+        let (client, provider) = provider(&[flaky], 0, 1);
+
+        assert!(provider.get_block_number().await.is_err());
+        assert_eq!(client.endpoint_health(), vec![false]);
+        // Cooling down, but it is the only endpoint left.
+        assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 7);
+        assert_eq!(client.endpoint_health(), vec![true]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_rounds_with_backoff() {
+        let (flaky, calls) = serve(Mode::HtmlThenBlock(2, 9)).await;
+        let (_, provider) = provider(&[flaky], 2, 1);
+
+        let started = Instant::now();
+        assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 9);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        // 250ms before the first retry, 500ms before the second.
+        assert!(started.elapsed() >= RETRY_BACKOFF * 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_configured_retries() {
+        let (down, calls) = serve(Mode::Html).await;
+        let (_, provider) = provider(&[down], 1, 1);
+
+        let err = provider.get_block_number().await.unwrap_err();
+        assert!(err.as_serde_error().is_some());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn returns_reverts_without_retrying_or_failing_over() {
+        let (reverting, reverting_calls) = serve(Mode::Revert).await;
+        let (up, up_calls) = serve(Mode::Block(1)).await;
+        let (client, provider) = provider(&[reverting, up], 2, 1);
+
+        let err = provider.get_block_number().await.unwrap_err();
+        assert!(err.as_error_response().unwrap().is_revert());
+        assert_eq!(reverting_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(up_calls.load(Ordering::SeqCst), 0);
+        assert_eq!(client.endpoint_health(), vec![true, true]);
+    }
+
+    #[tokio::test]
+    async fn times_out_slow_endpoints() {
+        let (slow, _) = serve(Mode::Hang).await;
+        let (up, _) = serve(Mode::Block(5)).await;
+        let (client, provider) = provider(&[slow, up], 0, 1);
+
+        let started = Instant::now();
+        assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 5);
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(client.endpoint_health(), vec![false, true]);
+    }
+
+    #[tokio::test]
+    async fn returns_the_answer_a_quorum_agrees_on() {
+        let (outlier, _) = serve(Mode::Block(2)).await;
+        let (first, _) = serve(Mode::Block(1)).await;
+        let (second, _) = serve(Mode::Block(1)).await;
+        let (_, provider) = provider(&[outlier, first, second], 0, 2);
+
+        assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_without_a_quorum() {
+        let (one, _) = serve(Mode::Block(1)).await;
+        let (two, _) = serve(Mode::Block(2)).await;
+        let (down, _) = serve(Mode::Html).await;
+        let (_, provider) = provider(&[one, two, down], 1, 2);
+
+        let err = provider.get_block_number().await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no 2 RPC endpoints agreed on an answer (2 answered)"
+        );
+    }
+
+    #[tokio::test]
+    async fn already_known_transactions_return_their_hash() {
+        let (node, _) = serve(Mode::AlreadyKnown).await;
+        let (_, provider) = provider(&[node], 0, 1);
+
+        let raw = Bytes::from(vec![0x02, 0xf8, 0x6c, 0x01]);
+        let pending = provider.send_raw_transaction(raw.clone()).await.unwrap();
+        assert_eq!(pending.tx_hash(), H256::from(keccak256(&raw)));
+
+        // Other methods still get the error.
+        let tx: TypedTransaction = TransactionRequest::new().into();
+        assert!(provider.estimate_gas(&tx, None).await.is_err());
+    }
+}
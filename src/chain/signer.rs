@@ -0,0 +1,151 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use ethers::signers::{LocalWallet, Signer, WalletError};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::transaction::eip712::Eip712;
+use ethers::types::{Address, Signature};
+
+use super::remote_signer::{RemoteSigner, RemoteSignerError};
+use crate::constants::{Constants, SignerConfig};
+
+/// Signs the transactions of one pool wallet, either in process or through a remote
+/// signer so the key never enters the API process.
+#[derive(Debug, Clone)]
+pub enum AppSigner {
+    Local(LocalWallet),
+    Remote(RemoteSigner),
+}
+
+#[derive(Debug)]
+pub enum SignerError {
+    Local(WalletError),
+    Remote(RemoteSignerError),
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignerError::Local(e) => write!(f, "{}", e),
+            SignerError::Remote(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+#[async_trait::async_trait]
+impl Signer for AppSigner {
+    type Error = SignerError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            AppSigner::Local(wallet) => wallet
+                .sign_message(message)
+                .await
+                .map_err(SignerError::Local),
+            AppSigner::Remote(remote) => remote
+                .sign_message(message)
+                .await
+                .map_err(SignerError::Remote),
+        }
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, Self::Error> {
+        match self {
+            AppSigner::Local(wallet) => wallet
+                .sign_transaction(tx)
+                .await
+                .map_err(SignerError::Local),
+            AppSigner::Remote(remote) => remote
+                .sign_transaction(tx)
+                .await
+                .map_err(SignerError::Remote),
+        }
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            AppSigner::Local(wallet) => wallet
+                .sign_typed_data(payload)
+                .await
+                .map_err(SignerError::Local),
+            AppSigner::Remote(remote) => remote
+                .sign_typed_data(payload)
+                .await
+                .map_err(SignerError::Remote),
+        }
+    }
+
+    fn address(&self) -> Address {
+        match self {
+            AppSigner::Local(wallet) => wallet.address(),
+            AppSigner::Remote(remote) => remote.address(),
+        }
+    }
+
+    fn chain_id(&self) -> u64 {
+        match self {
+            AppSigner::Local(wallet) => wallet.chain_id(),
+            AppSigner::Remote(remote) => remote.chain_id(),
+        }
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            AppSigner::Local(wallet) => AppSigner::Local(wallet.with_chain_id(chain_id)),
+            AppSigner::Remote(remote) => AppSigner::Remote(remote.with_chain_id(chain_id)),
+        }
+    }
+}
+
+/// Opens the signers of the pool as configured, in configuration order.
+pub async fn load_signers(config: &Constants) -> Result<Vec<AppSigner>> {
+    let Some(signer_config) = &config.signers else {
+        anyhow::bail!("read-only servers have no signers");
+    };
+    let signers = match signer_config {
+        SignerConfig::PrivateKeys(keys) => keys
+            .iter()
+            .map(|key| Ok(AppSigner::Local(key.parse::<LocalWallet>()?)))
+            .collect::<Result<Vec<_>>>()?,
+        SignerConfig::Keystores { paths, password } => paths
+            .iter()
+            .map(|path| {
+                let wallet = LocalWallet::decrypt_keystore(path, password)
+                    .with_context(|| format!("failed to decrypt keystore {}", path))?;
+                Ok(AppSigner::Local(wallet))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        SignerConfig::Remote { url, public_keys } => {
+            let http = reqwest::Client::new();
+            let public_keys = match public_keys {
+                Some(public_keys) => public_keys.clone(),
+                None => RemoteSigner::public_keys(&http, url)
+                    .await
+                    .with_context(|| format!("failed to list the keys of {}", url))?,
+            };
+            public_keys
+                .iter()
+                .map(|public_key| {
+                    Ok(AppSigner::Remote(RemoteSigner::new(
+                        http.clone(),
+                        url,
+                        public_key,
+                        config.nft_chain().chain_id,
+                    )?))
+                })
+                .collect::<Result<Vec<_>>>()?
+        }
+    };
+    Ok(signers
+        .into_iter()
+        .map(|signer| signer.with_chain_id(config.nft_chain().chain_id))
+        .collect())
+}
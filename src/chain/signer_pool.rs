@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use ethers::providers::Middleware;
+use ethers::types::Address;
+
+use super::chain::{get_ethers_client, EthersClient};
+use super::rpc::RpcProvider;
+use super::signer::load_signers;
+use crate::constants::{Constants, SignerSelection};
+
+/// One wallet of the pool with its own nonce manager.
+pub struct PoolSigner {
+    pub address: Address,
+    pub client: Arc<EthersClient>,
+    /// Mints sent from this wallet that are not settled yet.
+    pending: AtomicUsize,
+    /// Set by the transaction monitor while one of its transactions is stuck.
+    stuck: AtomicBool,
+}
+
+impl PoolSigner {
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    pub fn is_stuck(&self) -> bool {
+        self.stuck.load(Ordering::SeqCst)
+    }
+
+    pub fn set_stuck(&self, stuck: bool) {
+        self.stuck.store(stuck, Ordering::SeqCst);
+    }
+
+    /// Counts a transaction sent from this wallet until [`PoolSigner::finish`].
+    pub fn begin(&self) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn finish(&self) {
+        let _ = self
+            .pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+    }
+}
+
+/// The wallets allowed to mint. Each has its own nonce sequence, so a stuck
+/// transaction only holds up the mints of its wallet, and that wallet is skipped
+/// until it recovers.
+pub struct SignerPool {
+    signers: Vec<Arc<PoolSigner>>,
+    selection: SignerSelection,
+    next: AtomicUsize,
+}
+
+impl SignerPool {
+    /// Loads the wallets of `config`, sending through `provider` of the NFT chain.
+    pub async fn from_config(config: &Constants, provider: &RpcProvider) -> Result<Self> {
+        let mut signers = Vec::new();
+        for signer in load_signers(config).await? {
+            let client = get_ethers_client(provider, signer).await?;
+            signers.push(Arc::new(PoolSigner {
+                address: client.inner().address(),
+                client,
+                pending: AtomicUsize::new(0),
+                stuck: AtomicBool::new(false),
+            }));
+        }
+        anyhow::ensure!(!signers.is_empty(), "at least one signer is required");
+
+        Ok(SignerPool {
+            signers,
+            selection: config.signer_selection,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// The first configured wallet, used for reads and simulations.
+    pub fn primary(&self) -> &Arc<PoolSigner> {
+        &self.signers[0]
+    }
+
+    pub fn signers(&self) -> &[Arc<PoolSigner>] {
+        &self.signers
+    }
+
+    pub fn by_address(&self, address: Address) -> Option<&Arc<PoolSigner>> {
+        self.signers.iter().find(|signer| signer.address == address)
+    }
+
+    /// Picks the wallet for the next mint, or `None` while every wallet is stuck.
+    pub fn select(&self) -> Option<Arc<PoolSigner>> {
+        let available = self.signers.iter().filter(|signer| !signer.is_stuck());
+        match self.selection {
+            SignerSelection::LeastPending => available.min_by_key(|signer| signer.pending()),
+            SignerSelection::RoundRobin => {
+                let available: Vec<&Arc<PoolSigner>> = available.collect();
+                if available.is_empty() {
+                    return None;
+                }
+                let index = self.next.fetch_add(1, Ordering::SeqCst) % available.len();
+                Some(available[index])
+            }
+        }
+        .cloned()
+    }
+}
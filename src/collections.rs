@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use warp::{Filter, Rejection};
+
+use crate::chain::registry::{Chain, ChainRegistry};
+use crate::constants::{CollectionConfig, Constants};
+use crate::error::ServerError;
+use crate::state::{with_state, AppState};
+
+/// An NFT contract served by this deployment, with the chain it is deployed on.
+pub struct Collection {
+    pub config: CollectionConfig,
+    pub chain: Arc<Chain>,
+    /// Whether the signer pool sends on the collection's chain.
+    mintable: bool,
+}
+
+impl Collection {
+    pub fn slug(&self) -> &str {
+        &self.config.slug
+    }
+
+    /// Fails with 503 when the signers of this server cannot mint on the collection's chain.
+    pub fn ensure_mintable(&self) -> Result<(), ServerError> {
+        if self.mintable {
+            return Ok(());
+        }
+        Err(ServerError::Unavailable(format!(
+            "Collection {} is on chain {}, which this server does not mint on",
+            self.config.slug, self.config.chain
+        )))
+    }
+}
+
+/// Every configured collection by slug.
+pub struct CollectionRegistry {
+    collections: HashMap<String, Arc<Collection>>,
+    default: String,
+}
+
+impl CollectionRegistry {
+    pub fn from_config(config: &Constants, chains: &ChainRegistry) -> Result<Self> {
+        let mut collections = HashMap::new();
+        for (slug, collection_config) in &config.collections {
+            let chain = chains
+                .get(&collection_config.chain)
+                .with_context(|| format!("collection {} has no chain", slug))?;
+            collections.insert(
+                slug.clone(),
+                Arc::new(Collection {
+                    config: collection_config.clone(),
+                    chain: chain.clone(),
+                    mintable: collection_config.chain == config.nft_chain,
+                }),
+            );
+        }
+        Ok(CollectionRegistry {
+            collections,
+            default: config.default_collection.clone(),
+        })
+    }
+
+    pub fn get(&self, slug: &str) -> Option<&Arc<Collection>> {
+        self.collections.get(slug)
+    }
+
+    pub fn contains(&self, slug: &str) -> bool {
+        self.collections.contains_key(slug)
+    }
+
+    /// Collection of the routes without a `/collections/{slug}` prefix.
+    pub fn default(&self) -> &Arc<Collection> {
+        &self.collections[&self.default]
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &Arc<Collection>> {
+        self.collections.values()
+    }
+}
+
+/// Matches `/api/collections/{slug}` and extracts its collection, or matches `/api` and
+/// extracts the default collection. Routes continue with the rest of their path.
+pub fn with_collection(
+    state: Arc<AppState>,
+) -> impl Filter<Extract = (Arc<Collection>,), Error = Rejection> + Clone {
+    let named = warp::path!("api" / "collections" / String / ..)
+        .and(with_state(state.clone()))
+        .and_then(|slug: String, state: Arc<AppState>| async move {
+            state.collections.get(&slug).cloned().ok_or_else(|| {
+                warp::reject::custom(ServerError::NotFound(format!(
+                    "Collection {} not found",
+                    slug
+                )))
+            })
+        });
+    let default = warp::path("api")
+        .and(with_state(state))
+        .map(|state: Arc<AppState>| state.collections.default().clone());
+    named.or(default).unify()
+}
@@ -1,55 +1,621 @@
-use std::{env, sync::Arc};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 
-use warp::Filter;
+use ethers::signers::LocalWallet;
+use ethers::types::{Address, U256};
+use ethers::utils::{parse_ether, parse_units};
 
-pub struct Constants {
-    pub nft_address: String,
-    pub auction_address: String,
-    pub private_key: String,
-    pub chain_url: String,
+use crate::settings::{describe, ConfigError, Settings};
+
+/// What happens to a mint while the network fee is above `max_fee_per_gas`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeCapAction {
+    /// Reject new mints and fail queued ones.
+    Refuse,
+    /// Accept mints and hold the queue until fees drop.
+    Queue,
+}
+
+/// How the signer pool picks the wallet for a mint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignerSelection {
+    RoundRobin,
+    /// The wallet with the fewest unsettled mints.
+    LeastPending,
+}
+
+/// Where the keys of the signer pool come from. Every wallet needs the minter role on
+/// the contract.
+pub enum SignerConfig {
+    /// Plaintext keys from `PRIVATE_KEYS`, or `PRIVATE_KEY` for a single wallet.
+    PrivateKeys(Vec<String>),
+    /// Encrypted JSON keystores sharing one password.
+    Keystores {
+        paths: Vec<String>,
+        password: String,
+    },
+    /// A Web3Signer-compatible service holding the keys. All of its keys are used
+    /// unless `public_keys` narrows them down.
+    Remote {
+        url: String,
+        public_keys: Option<Vec<String>>,
+    },
+}
+
+impl SignerConfig {
+    /// The first of a remote signer, keystores and plaintext keys that is configured.
+    fn from_settings(settings: &mut Settings) -> Option<Self> {
+        if let Some(url) = settings.convert("signers.remote_url", "a URL", parse_url) {
+            return Some(SignerConfig::Remote {
+                url: url.trim_end_matches('/').to_string(),
+                public_keys: settings.list("signers.remote_public_keys"),
+            });
+        }
+        if let Some(paths) = settings.list("signers.keystore_paths") {
+            let password = settings.required("signers.keystore_password")?;
+            return Some(SignerConfig::Keystores { paths, password });
+        }
+        if let Some(keys) = settings.list("signers.private_keys") {
+            for (index, key) in keys.iter().enumerate() {
+                if key.parse::<LocalWallet>().is_err() {
+                    settings.problem(format!(
+                        "{} entry {} is not a private key",
+                        describe("signers.private_keys"),
+                        index + 1
+                    ));
+                }
+            }
+            return Some(SignerConfig::PrivateKeys(keys));
+        }
+        settings.problem(format!(
+            "No signer is configured; set {}, {} or {}",
+            describe("signers.remote_url"),
+            describe("signers.keystore_paths"),
+            describe("signers.private_keys")
+        ));
+        None
+    }
+}
+
+/// How fees and gas limits are chosen for the transactions the server sends.
+pub struct GasPolicy {
+    /// Upper bound for `maxFeePerGas`, in wei. No cap when unset.
+    pub max_fee_per_gas: Option<U256>,
+    /// Tip in wei. The node's estimate is used when unset.
+    pub max_priority_fee_per_gas: Option<U256>,
+    pub on_fee_above_cap: FeeCapAction,
+    /// Fee growth per `escalation_interval_secs` a stuck transaction has been pending.
+    pub escalation_coefficient: f64,
+    pub escalation_interval_secs: u64,
+    /// Minutes a transaction may stay pending before it is sped up and its wallet gets
+    /// no new mints.
+    pub stuck_after_minutes: u64,
+    /// Gas limit of mints. Estimated per transaction when unset.
+    pub mint_gas_limit: Option<U256>,
+    /// Gas limit of the self-transfers that cancel stuck transactions.
+    pub cancel_gas_limit: U256,
+}
+
+impl GasPolicy {
+    fn from_settings(settings: &mut Settings) -> Self {
+        GasPolicy {
+            max_fee_per_gas: settings.convert("gas.max_fee_gwei", "an amount in gwei", parse_gwei),
+            max_priority_fee_per_gas: settings.convert(
+                "gas.priority_fee_gwei",
+                "an amount in gwei",
+                parse_gwei,
+            ),
+            on_fee_above_cap: settings
+                .convert("gas.fee_cap_action", "refuse or queue", |v| match v {
+                    "refuse" => Some(FeeCapAction::Refuse),
+                    "queue" => Some(FeeCapAction::Queue),
+                    _ => None,
+                })
+                .unwrap_or(FeeCapAction::Queue),
+            escalation_coefficient: settings
+                .parse("gas.escalation_coefficient", "a number")
+                .unwrap_or(1.125),
+            escalation_interval_secs: settings
+                .parse("gas.escalation_interval_secs", "an integer")
+                .unwrap_or(60),
+            stuck_after_minutes: settings
+                .parse_positive("gas.stuck_after_minutes")
+                .unwrap_or(10),
+            mint_gas_limit: settings.convert("gas.limit_mint", "an integer", parse_integer),
+            cancel_gas_limit: settings
+                .convert("gas.limit_cancel", "an integer", parse_integer)
+                .unwrap_or_else(|| U256::from(21_000)),
+        }
+    }
+}
+
+fn parse_gwei(value: &str) -> Option<U256> {
+    parse_units(value, "gwei").ok().map(Into::into)
+}
+
+fn parse_integer(value: &str) -> Option<U256> {
+    U256::from_dec_str(value).ok()
+}
+
+fn parse_url(value: &str) -> Option<String> {
+    reqwest::Url::parse(value).ok().map(|_| value.to_string())
+}
+
+/// How requests to the RPC endpoints of a chain ride out failures.
+#[derive(Debug, Clone)]
+pub struct RpcPolicy {
+    /// Per request and endpoint.
+    pub timeout_secs: u64,
+    /// Rounds over all endpoints after the first one failed, with exponential backoff.
+    pub retries: u32,
+    /// Endpoints that must agree on critical reads (`ownerOf`, `auctions`). 1 asks only
+    /// the first healthy endpoint.
+    pub quorum: usize,
+}
+
+impl RpcPolicy {
+    fn from_settings(settings: &mut Settings, name: &str, endpoints: usize) -> Self {
+        let key = |setting: &str| format!("chains.{}.{}", name, setting);
+        let quorum = settings.parse_positive(&key("rpc_quorum")).unwrap_or(1);
+        if endpoints > 0 && quorum > endpoints {
+            settings.problem(format!(
+                "{} is {}, but {} lists only {} endpoints",
+                describe(&key("rpc_quorum")),
+                quorum,
+                describe(&key("rpc_urls")),
+                endpoints
+            ));
+        }
+        RpcPolicy {
+            timeout_secs: settings
+                .parse_positive(&key("rpc_timeout_secs"))
+                .unwrap_or(10),
+            retries: settings
+                .parse(&key("rpc_retries"), "an integer")
+                .unwrap_or(2),
+            quorum,
+        }
+    }
+}
+
+impl Default for RpcPolicy {
+    fn default() -> Self {
+        RpcPolicy {
+            timeout_secs: 10,
+            retries: 2,
+            quorum: 1,
+        }
+    }
+}
+
+/// A network the contracts are deployed on.
+#[derive(Debug, Clone)]
+pub struct ChainConfig {
+    pub name: String,
     pub chain_id: u64,
-    pub graph_url_nft: String,
-    pub graph_url_auction: String, // Add other typed environment variables here
-    pub etherscan_api_key: String,
-    pub mongo_atlas_username: String,
-    pub mongo_atlas_password: String,
+    /// JSON-RPC endpoints in order of preference.
+    pub rpc_urls: Vec<String>,
+    pub rpc_policy: RpcPolicy,
+    /// WebSocket endpoint for live event subscriptions. Events are not watched without it.
+    pub ws_url: Option<String>,
+    /// Blocks on top of a transaction, itself included, before it counts as final.
+    pub confirmations: usize,
+    /// Block explorer, used to link transactions.
+    pub explorer_url: Option<String>,
+    /// Subgraphs indexing the chain, by contract (`nft`, `auction`).
+    pub graph_urls: HashMap<String, String>,
+}
+
+impl ChainConfig {
+    fn from_settings(settings: &mut Settings, name: &str) -> Self {
+        let key = |setting: &str| format!("chains.{}.{}", name, setting);
+
+        let rpc_urls = settings.list(&key("rpc_urls")).unwrap_or_default();
+        if rpc_urls.is_empty() {
+            settings.problem(format!("{} is not set", describe(&key("rpc_urls"))));
+        }
+        for (index, url) in rpc_urls.iter().enumerate() {
+            if parse_url(url).is_none() {
+                settings.problem(format!(
+                    "{} entry {} is not a URL",
+                    describe(&key("rpc_urls")),
+                    index + 1
+                ));
+            }
+        }
+        let graph_urls = settings
+            .names(&key("graph_urls"))
+            .into_iter()
+            .filter_map(|contract| {
+                let url = settings.convert(
+                    &format!("chains.{}.graph_urls.{}", name, contract),
+                    "a URL",
+                    parse_url,
+                )?;
+                Some((contract, url))
+            })
+            .collect();
+        let rpc_policy = RpcPolicy::from_settings(settings, name, rpc_urls.len());
+
+        ChainConfig {
+            name: name.to_string(),
+            chain_id: settings
+                .parse_required(&key("chain_id"), "an integer")
+                .unwrap_or_default(),
+            rpc_urls,
+            rpc_policy,
+            ws_url: settings.convert(&key("ws_url"), "a URL", parse_url),
+            confirmations: settings.parse_positive(&key("confirmations")).unwrap_or(1),
+            explorer_url: settings
+                .convert(&key("explorer_url"), "a URL", parse_url)
+                .map(|url| url.trim_end_matches('/').to_string()),
+            graph_urls,
+        }
+    }
+
+    /// Older deployments describe their only chain with flat settings.
+    fn from_flat_settings(settings: &mut Settings) -> Self {
+        let mut graph_urls = HashMap::from([(
+            "auction".to_string(),
+            required_url(settings, "graph_url_auction"),
+        )]);
+        // Declared collections bring their own subgraphs.
+        if settings.names("collections").is_empty() || settings.optional("graph_url_nft").is_some()
+        {
+            graph_urls.insert("nft".to_string(), required_url(settings, "graph_url_nft"));
+        }
+        ChainConfig {
+            name: DEFAULT_CHAIN.to_string(),
+            chain_id: settings
+                .parse_required("chain_id", "an integer")
+                .unwrap_or_default(),
+            rpc_urls: vec![required_url(settings, "chain_url")],
+            rpc_policy: RpcPolicy::default(),
+            ws_url: settings.convert("chain_ws_url", "a URL", parse_url),
+            confirmations: 1,
+            explorer_url: None,
+            graph_urls,
+        }
+    }
+}
+
+/// Name of the chain built from the flat `chain_url`, `chain_id` and Graph settings.
+const DEFAULT_CHAIN: &str = "default";
+
+/// Parses the chain registry and returns it with the chains of the NFT and the
+/// auction contract.
+fn chains_from_settings(settings: &mut Settings) -> (HashMap<String, ChainConfig>, String, String) {
+    let names = settings.names("chains");
+    let chains: HashMap<String, ChainConfig> = if names.is_empty() {
+        let chain = ChainConfig::from_flat_settings(settings);
+        HashMap::from([(chain.name.clone(), chain)])
+    } else {
+        for flat in [
+            "chain_id",
+            "chain_url",
+            "graph_url_nft",
+            "graph_url_auction",
+        ] {
+            if settings.optional(flat).is_some() {
+                settings.problem(format!(
+                    "{} only applies without chains; set it on chains.<name> instead",
+                    describe(flat)
+                ));
+            }
+        }
+        names
+            .iter()
+            .map(|name| (name.clone(), ChainConfig::from_settings(settings, name)))
+            .collect()
+    };
+
+    let mut contract_chain = |key: &str| -> String {
+        let name = match settings.optional(key) {
+            Some(name) => name,
+            None if chains.len() == 1 => chains.keys().next().unwrap().clone(),
+            None => {
+                settings.problem(format!(
+                    "{} is not set and there is more than one chain",
+                    describe(key)
+                ));
+                return String::new();
+            }
+        };
+        if !chains.contains_key(&name) {
+            settings.problem(format!("{} names unknown chain {}", describe(key), name));
+        }
+        name
+    };
+    let nft_chain = contract_chain("nft_chain");
+    let auction_chain = contract_chain("auction_chain");
+    if let Some(chain) = chains.get(&auction_chain) {
+        chain_graph_url(settings, chain, "auction");
+    }
+    (chains, nft_chain, auction_chain)
+}
+
+/// The subgraph of `contract` on `chain`, reported as a problem when it is missing.
+fn chain_graph_url(settings: &mut Settings, chain: &ChainConfig, contract: &str) -> String {
+    match chain.graph_urls.get(contract) {
+        Some(url) => url.clone(),
+        None => {
+            settings.problem(format!(
+                "{} is not set",
+                describe(&format!("chains.{}.graph_urls.{}", chain.name, contract))
+            ));
+            String::new()
+        }
+    }
+}
+
+/// An NFT contract served by this deployment under its own routes and storage.
+#[derive(Clone)]
+pub struct CollectionConfig {
+    pub slug: String,
+    /// Unset on read-only servers, which never send transactions to the contract.
+    pub nft_address: Option<Address>,
+    /// Chain the contract is deployed on.
+    pub chain: String,
+    /// Subgraph of the contract.
+    pub graph_url: String,
+    /// Mongo collection holding the token metadata.
+    pub mongo_collection: String,
+}
+
+/// Slug of the collection built from the flat `nft_address` and `mongo.nft_collection`.
+const DEFAULT_COLLECTION: &str = "snapit";
+
+/// Parses the collections and returns them with the slug of the one the routes without
+/// a collection serve.
+fn collections_from_settings(
+    settings: &mut Settings,
+    read_only: bool,
+    chains: &HashMap<String, ChainConfig>,
+    nft_chain: &str,
+) -> (HashMap<String, CollectionConfig>, String) {
+    let nft_address = |settings: &mut Settings, key: &str| {
+        if read_only {
+            settings.parse(key, "an address")
+        } else {
+            settings.parse_required(key, "an address")
+        }
+    };
+    let graph_url = |settings: &mut Settings, chain: &str, key: Option<&str>| {
+        if let Some(url) = key.and_then(|key| settings.convert(key, "a URL", parse_url)) {
+            return url;
+        }
+        // An unknown chain is reported on its own.
+        let Some(chain) = chains.get(chain) else {
+            return String::new();
+        };
+        match (key, chain.graph_urls.get("nft")) {
+            (_, Some(url)) => url.clone(),
+            (Some(key), None) => {
+                settings.problem(format!(
+                    "{} is not set and chain {} has no graph_urls.nft",
+                    describe(key),
+                    chain.name
+                ));
+                String::new()
+            }
+            (None, None) => chain_graph_url(settings, chain, "nft"),
+        }
+    };
+
+    let slugs = settings.names("collections");
+    let collections: HashMap<String, CollectionConfig> = if slugs.is_empty() {
+        let collection = CollectionConfig {
+            slug: DEFAULT_COLLECTION.to_string(),
+            nft_address: nft_address(settings, "nft_address"),
+            chain: nft_chain.to_string(),
+            graph_url: graph_url(settings, nft_chain, None),
+            mongo_collection: settings
+                .required("mongo.nft_collection")
+                .unwrap_or_default(),
+        };
+        HashMap::from([(collection.slug.clone(), collection)])
+    } else {
+        if settings.optional("nft_address").is_some() {
+            settings.problem(format!(
+                "{} only applies without collections; set it on collections.<slug> instead",
+                describe("nft_address")
+            ));
+        }
+        slugs
+            .iter()
+            .map(|slug| {
+                let key = |setting: &str| format!("collections.{}.{}", slug, setting);
+                let chain = settings
+                    .optional(&key("chain"))
+                    .unwrap_or_else(|| nft_chain.to_string());
+                if !chains.contains_key(&chain) {
+                    settings.problem(format!(
+                        "{} names unknown chain {}",
+                        describe(&key("chain")),
+                        chain
+                    ));
+                }
+                let collection = CollectionConfig {
+                    slug: slug.clone(),
+                    nft_address: nft_address(settings, &key("nft_address")),
+                    graph_url: graph_url(settings, &chain, Some(&key("graph_url"))),
+                    chain,
+                    mongo_collection: settings
+                        .required(&key("mongo_collection"))
+                        .unwrap_or_default(),
+                };
+                (slug.clone(), collection)
+            })
+            .collect()
+    };
+
+    let default_collection = match settings.optional("default_collection") {
+        Some(slug) => {
+            if !collections.contains_key(&slug) {
+                settings.problem(format!(
+                    "{} names unknown collection {}",
+                    describe("default_collection"),
+                    slug
+                ));
+            }
+            slug
+        }
+        None if collections.len() == 1 => collections.keys().next().unwrap().clone(),
+        None => {
+            settings.problem(format!(
+                "{} is not set and there is more than one collection",
+                describe("default_collection")
+            ));
+            String::new()
+        }
+    };
+    (collections, default_collection)
+}
+
+/// Where the API keeps its data.
+pub struct MongoConfig {
+    /// Connection string, built from the Atlas host and credentials unless set as is.
+    pub uri: String,
+}
+
+impl MongoConfig {
+    fn from_settings(settings: &mut Settings) -> Self {
+        let uri = match settings.optional("mongo.uri") {
+            Some(uri) => uri,
+            None => {
+                let host = settings.required("mongo.host").unwrap_or_default();
+                let username = settings.required("mongo.username").unwrap_or_default();
+                let password = settings.required("mongo.password").unwrap_or_default();
+                let mut uri = format!(
+                    "mongodb+srv://{}:{}@{}/?retryWrites=true&w=majority",
+                    username, password, host
+                );
+                if let Some(app_name) = settings.optional("mongo.app_name") {
+                    uri.push_str(&format!("&appName={}", app_name));
+                }
+                uri
+            }
+        };
+        MongoConfig { uri }
+    }
+}
+
+pub struct Constants {
+    /// Where the HTTP server listens.
+    pub bind_address: SocketAddr,
+    /// Serve reads only: no signer is loaded, and the mint and admin routes answer
+    /// 503. Set with `READ_ONLY=true`, e.g. on read replicas.
+    pub read_only: bool,
+    pub auction_address: Address,
+    /// Unset on read-only servers.
+    pub signers: Option<SignerConfig>,
+    pub signer_selection: SignerSelection,
+    /// Networks by name. Every contract is bound to one of them.
+    pub chains: HashMap<String, ChainConfig>,
+    /// Chain the signers send mints on and SIWE sessions are issued for. Collections
+    /// on other chains are served read-only.
+    pub nft_chain: String,
+    pub auction_chain: String,
+    /// NFT contracts by slug.
+    pub collections: HashMap<String, CollectionConfig>,
+    /// Collection served by the routes without a `/collections/{slug}` prefix.
+    pub default_collection: String,
+    pub mongo: MongoConfig,
     pub alchemy_api_key: String,
     pub jwt_secret: String,
+    /// Off unless enabled, so a deployment never accepts the public sandbox key by
+    /// accident.
+    pub sandbox_api_key_enabled: bool,
+    /// Domain SIWE messages must be issued for. Wallet sign-in is disabled when unset.
+    pub siwe_domain: Option<String>,
+    pub gas_policy: GasPolicy,
+    /// Signer balance in wei under which `low_balance_webhook_url` is called.
+    pub low_balance_threshold: Option<U256>,
+    pub low_balance_webhook_url: Option<String>,
 }
 
 impl Constants {
-    pub fn new() -> Self {
+    /// Reads the configuration as described in [`Settings`], after loading `.env`.
+    /// Fails with every missing or malformed setting at once.
+    pub fn new() -> Result<Self, ConfigError> {
         dotenv::dotenv().ok(); // Load the .env file
+        let mut settings = Settings::load();
+
+        let read_only = settings
+            .parse("read_only", "true or false")
+            .unwrap_or(false);
+        let (chains, nft_chain, auction_chain) = chains_from_settings(&mut settings);
+        let (collections, default_collection) =
+            collections_from_settings(&mut settings, read_only, &chains, &nft_chain);
+        let signers = if read_only {
+            None
+        } else {
+            SignerConfig::from_settings(&mut settings)
+        };
+
+        let config = Constants {
+            bind_address: settings
+                .parse("bind_address", "a host:port socket address")
+                .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 3030))),
+            read_only,
+            auction_address: settings
+                .parse_required("auction_address", "an address")
+                .unwrap_or_default(),
+            signers,
+            signer_selection: settings
+                .convert(
+                    "signers.selection",
+                    "round-robin or least-pending",
+                    |v| match v {
+                        "round-robin" => Some(SignerSelection::RoundRobin),
+                        "least-pending" => Some(SignerSelection::LeastPending),
+                        _ => None,
+                    },
+                )
+                .unwrap_or(SignerSelection::LeastPending),
+            chains,
+            nft_chain,
+            auction_chain,
+            collections,
+            default_collection,
+            mongo: MongoConfig::from_settings(&mut settings),
+            alchemy_api_key: settings.required("alchemy_api_key").unwrap_or_default(),
+            jwt_secret: settings.required("jwt_secret").unwrap_or_default(),
+            sandbox_api_key_enabled: settings
+                .parse("sandbox_api_key_enabled", "true or false")
+                .unwrap_or(false),
+            siwe_domain: settings.optional("siwe_domain"),
+            gas_policy: GasPolicy::from_settings(&mut settings),
+            low_balance_threshold: settings.convert(
+                "low_balance.threshold_eth",
+                "an amount in ETH",
+                |v| parse_ether(v).ok(),
+            ),
+            low_balance_webhook_url: settings.convert(
+                "low_balance.webhook_url",
+                "a URL",
+                parse_url,
+            ),
+        };
+        settings.finish()?;
+        Ok(config)
+    }
+
+    pub fn nft_chain(&self) -> &ChainConfig {
+        &self.chains[&self.nft_chain]
+    }
+
+    pub fn auction_chain(&self) -> &ChainConfig {
+        &self.chains[&self.auction_chain]
+    }
+
+    pub fn graph_url_auction(&self) -> &str {
+        &self.auction_chain().graph_urls["auction"]
+    }
+}
 
-        Constants {
-            nft_address: env::var("ETH_SEPOLIA_NFT_ADDRESS").expect("NFT_ADDRESS must be set"),
-            auction_address: env::var("BSC_TEST_AUCTION_ADDRESS")
-                .expect("AUCTION_ADDRESS must be set"),
-            private_key: env::var("PRIVATE_KEY").expect("PRIVATE_KEY must be set"),
-            chain_url: env::var("ETH_SEPOLIA_CHAIN_URL")
-                .expect("ETH_SEPOLIA_CHAIN_URL must be set"),
-            chain_id: env::var("ETH_SEPOLIA_CHAIN_ID")
-                .expect("ETH_SEPOLIA_CHAIN_ID must be set")
-                .parse()
-                .expect("ETH_SEPOLIA_CHAIN_ID should be an integer"),
-            graph_url_nft: env::var("GRAPH_URL_NFT").expect("GRAPH_URL_NFT must be set"),
-            graph_url_auction: env::var("GRAPH_URL_AUCTION")
-                .expect("GRAPH_URL_AUCTION must be set"),
-            etherscan_api_key: env::var("ETHERSCAN_API_KEY")
-                .expect("ETHERSCAN_API_KEY must be set"),
-            mongo_atlas_username: env::var("MONGO_ATLAS_USERNAME")
-                .expect("MONGO_ATLAS_USERNAME must be set"),
-            mongo_atlas_password: env::var("MONGO_ATLAS_PASSWORD")
-                .expect("MONGO_ATLAS_PASSWORD must be set"),
-            alchemy_api_key: env::var("ALCHEMY_API_KEY").expect("ALCHEMY_API_KEY must be set"),
-            jwt_secret: env::var("JWT_SECRET").expect("JWT_SECRET must be set"),
-            // Initialize other environment variables here
-        }
-    }
-}
-
-pub fn with_config(
-    config: Arc<Constants>,
-) -> impl Filter<Extract = (Arc<Constants>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || config.clone())
+fn required_url(settings: &mut Settings, key: &str) -> String {
+    settings
+        .convert_required(key, "a URL", parse_url)
+        .unwrap_or_default()
 }
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, DateTime};
+use mongodb::options::{FindOptions, IndexOptions};
+use mongodb::{Client, IndexModel};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::Scope;
+
+const API_KEYS_COLLECTION_NAME: &str = "api-keys";
+
+/// An API client as persisted in Mongo. Only the keccak256 hash of the secret is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyDocument {
+    pub key_id: String,
+    pub name: String,
+    pub key_hash: String,
+    pub scopes: Vec<Scope>,
+    /// Slugs of the collections the key is limited to. Keys without it access all.
+    #[serde(default)]
+    pub collections: Option<Vec<String>>,
+    /// Subject of the admin that created the key, or `cli` for bootstrapped keys.
+    pub created_by: String,
+    pub created_at: DateTime,
+    pub expires_at: Option<DateTime>,
+    pub last_used_at: Option<DateTime>,
+    pub revoked_at: Option<DateTime>,
+}
+
+fn api_keys_collection(client: &Client) -> mongodb::Collection<ApiKeyDocument> {
+    client
+        .database("snapit")
+        .collection::<ApiKeyDocument>(API_KEYS_COLLECTION_NAME)
+}
+
+pub async fn create_api_key_indexes(client: Arc<Client>) -> Result<()> {
+    let unique = IndexOptions::builder().unique(true).build();
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "key_id": 1 })
+            .options(unique.clone())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "key_hash": 1 })
+            .options(unique)
+            .build(),
+    ];
+    api_keys_collection(&client)
+        .create_indexes(indexes, None)
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_api_key(client: Arc<Client>, api_key: &ApiKeyDocument) -> Result<()> {
+    api_keys_collection(&client)
+        .insert_one(api_key, None)
+        .await?;
+    Ok(())
+}
+
+pub async fn find_api_key_by_hash(
+    client: Arc<Client>,
+    key_hash: &str,
+) -> Result<Option<ApiKeyDocument>> {
+    let result = api_keys_collection(&client)
+        .find_one(doc! { "key_hash": key_hash }, None)
+        .await?;
+    Ok(result)
+}
+
+pub async fn list_api_keys(client: Arc<Client>) -> Result<Vec<ApiKeyDocument>> {
+    let find_options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    let cursor = api_keys_collection(&client)
+        .find(doc! {}, find_options)
+        .await?;
+    Ok(cursor.try_collect().await?)
+}
+
+/// Marks the key as revoked. Returns the updated key, or `None` if it does not exist.
+pub async fn revoke_api_key(client: Arc<Client>, key_id: &str) -> Result<Option<ApiKeyDocument>> {
+    let collection = api_keys_collection(&client);
+    collection
+        .update_one(
+            doc! { "key_id": key_id, "revoked_at": bson::Bson::Null },
+            doc! { "$set": { "revoked_at": DateTime::now() } },
+            None,
+        )
+        .await?;
+    Ok(collection.find_one(doc! { "key_id": key_id }, None).await?)
+}
+
+pub async fn touch_api_key(client: Arc<Client>, key_id: &str) -> Result<()> {
+    api_keys_collection(&client)
+        .update_one(
+            doc! { "key_id": key_id },
+            doc! { "$set": { "last_used_at": DateTime::now() } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use mongodb::bson::{doc, DateTime};
+use mongodb::options::IndexOptions;
+use mongodb::{Client, IndexModel};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::db::mongo::is_duplicate_key_error;
+
+const IDEMPOTENCY_KEYS_COLLECTION_NAME: &str = "idempotency-keys";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    /// Keys are scoped to the caller, so two clients may use the same key.
+    pub subject: String,
+    pub key: String,
+    /// Hash of the route and request body the key was first used with.
+    pub fingerprint: String,
+    /// Unset while the first request is still running.
+    pub status_code: Option<u16>,
+    pub response: Option<Value>,
+    /// When the request holding the key started.
+    pub reserved_at: DateTime,
+    pub expires_at: DateTime,
+}
+
+fn idempotency_keys_collection(client: &Client) -> mongodb::Collection<IdempotencyRecord> {
+    client
+        .database("snapit")
+        .collection::<IdempotencyRecord>(IDEMPOTENCY_KEYS_COLLECTION_NAME)
+}
+
+pub async fn create_idempotency_key_indexes(client: Arc<Client>) -> Result<()> {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "subject": 1, "key": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        // Let Mongo delete keys once they expire.
+        IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Duration::from_secs(0))
+                    .build(),
+            )
+            .build(),
+    ];
+    idempotency_keys_collection(&client)
+        .create_indexes(indexes, None)
+        .await?;
+    Ok(())
+}
+
+/// Claims `key` for a new request. Returns the existing record instead if the key
+/// was already used. A key still in progress since before `stale_before` belongs to a
+/// request that died before finishing, and is claimed again.
+pub async fn reserve_idempotency_key(
+    client: Arc<Client>,
+    subject: &str,
+    key: &str,
+    fingerprint: &str,
+    stale_before: DateTime,
+    expires_at: DateTime,
+) -> Result<Option<IdempotencyRecord>> {
+    let record = IdempotencyRecord {
+        subject: subject.to_string(),
+        key: key.to_string(),
+        fingerprint: fingerprint.to_string(),
+        status_code: None,
+        response: None,
+        reserved_at: DateTime::now(),
+        expires_at,
+    };
+    match idempotency_keys_collection(&client)
+        .insert_one(record, None)
+        .await
+        .map_err(anyhow::Error::new)
+    {
+        Ok(_) => Ok(None),
+        Err(e) if is_duplicate_key_error(&e) => {
+            let reclaimed = idempotency_keys_collection(&client)
+                .find_one_and_update(
+                    doc! {
+                        "subject": subject,
+                        "key": key,
+                        "status_code": null,
+                        "reserved_at": { "$lt": stale_before },
+                    },
+                    doc! { "$set": {
+                        "fingerprint": fingerprint,
+                        "reserved_at": DateTime::now(),
+                        "expires_at": expires_at,
+                    } },
+                    None,
+                )
+                .await?;
+            if reclaimed.is_some() {
+                return Ok(None);
+            }
+            Ok(idempotency_keys_collection(&client)
+                .find_one(doc! { "subject": subject, "key": key }, None)
+                .await?)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn complete_idempotency_key(
+    client: Arc<Client>,
+    subject: &str,
+    key: &str,
+    status_code: u16,
+    response: &Value,
+) -> Result<()> {
+    idempotency_keys_collection(&client)
+        .update_one(
+            doc! { "subject": subject, "key": key },
+            doc! { "$set": {
+                "status_code": status_code as i32,
+                "response": mongodb::bson::to_bson(response)?,
+            } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Frees a key whose request failed so the client can retry it.
+pub async fn release_idempotency_key(client: Arc<Client>, subject: &str, key: &str) -> Result<()> {
+    idempotency_keys_collection(&client)
+        .delete_one(doc! { "subject": subject, "key": key }, None)
+        .await?;
+    Ok(())
+}
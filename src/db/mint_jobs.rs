@@ -0,0 +1,387 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, oid::ObjectId, DateTime};
+use mongodb::options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument};
+use mongodb::{Client, IndexModel};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::chain::chain::TransactionReceiptSchema;
+use crate::chain::mint::MintData;
+use crate::db::mongo::Metadata;
+
+const MINT_JOBS_COLLECTION_NAME: &str = "mint-jobs";
+
+/// How long a claim holds a job before another instance may take it over. Renewed
+/// right before the job's transaction is sent.
+const LEASE_DURATION: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MintJobStatus {
+    /// Waiting for the worker.
+    Queued,
+    /// Claimed by a worker, transaction not sent yet.
+    Processing,
+    /// Transaction sent, waiting for the receipt.
+    Submitted,
+    Confirmed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MintJob {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub status: MintJobStatus,
+    /// Slug of the NFT collection to mint in.
+    pub collection: String,
+    pub owner_address: String,
+    pub token_id: u64,
+    pub metadata: Metadata,
+    /// Payload for the `mint(address,uint256,bytes)` overload.
+    #[serde(default)]
+    pub data: Option<MintData>,
+    /// Subject of the caller that requested the mint.
+    pub requested_by: String,
+    /// Address of the pool wallet that sent the mint. Set when queued for batch jobs,
+    /// which all go through one wallet.
+    #[serde(default)]
+    pub signer: Option<String>,
+    /// Shared by the jobs of one batch request, which are sent in the order of its items.
+    #[serde(default)]
+    pub batch_id: Option<ObjectId>,
+    /// Instance whose worker claimed the job.
+    #[serde(default)]
+    pub locked_by: Option<String>,
+    /// End of the claim; a job still `processing` after it was abandoned.
+    #[serde(default)]
+    pub locked_until: Option<DateTime>,
+    pub tx_hash: Option<String>,
+    pub receipt: Option<TransactionReceiptSchema>,
+    pub error_code: Option<String>,
+    pub error: Option<String>,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl MintJob {
+    pub fn new(
+        collection: String,
+        owner_address: String,
+        token_id: u64,
+        metadata: Metadata,
+        data: Option<MintData>,
+        requested_by: String,
+    ) -> Self {
+        let now = DateTime::now();
+        MintJob {
+            id: ObjectId::new(),
+            status: MintJobStatus::Queued,
+            collection,
+            owner_address,
+            token_id,
+            metadata,
+            data,
+            requested_by,
+            signer: None,
+            batch_id: None,
+            locked_by: None,
+            locked_until: None,
+            tx_hash: None,
+            receipt: None,
+            error_code: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+fn mint_jobs_collection(client: &Client) -> mongodb::Collection<MintJob> {
+    client
+        .database("snapit")
+        .collection::<MintJob>(MINT_JOBS_COLLECTION_NAME)
+}
+
+pub async fn create_mint_job_indexes(client: Arc<Client>) -> Result<()> {
+    let index = IndexModel::builder()
+        .keys(doc! { "status": 1, "created_at": 1 })
+        .options(IndexOptions::builder().build())
+        .build();
+    mint_jobs_collection(&client)
+        .create_index(index, None)
+        .await?;
+    Ok(())
+}
+
+/// Assigns the jobs queued before collections existed to `collection`.
+pub async fn assign_legacy_mint_jobs(client: Arc<Client>, collection: &str) -> Result<()> {
+    client
+        .database("snapit")
+        .collection::<bson::Document>(MINT_JOBS_COLLECTION_NAME)
+        .update_many(
+            doc! { "collection": { "$exists": false } },
+            doc! { "$set": { "collection": collection } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_mint_job(client: Arc<Client>, job: &MintJob) -> Result<()> {
+    mint_jobs_collection(&client).insert_one(job, None).await?;
+    Ok(())
+}
+
+pub async fn insert_mint_jobs(client: Arc<Client>, jobs: &[MintJob]) -> Result<()> {
+    mint_jobs_collection(&client)
+        .insert_many(jobs, None)
+        .await?;
+    Ok(())
+}
+
+pub async fn find_mint_job(client: Arc<Client>, id: ObjectId) -> Result<Option<MintJob>> {
+    Ok(mint_jobs_collection(&client)
+        .find_one(doc! { "_id": id }, None)
+        .await?)
+}
+
+pub async fn find_mint_jobs_by_status(
+    client: Arc<Client>,
+    status: MintJobStatus,
+) -> Result<Vec<MintJob>> {
+    let cursor = mint_jobs_collection(&client)
+        .find(doc! { "status": bson::to_bson(&status)? }, None)
+        .await?;
+    Ok(cursor.try_collect().await?)
+}
+
+/// Jobs that may still send or confirm a transaction.
+const ACTIVE_STATUSES: [MintJobStatus; 3] = [
+    MintJobStatus::Queued,
+    MintJobStatus::Processing,
+    MintJobStatus::Submitted,
+];
+
+/// True while a job for `token_id` of `collection` may still send or confirm a
+/// transaction.
+pub async fn has_active_mint_job(
+    client: Arc<Client>,
+    collection: &str,
+    token_id: u64,
+) -> Result<bool> {
+    let count = mint_jobs_collection(&client)
+        .count_documents(
+            doc! {
+                "collection": collection,
+                "token_id": token_id as i64,
+                "status": { "$in": bson::to_bson(&ACTIVE_STATUSES)? },
+            },
+            None,
+        )
+        .await?;
+    Ok(count > 0)
+}
+
+/// Jobs whose transaction has not been mined yet, across collections.
+pub async fn count_active_mint_jobs(client: Arc<Client>) -> Result<u64> {
+    Ok(mint_jobs_collection(&client)
+        .count_documents(
+            doc! { "status": { "$in": bson::to_bson(&ACTIVE_STATUSES)? } },
+            None,
+        )
+        .await?)
+}
+
+fn lease_end() -> DateTime {
+    DateTime::from_system_time(SystemTime::now() + LEASE_DURATION)
+}
+
+/// Atomically moves the oldest queued job to `processing` under a lease held by
+/// `instance` and returns it. The rest of `batch` goes first, so no other job is sent
+/// between the jobs of a batch.
+pub async fn claim_next_mint_job(
+    client: Arc<Client>,
+    instance: &str,
+    batch: Option<ObjectId>,
+) -> Result<Option<MintJob>> {
+    if let Some(batch) = batch {
+        if let Some(job) = claim_mint_job(&client, instance, doc! { "batch_id": batch }).await? {
+            return Ok(Some(job));
+        }
+    }
+    claim_mint_job(&client, instance, doc! {}).await
+}
+
+async fn claim_mint_job(
+    client: &Client,
+    instance: &str,
+    mut filter: bson::Document,
+) -> Result<Option<MintJob>> {
+    filter.insert("status", bson::to_bson(&MintJobStatus::Queued)?);
+    // Jobs of a batch share their creation time closely; ids keep them in item order.
+    let options = FindOneAndUpdateOptions::builder()
+        .sort(doc! { "created_at": 1, "_id": 1 })
+        .return_document(ReturnDocument::After)
+        .build();
+    Ok(mint_jobs_collection(client)
+        .find_one_and_update(
+            filter,
+            doc! { "$set": {
+                "status": bson::to_bson(&MintJobStatus::Processing)?,
+                "locked_by": instance,
+                "locked_until": lease_end(),
+                "updated_at": DateTime::now(),
+            } },
+            options,
+        )
+        .await?)
+}
+
+/// Extends the lease of `instance` on a processing job. False if the lease was lost to
+/// another instance, which then owns the job.
+pub async fn renew_mint_job_lease(
+    client: Arc<Client>,
+    id: ObjectId,
+    instance: &str,
+) -> Result<bool> {
+    let result = mint_jobs_collection(&client)
+        .update_one(
+            doc! {
+                "_id": id,
+                "status": bson::to_bson(&MintJobStatus::Processing)?,
+                "locked_by": instance,
+            },
+            doc! { "$set": { "locked_until": lease_end(), "updated_at": DateTime::now() } },
+            None,
+        )
+        .await?;
+    Ok(result.matched_count > 0)
+}
+
+pub async fn mark_mint_job_submitted(
+    client: Arc<Client>,
+    id: ObjectId,
+    signer: &str,
+    tx_hash: &str,
+) -> Result<()> {
+    mint_jobs_collection(&client)
+        .update_one(
+            doc! { "_id": id },
+            doc! { "$set": {
+                "status": bson::to_bson(&MintJobStatus::Submitted)?,
+                "signer": signer,
+                "tx_hash": tx_hash,
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Puts a job claimed by `instance` back in the queue without a retry penalty.
+pub async fn requeue_mint_job(client: Arc<Client>, id: ObjectId, instance: &str) -> Result<()> {
+    mint_jobs_collection(&client)
+        .update_one(
+            doc! {
+                "_id": id,
+                "status": bson::to_bson(&MintJobStatus::Processing)?,
+                "locked_by": instance,
+            },
+            doc! { "$set": {
+                "status": bson::to_bson(&MintJobStatus::Queued)?,
+                "locked_by": null,
+                "locked_until": null,
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Points a submitted job at the transaction that replaced its original one.
+pub async fn update_mint_job_tx_hash(
+    client: Arc<Client>,
+    id: ObjectId,
+    tx_hash: &str,
+) -> Result<()> {
+    mint_jobs_collection(&client)
+        .update_one(
+            doc! { "_id": id },
+            doc! { "$set": { "tx_hash": tx_hash, "updated_at": DateTime::now() } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// `receipt` is `None` when the mint was found on chain without its transaction.
+pub async fn mark_mint_job_confirmed(
+    client: Arc<Client>,
+    id: ObjectId,
+    receipt: Option<&TransactionReceiptSchema>,
+) -> Result<()> {
+    mint_jobs_collection(&client)
+        .update_one(
+            doc! { "_id": id },
+            doc! { "$set": {
+                "status": bson::to_bson(&MintJobStatus::Confirmed)?,
+                "receipt": bson::to_bson(&receipt)?,
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn mark_mint_job_failed(
+    client: Arc<Client>,
+    id: ObjectId,
+    error_code: &str,
+    error: &str,
+    receipt: Option<&TransactionReceiptSchema>,
+) -> Result<()> {
+    mint_jobs_collection(&client)
+        .update_one(
+            doc! { "_id": id },
+            doc! { "$set": {
+                "status": bson::to_bson(&MintJobStatus::Failed)?,
+                "error_code": error_code,
+                "error": error,
+                "receipt": bson::to_bson(&receipt)?,
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Puts jobs that were claimed but never submitted back in the queue once their lease
+/// ran out, so jobs another instance is working on are left alone.
+pub async fn requeue_expired_mint_jobs(client: Arc<Client>) -> Result<u64> {
+    let result = mint_jobs_collection(&client)
+        .update_many(
+            doc! {
+                "status": bson::to_bson(&MintJobStatus::Processing)?,
+                // Also matches jobs claimed before leases, which have none.
+                "locked_until": { "$not": { "$gte": DateTime::now() } },
+            },
+            doc! { "$set": {
+                "status": bson::to_bson(&MintJobStatus::Queued)?,
+                "locked_by": null,
+                "locked_until": null,
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await?;
+    Ok(result.modified_count)
+}
@@ -1 +1,7 @@
-pub mod mongo;
\ No newline at end of file
+pub mod api_keys;
+pub mod idempotency_keys;
+pub mod mint_jobs;
+pub mod mongo;
+pub mod siwe_nonces;
+pub mod token_ids;
+pub mod transactions;
@@ -1,23 +1,23 @@
 use anyhow::Result;
 use futures::TryStreamExt;
-use mongodb::bson::{self, doc};
-use mongodb::options::{FindOneOptions, ServerApi, ServerApiVersion};
-use mongodb::{options::ClientOptions, Client};
+use mongodb::bson::{self, doc, oid::ObjectId};
+use mongodb::error::{BulkWriteError, BulkWriteFailure, ErrorKind, WriteFailure};
+use mongodb::options::{
+    FindOneOptions, IndexOptions, InsertManyOptions, ServerApi, ServerApiVersion, UpdateOptions,
+};
+use mongodb::{options::ClientOptions, Client, IndexModel};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use utoipa::ToSchema;
-use warp::Filter;
 
 use crate::constants::Constants;
 
-const COLLECTION_NAME: &str = "snapit-nft-testnet";
-const SETTINGS_COLLECTION_NAME: &str = "settings";
+pub const SETTINGS_COLLECTION_NAME: &str = "settings";
 
 pub async fn init_db(config: Arc<Constants>) -> Result<Client> {
-    let mongo_uri = format!("mongodb+srv://{}:{}@test-snapit-api.zowevot.mongodb.net/?retryWrites=true&w=majority&appName=test-snapit-api", config.mongo_atlas_username, config.mongo_atlas_password);
-
-    let mut client_options = ClientOptions::parse(mongo_uri).await?;
+    let mut client_options = ClientOptions::parse(&config.mongo.uri).await?;
 
     // Set the server_api field of the client_options object to set the version of the Stable API on the client
     let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
@@ -34,50 +34,210 @@ pub async fn init_db(config: Arc<Constants>) -> Result<Client> {
     Ok(client)
 }
 
-pub fn with_mongo_client(
-    client: Arc<Client>,
-) -> impl Filter<Extract = (Arc<Client>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || client.clone())
+/// MongoDB's code for a unique index violation.
+pub const DUPLICATE_KEY_CODE: i32 = 11000;
+
+/// Returns true when `err` is a MongoDB unique index violation (E11000).
+pub fn is_duplicate_key_error(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<mongodb::error::Error>() {
+        Some(e) => match e.kind.as_ref() {
+            ErrorKind::Write(WriteFailure::WriteError(write_error)) => {
+                write_error.code == DUPLICATE_KEY_CODE
+            }
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+/// One document per token id, so a second mint of the same id cannot write metadata.
+pub async fn create_nft_indexes(client: Arc<Client>, collection: &str) -> Result<()> {
+    let index = IndexModel::builder()
+        .keys(doc! { "token_id": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    client
+        .database("snapit")
+        .collection::<bson::Document>(collection)
+        .create_index(index, None)
+        .await?;
+    Ok(())
 }
 
-pub async fn add_nft(client: Arc<Client>, token: AddNFTInput) -> Result<()> {
-    let collection = client.database("snapit").collection(COLLECTION_NAME);
+/// Writes the metadata of a token that is about to be minted. It stays hidden from
+/// reads until `mark_nft_minted` runs. Safe to repeat for the same job; fails with a
+/// duplicate key error if the token already has a document from anywhere else.
+pub async fn add_pending_nft(
+    client: Arc<Client>,
+    collection: &str,
+    token: AddNFTInput,
+    mint_job_id: ObjectId,
+) -> Result<()> {
+    let collection = client
+        .database("snapit")
+        .collection::<bson::Document>(collection);
 
-    let metadata_bson = bson::to_bson(&token.metadata).map_err(|e| anyhow::Error::new(e))?; // Convert bson error to anyhow error
+    let metadata_bson = bson::to_bson(&token.metadata).map_err(anyhow::Error::new)?; // Convert bson error to anyhow error
 
     let token_id_str = token.token_id.to_string();
 
-    let new_doc = doc! {
-        "token_id": token_id_str,
-        "metadata": metadata_bson,
-    };
-
+    let options = UpdateOptions::builder().upsert(true).build();
     collection
-        .insert_one(new_doc, None)
+        .update_one(
+            doc! {
+                "token_id": &token_id_str,
+                "status": bson::to_bson(&NFTStatus::Pending)?,
+                "mint_job_id": mint_job_id,
+            },
+            doc! { "$set": { "metadata": metadata_bson } },
+            options,
+        )
         .await
-        .map_err(|e| anyhow::Error::new(e))?; // Convert MongoDB error to anyhow error
+        .map_err(anyhow::Error::new)?; // Convert MongoDB error to anyhow error
+
+    Ok(())
+}
+
+/// Bulk version of `add_pending_nft` for tokens that have no document yet.
+/// Returns the error for each input index that could not be written.
+pub async fn add_pending_nfts(
+    client: Arc<Client>,
+    collection: &str,
+    tokens: &[(ObjectId, AddNFTInput)],
+) -> Result<HashMap<usize, BulkWriteError>> {
+    let collection = client
+        .database("snapit")
+        .collection::<bson::Document>(collection);
+
+    let status = bson::to_bson(&NFTStatus::Pending)?;
+    let documents = tokens
+        .iter()
+        .map(|(mint_job_id, token)| {
+            Ok(doc! {
+                "token_id": token.token_id.to_string(),
+                "metadata": bson::to_bson(&token.metadata)?,
+                "status": status.clone(),
+                "mint_job_id": mint_job_id,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Unordered, so one bad document does not stop the rest.
+    let options = InsertManyOptions::builder().ordered(false).build();
+    match collection.insert_many(documents, options).await {
+        Ok(_) => Ok(HashMap::new()),
+        Err(e) => match e.kind.as_ref() {
+            ErrorKind::BulkWrite(BulkWriteFailure {
+                write_errors: Some(write_errors),
+                write_concern_error: None,
+                ..
+            }) => Ok(write_errors
+                .iter()
+                .map(|write_error| (write_error.index, write_error.clone()))
+                .collect()),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Returns the ids in `token_ids` that already have a document, minted or pending.
+pub async fn find_known_token_ids(
+    client: Arc<Client>,
+    collection: &str,
+    token_ids: &[u64],
+) -> Result<HashSet<u64>> {
+    let collection = client
+        .database("snapit")
+        .collection::<bson::Document>(collection);
+
+    let token_ids_str: Vec<String> = token_ids.iter().map(|id| id.to_string()).collect();
+    let documents: Vec<bson::Document> = collection
+        .find(doc! { "token_id": { "$in": token_ids_str } }, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    documents
+        .iter()
+        .map(|document| Ok(document.get_str("token_id")?.parse::<u64>()?))
+        .collect()
+}
+
+pub async fn mark_nft_minted(client: Arc<Client>, collection: &str, token_id: u64) -> Result<()> {
+    let collection = client
+        .database("snapit")
+        .collection::<bson::Document>(collection);
+
+    collection
+        .update_one(
+            doc! { "token_id": token_id.to_string(), "status": bson::to_bson(&NFTStatus::Pending)? },
+            doc! { "$set": { "status": bson::to_bson(&NFTStatus::Minted)? } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
 
+/// Rolls back the pending metadata of a mint that did not happen.
+pub async fn remove_pending_nft(
+    client: Arc<Client>,
+    collection: &str,
+    token_id: u64,
+) -> Result<()> {
+    let collection = client
+        .database("snapit")
+        .collection::<bson::Document>(collection);
+
+    collection
+        .delete_one(
+            doc! { "token_id": token_id.to_string(), "status": bson::to_bson(&NFTStatus::Pending)? },
+            None,
+        )
+        .await?;
     Ok(())
 }
 
-pub async fn contract_metadata(client: Arc<Client>) -> Result<Option<ContractMetadata>> {
+/// Token ids whose metadata is still waiting for a mint outcome.
+pub async fn find_pending_nft_token_ids(client: Arc<Client>, collection: &str) -> Result<Vec<u64>> {
+    let collection = client
+        .database("snapit")
+        .collection::<bson::Document>(collection);
+
+    let documents: Vec<bson::Document> = collection
+        .find(doc! { "status": bson::to_bson(&NFTStatus::Pending)? }, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    documents
+        .iter()
+        .map(|document| Ok(document.get_str("token_id")?.parse::<u64>()?))
+        .collect()
+}
+
+/// The contract metadata document of the NFT collection with slug `nft_collection`.
+pub async fn contract_metadata(
+    client: Arc<Client>,
+    nft_collection: &str,
+) -> Result<Option<ContractMetadata>> {
     let collection = client
         .database("snapit")
         .collection::<bson::Document>(SETTINGS_COLLECTION_NAME);
 
-    let filter = doc! { "type": "contract-metadata", "version": "0.0.1" };
+    let filter =
+        doc! { "type": "contract-metadata", "version": "0.0.1", "collection": nft_collection };
     let find_option: FindOneOptions = FindOneOptions::builder()
-        .projection(doc! { "_id": 0, "type": 0, "version": 0 })
+        .projection(doc! { "_id": 0, "type": 0, "version": 0, "collection": 0 })
         .build();
     let result = collection
         .find_one(filter, find_option)
         .await
-        .map_err(|e| anyhow::Error::new(e))?; // Convert MongoDB error to anyhow error
+        .map_err(anyhow::Error::new)?; // Convert MongoDB error to anyhow error
 
     if let Some(document) = result {
         // Convert the BSON document to JSON
         let metadata_json = bson::to_bson(&document)
-            .map_err(|e| anyhow::Error::new(e))?
+            .map_err(anyhow::Error::new)?
             .as_document()
             .ok_or_else(|| anyhow::anyhow!("Failed to convert BSON to Document"))?
             .clone();
@@ -88,24 +248,50 @@ pub async fn contract_metadata(client: Arc<Client>) -> Result<Option<ContractMet
     }
 }
 
-pub async fn find_one_nft(client: Arc<Client>, token_id: u64) -> Result<Option<DBNFTWithoutId>> {
+/// Assigns the contract metadata written before collections existed to `nft_collection`.
+pub async fn assign_legacy_contract_metadata(
+    client: Arc<Client>,
+    nft_collection: &str,
+) -> Result<()> {
+    client
+        .database("snapit")
+        .collection::<bson::Document>(SETTINGS_COLLECTION_NAME)
+        .update_many(
+            doc! { "type": "contract-metadata", "collection": { "$exists": false } },
+            doc! { "$set": { "collection": nft_collection } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Matches minted tokens, including documents written before statuses existed.
+fn minted_filter() -> Result<bson::Document> {
+    Ok(doc! { "$nin": [bson::to_bson(&NFTStatus::Pending)?] })
+}
+
+pub async fn find_one_nft(
+    client: Arc<Client>,
+    collection: &str,
+    token_id: u64,
+) -> Result<Option<DBNFTWithoutId>> {
     let collection = client
         .database("snapit")
-        .collection::<bson::Document>(COLLECTION_NAME);
+        .collection::<bson::Document>(collection);
 
-    let filter = doc! { "token_id": token_id.to_string() };
+    let filter = doc! { "token_id": token_id.to_string(), "status": minted_filter()? };
     let find_option: FindOneOptions = FindOneOptions::builder()
         .projection(doc! { "_id": 0 })
         .build();
     let result = collection
         .find_one(filter, find_option)
         .await
-        .map_err(|e| anyhow::Error::new(e))?; // Convert MongoDB error to anyhow error
+        .map_err(anyhow::Error::new)?; // Convert MongoDB error to anyhow error
 
     if let Some(document) = result {
         // Convert the BSON document to JSON
         let metadata_json = bson::to_bson(&document)
-            .map_err(|e| anyhow::Error::new(e))?
+            .map_err(anyhow::Error::new)?
             .as_document()
             .ok_or_else(|| anyhow::anyhow!("Failed to convert BSON to Document"))?
             .clone();
@@ -116,26 +302,30 @@ pub async fn find_one_nft(client: Arc<Client>, token_id: u64) -> Result<Option<D
     }
 }
 
-pub async fn find_nfts(client: Arc<Client>, token_ids: Vec<u64>) -> Result<Vec<DBNFTWithoutId>> {
+pub async fn find_nfts(
+    client: Arc<Client>,
+    collection: &str,
+    token_ids: Vec<u64>,
+) -> Result<Vec<DBNFTWithoutId>> {
     let collection = client
         .database("snapit")
-        .collection::<bson::Document>(COLLECTION_NAME);
+        .collection::<bson::Document>(collection);
 
     // Convert token_ids to strings and prepare for $in query
     let token_ids_str: Vec<String> = token_ids.iter().map(|id| id.to_string()).collect();
 
-    let filter = doc! { "token_id": { "$in": token_ids_str } };
+    let filter = doc! { "token_id": { "$in": token_ids_str }, "status": minted_filter()? };
 
     let mut cursor = collection
         .find(filter, None)
         .await
-        .map_err(|e| anyhow::Error::new(e))?; // Convert MongoDB error to anyhow error
+        .map_err(anyhow::Error::new)?; // Convert MongoDB error to anyhow error
 
     let mut results = Vec::new();
-    while let Some(document) = cursor.try_next().await.map_err(|e| anyhow::Error::new(e))? {
+    while let Some(document) = cursor.try_next().await.map_err(anyhow::Error::new)? {
         // Convert each BSON document to JSON
         let metadata_json = bson::to_bson(&document)
-            .map_err(|e| anyhow::Error::new(e))?
+            .map_err(anyhow::Error::new)?
             .as_document()
             .ok_or_else(|| anyhow::anyhow!("Failed to convert BSON to Document"))?
             .clone();
@@ -176,21 +366,21 @@ pub struct MetadataAttribute {
     value: Value,
 }
 
+/// Lifecycle of a token document. Documents without a status predate it and are minted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NFTStatus {
+    /// Metadata saved, mint transaction not confirmed yet.
+    Pending,
+    Minted,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
 pub struct AddNFTInput {
     pub token_id: u64,
     pub metadata: Metadata,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DBNFT {
-    // fields corresponding to your MongoDB collection
-    _id: bson::oid::ObjectId,
-    token_id: String,
-    metadata: Metadata,
-    // other fields...
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DBNFTWithoutId {
     // fields corresponding to your MongoDB collection
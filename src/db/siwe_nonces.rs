@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use mongodb::bson::{doc, DateTime};
+use mongodb::options::IndexOptions;
+use mongodb::{Client, IndexModel};
+use serde::{Deserialize, Serialize};
+
+const SIWE_NONCES_COLLECTION_NAME: &str = "siwe-nonces";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SiweNonceDocument {
+    nonce: String,
+    expires_at: DateTime,
+    used: bool,
+}
+
+fn siwe_nonces_collection(client: &Client) -> mongodb::Collection<SiweNonceDocument> {
+    client
+        .database("snapit")
+        .collection::<SiweNonceDocument>(SIWE_NONCES_COLLECTION_NAME)
+}
+
+pub async fn create_siwe_nonce_indexes(client: Arc<Client>) -> Result<()> {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "nonce": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        // Let Mongo delete nonces once they expire. Issuing them needs no key, so this is
+        // what bounds the collection.
+        IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Duration::from_secs(0))
+                    .build(),
+            )
+            .build(),
+    ];
+    siwe_nonces_collection(&client)
+        .create_indexes(indexes, None)
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_siwe_nonce(
+    client: Arc<Client>,
+    nonce: &str,
+    expires_at: DateTime,
+) -> Result<()> {
+    let document = SiweNonceDocument {
+        nonce: nonce.to_string(),
+        expires_at,
+        used: false,
+    };
+    siwe_nonces_collection(&client)
+        .insert_one(document, None)
+        .await?;
+    Ok(())
+}
+
+/// Atomically marks an unexpired nonce as used. Returns false if it was unknown,
+/// expired or already used.
+pub async fn consume_siwe_nonce(client: Arc<Client>, nonce: &str) -> Result<bool> {
+    let result = siwe_nonces_collection(&client)
+        .find_one_and_update(
+            doc! { "nonce": nonce, "used": false, "expires_at": { "$gt": DateTime::now() } },
+            doc! { "$set": { "used": true } },
+            None,
+        )
+        .await?;
+    Ok(result.is_some())
+}
@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, DateTime};
+use mongodb::options::{
+    FindOneAndUpdateOptions, FindOptions, IndexOptions, ReturnDocument, UpdateOptions,
+};
+use mongodb::{Client, IndexModel};
+use serde::{Deserialize, Serialize};
+
+use crate::db::mongo::SETTINGS_COLLECTION_NAME;
+
+const TOKEN_ID_RESERVATIONS_COLLECTION_NAME: &str = "token-id-reservations";
+
+const TOKEN_ID_COUNTER_TYPE: &str = "token-id-counter";
+
+/// An allocated token id that has not been minted yet. Expired reservations are
+/// handed out again instead of being deleted, since the counter never goes back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenIdReservation {
+    /// Slug of the NFT collection the id belongs to.
+    pub collection: String,
+    pub token_id: u64,
+    pub reserved_by: String,
+    pub expires_at: DateTime,
+}
+
+fn token_id_reservations_collection(client: &Client) -> mongodb::Collection<TokenIdReservation> {
+    client
+        .database("snapit")
+        .collection::<TokenIdReservation>(TOKEN_ID_RESERVATIONS_COLLECTION_NAME)
+}
+
+pub async fn create_token_id_reservation_indexes(client: Arc<Client>) -> Result<()> {
+    // Ids were unique across the deployment before collections existed.
+    let _ = token_id_reservations_collection(&client)
+        .drop_index("token_id_1", None)
+        .await;
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "collection": 1, "token_id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder().keys(doc! { "expires_at": 1 }).build(),
+    ];
+    token_id_reservations_collection(&client)
+        .create_indexes(indexes, None)
+        .await?;
+    Ok(())
+}
+
+/// Assigns the counter and reservations written before collections existed to
+/// `collection`.
+pub async fn assign_legacy_token_ids(client: Arc<Client>, collection: &str) -> Result<()> {
+    let assign = doc! { "$set": { "collection": collection } };
+    client
+        .database("snapit")
+        .collection::<bson::Document>(SETTINGS_COLLECTION_NAME)
+        .update_many(
+            doc! { "type": TOKEN_ID_COUNTER_TYPE, "collection": { "$exists": false } },
+            assign.clone(),
+            None,
+        )
+        .await?;
+    client
+        .database("snapit")
+        .collection::<bson::Document>(TOKEN_ID_RESERVATIONS_COLLECTION_NAME)
+        .update_many(doc! { "collection": { "$exists": false } }, assign, None)
+        .await?;
+    Ok(())
+}
+
+/// Moves the counter of `collection` past the highest token id stored in
+/// `mongo_collection`, so ids minted before the counter existed are not handed out one
+/// by one only to be found taken. Never moves the counter back. The stored ids are only
+/// scanned once per collection; the counter is marked as seeded afterwards.
+pub async fn seed_token_id_counter(
+    client: Arc<Client>,
+    collection: &str,
+    mongo_collection: &str,
+) -> Result<()> {
+    let counters = client
+        .database("snapit")
+        .collection::<bson::Document>(SETTINGS_COLLECTION_NAME);
+    let counter = doc! { "type": TOKEN_ID_COUNTER_TYPE, "collection": collection };
+
+    let mut seeded = counter.clone();
+    seeded.insert("seeded", true);
+    if counters.count_documents(seeded, None).await? > 0 {
+        return Ok(());
+    }
+
+    let options = FindOptions::builder()
+        .projection(doc! { "_id": 0, "token_id": 1 })
+        .build();
+    let token_ids: Vec<String> = client
+        .database("snapit")
+        .collection::<bson::Document>(mongo_collection)
+        .find(doc! {}, options)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .filter_map(|document| document.get_str("token_id").ok().map(str::to_string))
+        .collect();
+    let highest = highest_token_id(&token_ids).unwrap_or(0);
+    counters
+        .update_one(
+            counter,
+            doc! {
+                "$max": { "last_token_id": highest as i64 },
+                "$set": { "seeded": true },
+            },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// The highest numeric id among `token_ids`; others are ignored.
+fn highest_token_id(token_ids: &[String]) -> Option<u64> {
+    token_ids
+        .iter()
+        .filter_map(|token_id| token_id.parse::<u64>().ok())
+        .max()
+}
+
+/// Atomically increments the counter of `collection` in `settings` and returns the new
+/// value. The first id handed out is 1, or the one after the highest stored id once
+/// [`seed_token_id_counter`] ran.
+pub async fn next_token_id(client: Arc<Client>, collection: &str) -> Result<u64> {
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+    let counter = client
+        .database("snapit")
+        .collection::<bson::Document>(SETTINGS_COLLECTION_NAME)
+        .find_one_and_update(
+            doc! { "type": TOKEN_ID_COUNTER_TYPE, "collection": collection },
+            doc! { "$inc": { "last_token_id": 1_i64 } },
+            options,
+        )
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("token id counter was not created"))?;
+    Ok(counter.get_i64("last_token_id")? as u64)
+}
+
+pub async fn insert_token_id_reservation(
+    client: Arc<Client>,
+    reservation: &TokenIdReservation,
+) -> Result<()> {
+    token_id_reservations_collection(&client)
+        .insert_one(reservation, None)
+        .await?;
+    Ok(())
+}
+
+/// Takes over the oldest expired reservation of `collection`, if any.
+pub async fn reclaim_expired_token_id_reservation(
+    client: Arc<Client>,
+    collection: &str,
+    reserved_by: &str,
+    expires_at: DateTime,
+) -> Result<Option<TokenIdReservation>> {
+    let options = FindOneAndUpdateOptions::builder()
+        .sort(doc! { "expires_at": 1 })
+        .return_document(ReturnDocument::After)
+        .build();
+    Ok(token_id_reservations_collection(&client)
+        .find_one_and_update(
+            doc! { "collection": collection, "expires_at": { "$lte": DateTime::now() } },
+            doc! { "$set": { "reserved_by": reserved_by, "expires_at": expires_at } },
+            options,
+        )
+        .await?)
+}
+
+/// Drops the reservation once its token exists, so the id is never handed out again.
+pub async fn delete_token_id_reservation(
+    client: Arc<Client>,
+    collection: &str,
+    token_id: u64,
+) -> Result<()> {
+    token_id_reservations_collection(&client)
+        .delete_one(
+            doc! { "collection": collection, "token_id": token_id as i64 },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Makes the id of a failed mint available again right away.
+pub async fn expire_token_id_reservation(
+    client: Arc<Client>,
+    collection: &str,
+    token_id: u64,
+) -> Result<()> {
+    token_id_reservations_collection(&client)
+        .update_one(
+            doc! { "collection": collection, "token_id": token_id as i64 },
+            doc! { "$set": { "expires_at": DateTime::now() } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(token_ids: &[&str]) -> Vec<String> {
+        token_ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn seeds_past_the_numerically_highest_id() {
+        // As strings "9" sorts after "25".
+        assert_eq!(highest_token_id(&ids(&["1", "25", "9", "3"])), Some(25));
+    }
+
+    #[test]
+    fn skips_ids_that_are_not_numbers() {
+        assert_eq!(highest_token_id(&ids(&["7", "", "genesis"])), Some(7));
+    }
+
+    #[test]
+    fn leaves_empty_collections_unseeded() {
+        assert_eq!(highest_token_id(&[]), None);
+        assert_eq!(highest_token_id(&ids(&["genesis"])), None);
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ethers::types::{Transaction, H256};
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, oid::ObjectId, DateTime};
+use mongodb::options::{FindOptions, IndexOptions};
+use mongodb::{Client, IndexModel};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::chain::chain::TransactionReceiptSchema;
+
+const TRANSACTIONS_COLLECTION_NAME: &str = "transactions";
+
+/// Why the server sent a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TxPurpose {
+    Mint,
+    /// Zero-value self-transfer replacing a stuck transaction.
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TxStatus {
+    /// Sent, not mined yet.
+    Pending,
+    /// Mined with status 1.
+    Confirmed,
+    /// Mined with status 0.
+    Failed,
+    /// No longer known to the node without having been mined.
+    Dropped,
+    /// Superseded by a transaction with the same nonce and higher fees.
+    Replaced,
+}
+
+impl TxStatus {
+    pub fn of_receipt(receipt: &TransactionReceiptSchema) -> Self {
+        match receipt.status {
+            Some(1) => TxStatus::Confirmed,
+            _ => TxStatus::Failed,
+        }
+    }
+}
+
+/// A transaction sent by the server. Amounts are decimal strings in wei.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub tx_hash: String,
+    pub purpose: TxPurpose,
+    /// Slug of the NFT collection the transaction mints in.
+    pub collection: String,
+    pub token_id: Option<u64>,
+    pub mint_job_id: Option<ObjectId>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub nonce: Option<u64>,
+    pub gas_limit: Option<String>,
+    /// Set for legacy transactions.
+    pub gas_price: Option<String>,
+    pub max_fee_per_gas: Option<String>,
+    pub max_priority_fee_per_gas: Option<String>,
+    pub status: TxStatus,
+    pub block_number: Option<u64>,
+    /// Depth when the record was last updated.
+    pub confirmations: u64,
+    pub receipt: Option<TransactionReceiptSchema>,
+    /// Hash of the stuck transaction this one replaces.
+    pub replaces: Option<String>,
+    pub replaced_by: Option<String>,
+    /// When the first transaction with this nonce was sent. Replacements keep the time
+    /// of the transaction they replace.
+    #[serde(default)]
+    pub nonce_sent_at: Option<DateTime>,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl TransactionRecord {
+    /// Builds a pending record from what the node reported for the sent transaction.
+    pub fn new(
+        hash: H256,
+        purpose: TxPurpose,
+        collection: &str,
+        transaction: Option<&Transaction>,
+    ) -> Self {
+        let now = DateTime::now();
+        TransactionRecord {
+            tx_hash: format!("{:?}", hash),
+            purpose,
+            collection: collection.to_string(),
+            token_id: None,
+            mint_job_id: None,
+            from: transaction.map(|tx| format!("{:?}", tx.from)),
+            to: transaction
+                .and_then(|tx| tx.to)
+                .map(|to| format!("{:?}", to)),
+            nonce: transaction.map(|tx| tx.nonce.as_u64()),
+            gas_limit: transaction.map(|tx| tx.gas.to_string()),
+            gas_price: transaction
+                .filter(|tx| tx.max_fee_per_gas.is_none())
+                .and_then(|tx| tx.gas_price)
+                .map(|price| price.to_string()),
+            max_fee_per_gas: transaction
+                .and_then(|tx| tx.max_fee_per_gas)
+                .map(|fee| fee.to_string()),
+            max_priority_fee_per_gas: transaction
+                .and_then(|tx| tx.max_priority_fee_per_gas)
+                .map(|fee| fee.to_string()),
+            status: TxStatus::Pending,
+            block_number: None,
+            confirmations: 0,
+            receipt: None,
+            replaces: None,
+            replaced_by: None,
+            nonce_sent_at: Some(now),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Since when the nonce of this transaction has been waiting to be mined.
+    pub fn nonce_pending_since(&self) -> DateTime {
+        self.nonce_sent_at.unwrap_or(self.created_at)
+    }
+}
+
+fn transactions_collection(client: &Client) -> mongodb::Collection<TransactionRecord> {
+    client
+        .database("snapit")
+        .collection::<TransactionRecord>(TRANSACTIONS_COLLECTION_NAME)
+}
+
+pub async fn create_transaction_indexes(client: Arc<Client>) -> Result<()> {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "tx_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "status": 1, "created_at": 1 })
+            .build(),
+    ];
+    transactions_collection(&client)
+        .create_indexes(indexes, None)
+        .await?;
+    Ok(())
+}
+
+/// Assigns the transactions sent before collections existed to `collection`.
+pub async fn assign_legacy_transactions(client: Arc<Client>, collection: &str) -> Result<()> {
+    client
+        .database("snapit")
+        .collection::<bson::Document>(TRANSACTIONS_COLLECTION_NAME)
+        .update_many(
+            doc! { "collection": { "$exists": false } },
+            doc! { "$set": { "collection": collection } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_transaction(client: Arc<Client>, record: &TransactionRecord) -> Result<()> {
+    transactions_collection(&client)
+        .insert_one(record, None)
+        .await?;
+    Ok(())
+}
+
+pub async fn find_transaction(
+    client: Arc<Client>,
+    tx_hash: &str,
+) -> Result<Option<TransactionRecord>> {
+    Ok(transactions_collection(&client)
+        .find_one(doc! { "tx_hash": tx_hash }, None)
+        .await?)
+}
+
+/// Stores the receipt of a mined transaction and its current depth.
+pub async fn mark_transaction_mined(
+    client: Arc<Client>,
+    tx_hash: &str,
+    receipt: &TransactionReceiptSchema,
+    confirmations: u64,
+) -> Result<()> {
+    let status = TxStatus::of_receipt(receipt);
+    transactions_collection(&client)
+        .update_one(
+            doc! { "tx_hash": tx_hash },
+            doc! { "$set": {
+                "status": bson::to_bson(&status)?,
+                "block_number": receipt.block_number.map(|number| number as i64),
+                "confirmations": confirmations as i64,
+                "receipt": bson::to_bson(receipt)?,
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn mark_transaction_dropped(client: Arc<Client>, tx_hash: &str) -> Result<()> {
+    transactions_collection(&client)
+        .update_one(
+            doc! { "tx_hash": tx_hash, "status": bson::to_bson(&TxStatus::Pending)? },
+            doc! { "$set": {
+                "status": bson::to_bson(&TxStatus::Dropped)?,
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Pending transactions sent before `sent_before`, oldest first.
+pub async fn find_stuck_transactions(
+    client: Arc<Client>,
+    sent_before: DateTime,
+) -> Result<Vec<TransactionRecord>> {
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": 1 })
+        .build();
+    let cursor = transactions_collection(&client)
+        .find(
+            doc! {
+                "status": bson::to_bson(&TxStatus::Pending)?,
+                "created_at": { "$lte": sent_before },
+            },
+            options,
+        )
+        .await?;
+    Ok(cursor.try_collect().await?)
+}
+
+pub async fn find_pending_transactions(client: Arc<Client>) -> Result<Vec<TransactionRecord>> {
+    let cursor = transactions_collection(&client)
+        .find(doc! { "status": bson::to_bson(&TxStatus::Pending)? }, None)
+        .await?;
+    Ok(cursor.try_collect().await?)
+}
+
+/// Returns `false` if the transaction was no longer pending.
+pub async fn mark_transaction_replaced(
+    client: Arc<Client>,
+    tx_hash: &str,
+    replaced_by: &str,
+) -> Result<bool> {
+    let result = transactions_collection(&client)
+        .update_one(
+            doc! { "tx_hash": tx_hash, "status": bson::to_bson(&TxStatus::Pending)? },
+            doc! { "$set": {
+                "status": bson::to_bson(&TxStatus::Replaced)?,
+                "replaced_by": replaced_by,
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await?;
+    Ok(result.modified_count == 1)
+}
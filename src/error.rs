@@ -1,54 +1,325 @@
-use ethers::providers::{Middleware, ProviderError};
+use std::fmt;
+
+use ethers::providers::{Middleware, ProviderError, RpcError};
+use ethers::types::U256;
+use ethers::utils::{format_ether, format_units};
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
 use warp::{http::StatusCode, Rejection, Reply};
 
-pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, std::convert::Infallible> {
-    let code;
-    let message;
+use crate::chain::revert::{decode_revert, DecodedRevert};
 
-    if err.is_not_found() {
-        code = StatusCode::NOT_FOUND;
-        message = "Not Found";
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, std::convert::Infallible> {
+    let problem = if err.is_not_found() {
+        ProblemDetails::new(StatusCode::NOT_FOUND, "NOT_FOUND", "Not Found".to_string())
     } else if let Some(server_error) = err.find::<ServerError>() {
-        // Here we handle the custom ServerError
-        code = StatusCode::UNAUTHORIZED; // Or any other appropriate status code
-        message = server_error._reason.as_str();
-    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
-        code = StatusCode::BAD_REQUEST;
-        message = "Invalid Query";
+        if let ServerError::Internal(reason) = server_error {
+            eprintln!("Internal error: {}", reason);
+        }
+        ProblemDetails::from(server_error)
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        ProblemDetails::new(StatusCode::BAD_REQUEST, "INVALID_QUERY", e.to_string())
+    } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        ProblemDetails::new(StatusCode::BAD_REQUEST, "INVALID_BODY", e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::MissingHeader>() {
+        ProblemDetails::new(StatusCode::BAD_REQUEST, "MISSING_HEADER", e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::UnsupportedMediaType>() {
+        ProblemDetails::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "UNSUPPORTED_MEDIA_TYPE",
+            e.to_string(),
+        )
+    } else if let Some(e) = err.find::<warp::reject::PayloadTooLarge>() {
+        ProblemDetails::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "PAYLOAD_TOO_LARGE",
+            e.to_string(),
+        )
+    } else if let Some(e) = err.find::<warp::reject::MethodNotAllowed>() {
+        ProblemDetails::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "METHOD_NOT_ALLOWED",
+            e.to_string(),
+        )
     } else {
         // Log the unhandled rejection
         eprintln!("Unhandled rejection: {:?}", err);
-        code = StatusCode::INTERNAL_SERVER_ERROR;
-        message = "Internal Server Error";
+        ProblemDetails::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "INTERNAL_ERROR",
+            "Internal Server Error".to_string(),
+        )
+    };
+
+    Ok(problem.into_reply())
+}
+
+/// RFC 7807 `application/problem+json` body returned for every error.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable, machine-readable error code.
+    pub code: String,
+    /// Decoded arguments of a contract revert, when available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Value>,
+}
+
+impl ProblemDetails {
+    fn new(status: StatusCode, code: &str, detail: String) -> Self {
+        ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: status
+                .canonical_reason()
+                .unwrap_or("Unknown Error")
+                .to_string(),
+            status: status.as_u16(),
+            detail,
+            code: code.to_string(),
+            args: None,
+        }
+    }
+
+    fn into_reply(self) -> warp::reply::Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let json = warp::reply::json(&self);
+        let json = warp::reply::with_header(json, "content-type", "application/problem+json");
+        warp::reply::with_status(json, status).into_response()
     }
+}
 
-    let json = warp::reply::json(&{
-        let mut map = std::collections::HashMap::new();
-        map.insert("message", message);
-        map
-    });
+impl From<&ServerError> for ProblemDetails {
+    fn from(err: &ServerError) -> Self {
+        let detail = match err {
+            // Never leak internal details to clients; they are logged instead.
+            ServerError::Internal(_) => "Internal Server Error".to_string(),
+            _ => err.to_string(),
+        };
+        let mut problem = ProblemDetails::new(err.status(), err.code(), detail);
+        match err {
+            ServerError::ContractRevert(revert) => {
+                problem.args = revert
+                    .decoded
+                    .as_ref()
+                    .map(|d| Value::Object(d.args.clone()));
+            }
+            ServerError::TokenAlreadyMinted { token_id, owner } => {
+                problem.args = Some(json!({ "tokenId": token_id.to_string(), "owner": owner }));
+            }
+            ServerError::FeeAboveCap { fee_per_gas, cap } => {
+                problem.args = Some(json!({
+                    "feePerGas": fee_per_gas.to_string(),
+                    "maxFeePerGas": cap.to_string(),
+                }));
+            }
+            ServerError::InsufficientFunds { balance, required } => {
+                problem.args = Some(json!({
+                    "balanceWei": balance.to_string(),
+                    "requiredWei": required.to_string(),
+                }));
+            }
+            _ => {}
+        }
+        problem
+    }
+}
 
-    Ok(warp::reply::with_status(json, code))
+/// External services the API depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamService {
+    Graph,
+    Alchemy,
+    Rpc,
+    Database,
+}
+
+impl UpstreamService {
+    fn name(&self) -> &'static str {
+        match self {
+            UpstreamService::Graph => "Graph",
+            UpstreamService::Alchemy => "Alchemy",
+            UpstreamService::Rpc => "RPC",
+            UpstreamService::Database => "Database",
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct ServerError {
-    _reason: String,
+pub enum ServerError {
+    /// Missing or invalid credentials.
+    Unauthorized(String),
+    /// Valid credentials without the scope the route requires.
+    Forbidden(String),
+    /// The request was understood but its content is invalid.
+    Validation(String),
+    NotFound(String),
+    /// A dependency (Graph, Alchemy, RPC node, Mongo) failed or timed out.
+    Upstream {
+        service: UpstreamService,
+        message: String,
+        timeout: bool,
+    },
+    /// The contract rejected the call.
+    ContractRevert(Box<ContractRevertError>),
+    /// The request conflicts with the current state of a resource.
+    Conflict(String),
+    /// The token id already has an owner on chain.
+    TokenAlreadyMinted {
+        token_id: u64,
+        owner: String,
+    },
+    /// The fee a transaction needs is above the configured `maxFeePerGas` cap.
+    FeeAboveCap {
+        fee_per_gas: U256,
+        cap: U256,
+    },
+    /// The signer wallets cannot pay for the transactions the request would send.
+    InsufficientFunds {
+        balance: U256,
+        required: U256,
+    },
+    /// The feature is disabled or the server cannot serve it right now.
+    Unavailable(String),
+    Internal(String),
 }
 
+/// A contract revert, decoded against the known ABIs when the selector matches.
+#[derive(Debug)]
+pub struct ContractRevertError {
+    pub decoded: Option<DecodedRevert>,
+    pub message: String,
+}
+
+impl ServerError {
+    pub fn upstream(service: UpstreamService, message: impl fmt::Display) -> Self {
+        ServerError::Upstream {
+            service,
+            message: message.to_string(),
+            timeout: false,
+        }
+    }
+
+    pub fn from_reqwest(service: UpstreamService, err: reqwest::Error) -> Self {
+        ServerError::Upstream {
+            service,
+            message: err.to_string(),
+            timeout: err.is_timeout(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ServerError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ServerError::Validation(_) => StatusCode::BAD_REQUEST,
+            ServerError::NotFound(_) => StatusCode::NOT_FOUND,
+            ServerError::Upstream { timeout: true, .. } => StatusCode::GATEWAY_TIMEOUT,
+            ServerError::Upstream {
+                service: UpstreamService::Database,
+                ..
+            } => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::Upstream { .. } => StatusCode::BAD_GATEWAY,
+            ServerError::ContractRevert(revert) => match &revert.decoded {
+                Some(decoded) if decoded.conflict => StatusCode::CONFLICT,
+                _ => StatusCode::UNPROCESSABLE_ENTITY,
+            },
+            ServerError::Conflict(_) | ServerError::TokenAlreadyMinted { .. } => {
+                StatusCode::CONFLICT
+            }
+            ServerError::FeeAboveCap { .. }
+            | ServerError::InsufficientFunds { .. }
+            | ServerError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            ServerError::Unauthorized(_) => "UNAUTHORIZED",
+            ServerError::Forbidden(_) => "FORBIDDEN",
+            ServerError::Validation(_) => "VALIDATION_ERROR",
+            ServerError::NotFound(_) => "NOT_FOUND",
+            ServerError::Upstream {
+                service, timeout, ..
+            } => match (service, timeout) {
+                (UpstreamService::Graph, false) => "GRAPH_ERROR",
+                (UpstreamService::Graph, true) => "GRAPH_TIMEOUT",
+                (UpstreamService::Alchemy, false) => "ALCHEMY_ERROR",
+                (UpstreamService::Alchemy, true) => "ALCHEMY_TIMEOUT",
+                (UpstreamService::Rpc, false) => "RPC_ERROR",
+                (UpstreamService::Rpc, true) => "RPC_TIMEOUT",
+                (UpstreamService::Database, false) => "DATABASE_ERROR",
+                (UpstreamService::Database, true) => "DATABASE_TIMEOUT",
+            },
+            ServerError::ContractRevert(revert) => match &revert.decoded {
+                Some(decoded) => decoded.code.as_str(),
+                None => "CONTRACT_REVERT",
+            },
+            ServerError::Conflict(_) => "CONFLICT",
+            ServerError::TokenAlreadyMinted { .. } => "TOKEN_ALREADY_MINTED",
+            ServerError::FeeAboveCap { .. } => "FEE_ABOVE_CAP",
+            ServerError::InsufficientFunds { .. } => "INSUFFICIENT_SIGNER_FUNDS",
+            ServerError::Unavailable(_) => "SERVICE_UNAVAILABLE",
+            ServerError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Unauthorized(reason)
+            | ServerError::Forbidden(reason)
+            | ServerError::Validation(reason)
+            | ServerError::NotFound(reason)
+            | ServerError::Conflict(reason)
+            | ServerError::Unavailable(reason)
+            | ServerError::Internal(reason) => write!(f, "{}", reason),
+            ServerError::Upstream {
+                service, message, ..
+            } => write!(f, "{} request failed: {}", service.name(), message),
+            ServerError::TokenAlreadyMinted { token_id, owner } => {
+                write!(f, "Token {} is already owned by {}", token_id, owner)
+            }
+            ServerError::FeeAboveCap { fee_per_gas, cap } => write!(
+                f,
+                "Network fee of {} gwei is above the cap of {} gwei",
+                format_units(*fee_per_gas, "gwei").unwrap_or_default(),
+                format_units(*cap, "gwei").unwrap_or_default()
+            ),
+            ServerError::InsufficientFunds { balance, required } => write!(
+                f,
+                "Signer wallets hold {} ETH, not enough for the estimated {} ETH of gas",
+                format_ether(*balance),
+                format_ether(*required)
+            ),
+            ServerError::ContractRevert(revert) => match &revert.decoded {
+                Some(decoded) => write!(f, "Contract reverted with {}", decoded.name),
+                None => write!(f, "Contract reverted: {}", revert.message),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
 impl From<anyhow::Error> for ServerError {
     fn from(err: anyhow::Error) -> ServerError {
-        ServerError {
-            _reason: err.to_string(),
+        if let Some(mongo_error) = err.downcast_ref::<mongodb::error::Error>() {
+            return ServerError::upstream(UpstreamService::Database, mongo_error);
         }
+        ServerError::Internal(err.to_string())
     }
 }
 
-impl From<reqwest::Error> for ServerError {
-    fn from(err: reqwest::Error) -> ServerError {
-        ServerError {
-            _reason: err.to_string(),
-        }
+impl From<mongodb::error::Error> for ServerError {
+    fn from(err: mongodb::error::Error) -> ServerError {
+        ServerError::upstream(UpstreamService::Database, err)
     }
 }
 
@@ -57,25 +328,34 @@ where
     M: Middleware,
 {
     fn from(err: ethers::contract::ContractError<M>) -> ServerError {
-        ServerError {
-            _reason: err.to_string(),
+        match err.as_revert() {
+            Some(data) => ServerError::ContractRevert(Box::new(ContractRevertError {
+                decoded: decode_revert(data),
+                message: err.to_string(),
+            })),
+            None => ServerError::upstream(UpstreamService::Rpc, err),
         }
     }
 }
 
 impl From<ProviderError> for ServerError {
     fn from(err: ProviderError) -> ServerError {
-        ServerError {
-            _reason: err.to_string(),
+        let revert_data = err
+            .as_error_response()
+            .and_then(|response| response.as_revert_data());
+        match revert_data {
+            Some(data) => ServerError::ContractRevert(Box::new(ContractRevertError {
+                decoded: decode_revert(&data),
+                message: err.to_string(),
+            })),
+            None => ServerError::upstream(UpstreamService::Rpc, err),
         }
     }
 }
 
 impl From<ethers::contract::AbiError> for ServerError {
     fn from(err: ethers::contract::AbiError) -> ServerError {
-        ServerError {
-            _reason: err.to_string(),
-        }
+        ServerError::Internal(err.to_string())
     }
 }
 
@@ -1,6 +1,6 @@
 use serde_json::Value;
 
-use crate::error::ServerError;
+use crate::error::{ServerError, UpstreamService};
 
 pub fn graphql_owner_tokens_query(owner_address: &str) -> String {
     format!(
@@ -45,16 +45,19 @@ pub fn graphql_auction_bid_query(token_id: &str, start_time: &str, end_time: &st
     )
 }
 
-pub async fn reqwest_graphql_query(query: String, graphql_url: &str) -> Result<Value, ServerError> {
-    let reqwest_client = reqwest::Client::new();
-    let res = reqwest_client
+pub async fn reqwest_graphql_query(
+    http_client: &reqwest::Client,
+    query: String,
+    graphql_url: &str,
+) -> Result<Value, ServerError> {
+    let res = http_client
         .post(graphql_url)
         .json(&serde_json::json!({"query": query}))
         .send()
         .await
-        .map_err(|e| ServerError::from(e))?
+        .map_err(|e| ServerError::from_reqwest(UpstreamService::Graph, e))?
         .json::<Value>()
         .await
-        .map_err(|e| ServerError::from(e))?; // Handle HTTP request error
+        .map_err(|e| ServerError::from_reqwest(UpstreamService::Graph, e))?; // Handle HTTP request error
     Ok(res)
 }
@@ -0,0 +1,220 @@
+use chrono::Utc;
+use ethers::core::rand::{thread_rng, RngCore};
+use ethers::utils::hex;
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+
+use crate::auth::{generate_api_key, AuthContext, Scope};
+use crate::db::api_keys::{insert_api_key, list_api_keys, revoke_api_key, ApiKeyDocument};
+use crate::error::ServerError;
+use crate::state::AppState;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Slugs of the collections the key may access. All collections when omitted.
+    pub collections: Option<Vec<String>>,
+    /// Days until the key expires. Keys without expiry stay valid until revoked.
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeyInfo {
+    key_id: String,
+    name: String,
+    scopes: Vec<Scope>,
+    collections: Option<Vec<String>>,
+    created_by: String,
+    created_at: String,
+    expires_at: Option<String>,
+    last_used_at: Option<String>,
+    revoked_at: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreateApiKeyResponse {
+    /// The secret is only returned once, at creation.
+    api_key: String,
+    #[serde(flatten)]
+    info: ApiKeyInfo,
+}
+
+impl From<ApiKeyDocument> for ApiKeyInfo {
+    fn from(key: ApiKeyDocument) -> Self {
+        ApiKeyInfo {
+            key_id: key.key_id,
+            name: key.name,
+            scopes: key.scopes,
+            collections: key.collections,
+            created_by: key.created_by,
+            created_at: format_datetime(key.created_at),
+            expires_at: key.expires_at.map(format_datetime),
+            last_used_at: key.last_used_at.map(format_datetime),
+            revoked_at: key.revoked_at.map(format_datetime),
+        }
+    }
+}
+
+fn format_datetime(datetime: DateTime) -> String {
+    datetime.try_to_rfc3339_string().unwrap_or_default()
+}
+
+/// Creates an API key document and returns it with its secret.
+pub async fn create_api_key(
+    state: &AppState,
+    req: CreateApiKeyRequest,
+    created_by: &str,
+) -> Result<CreateApiKeyResponse, ServerError> {
+    if req.name.trim().is_empty() {
+        return Err(ServerError::Validation(
+            "name must not be empty".to_string(),
+        ));
+    }
+    if req.scopes.is_empty() {
+        return Err(ServerError::Validation(
+            "at least one scope is required".to_string(),
+        ));
+    }
+    if let Some(collections) = &req.collections {
+        if collections.is_empty() {
+            return Err(ServerError::Validation(
+                "collections must not be empty; omit it to allow all".to_string(),
+            ));
+        }
+        if let Some(unknown) = collections
+            .iter()
+            .find(|slug| !state.collections.contains(slug))
+        {
+            return Err(ServerError::Validation(format!(
+                "Unknown collection: {}",
+                unknown
+            )));
+        }
+    }
+    let expires_at = match req.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(ServerError::Validation(
+                "expires_in_days must be positive".to_string(),
+            ))
+        }
+        Some(days) => {
+            let expires_at = Utc::now() + chrono::Duration::days(days);
+            Some(DateTime::from_millis(expires_at.timestamp_millis()))
+        }
+        None => None,
+    };
+
+    let mut key_id = [0u8; 8];
+    thread_rng().fill_bytes(&mut key_id);
+    let (api_key, key_hash) = generate_api_key();
+
+    let document = ApiKeyDocument {
+        key_id: hex::encode(key_id),
+        name: req.name,
+        key_hash,
+        scopes: req.scopes,
+        collections: req.collections,
+        created_by: created_by.to_string(),
+        created_at: DateTime::now(),
+        expires_at,
+        last_used_at: None,
+        revoked_at: None,
+    };
+    insert_api_key(state.mongo_client.clone(), &document).await?;
+
+    Ok(CreateApiKeyResponse {
+        api_key,
+        info: document.into(),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/api-keys",
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 201, description = "API key created", body = CreateApiKeyResponse),
+        (status = 400, description = "Bad Request", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing admin scope", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn create_api_key_handler(
+    req: CreateApiKeyRequest,
+    state: Arc<AppState>,
+    auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = create_api_key(&state, req, &auth.subject)
+        .await
+        .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        StatusCode::CREATED,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/api-keys",
+    responses(
+        (status = 200, description = "Returns all API keys", body = [ApiKeyInfo]),
+        (status = 403, description = "Missing admin scope", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn list_api_keys_handler(
+    state: Arc<AppState>,
+    _auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let keys = list_api_keys(state.mongo_client.clone())
+        .await
+        .map_err(|e| warp::reject::custom(ServerError::from(e)))?;
+
+    let keys: Vec<ApiKeyInfo> = keys.into_iter().map(ApiKeyInfo::from).collect();
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&keys),
+        StatusCode::OK,
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/admin/api-keys/{key_id}",
+    params(
+        ("key_id" = String, Path, description = "API key id")
+    ),
+    responses(
+        (status = 200, description = "API key revoked", body = ApiKeyInfo),
+        (status = 404, description = "API key not found", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn revoke_api_key_handler(
+    key_id: String,
+    state: Arc<AppState>,
+    _auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match revoke_api_key(state.mongo_client.clone(), &key_id).await {
+        Ok(Some(key)) => Ok(warp::reply::with_status(
+            warp::reply::json(&ApiKeyInfo::from(key)),
+            StatusCode::OK,
+        )),
+        Ok(None) => Err(warp::reject::custom(ServerError::NotFound(format!(
+            "API key {} not found",
+            key_id
+        )))),
+        Err(e) => Err(warp::reject::custom(ServerError::from(e))),
+    }
+}
@@ -0,0 +1,64 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use futures::stream::{self, Stream};
+use tokio::sync::broadcast::error::RecvError;
+use warp::sse::Event;
+
+use crate::auth::AuthContext;
+use crate::chain::events::ChainEvent;
+use crate::error::ServerError;
+use crate::state::AppState;
+
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    responses(
+        (status = 200, description = "Server-Sent Events stream of the events of the watched contracts. Each message is named after its event type; a `lagged` message carries the number of events the client fell too far behind to receive", body = ChainEvent, content_type = "text/event-stream"),
+        (status = 503, description = "No chain has a `ws_url`, so events are not watched", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn events_handler(
+    state: Arc<AppState>,
+    auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !state
+        .chains
+        .all()
+        .any(|chain| chain.config.ws_url.is_some())
+    {
+        return Err(warp::reject::custom(ServerError::Unavailable(
+            "Live events are off; no chain has a ws_url".to_string(),
+        )));
+    }
+    let events = event_stream(state.events.subscribe(), auth);
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+fn event_stream(
+    receiver: tokio::sync::broadcast::Receiver<ChainEvent>,
+    auth: AuthContext,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(receiver, move |mut receiver| {
+        let auth = auth.clone();
+        async move {
+            loop {
+                let message = match receiver.recv().await {
+                    Ok(event) if !event.visible_to(&auth) => continue,
+                    Ok(event) => Event::default()
+                        .event(event.event.name())
+                        .json_data(&event)
+                        .unwrap_or_else(|_| Event::default().comment("unserializable event")),
+                    Err(RecvError::Lagged(skipped)) => {
+                        Event::default().event("lagged").data(skipped.to_string())
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((Ok(message), receiver));
+            }
+        }
+    })
+}
@@ -1,6 +1,6 @@
-use crate::error::ServerError;
+use crate::auth::AuthContext;
+use crate::error::{ServerError, UpstreamService};
 use crate::graph::graph::{graphql_auction_bid_query, reqwest_graphql_query};
-use anyhow::anyhow;
 use ethers::prelude::*;
 use serde::de::Error as DeError;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -8,7 +8,7 @@ use std::str::FromStr;
 use std::sync::Arc;
 use warp::http::StatusCode;
 
-use crate::constants::Constants;
+use crate::state::AppState;
 
 #[derive(Deserialize)]
 pub struct GetAuctionQueryParams {
@@ -22,15 +22,14 @@ abigen!(
 );
 
 pub async fn get_auction(
-    // client: Arc<Client>,
-    ethers_client: Arc<SignerMiddleware<Provider<Http>, LocalWallet>>,
+    state: Arc<AppState>,
     params: GetAuctionQueryParams,
-    config: Arc<Constants>,
-    _auth_id: String,
+    _auth: AuthContext,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let contract_address = Address::from_str(config.auction_address.as_str()).unwrap();
-
-    let contract = AuctionContract::new(contract_address, ethers_client);
+    let contract = AuctionContract::new(
+        state.config.auction_address,
+        state.chains.auction().quorum_provider.clone(),
+    );
 
     match contract.auctions(U256::from(params.token_id)).await {
         Ok(auction_data_tuple) => {
@@ -48,10 +47,10 @@ pub async fn get_auction(
             if auction_data.auction_owner
                 == H160::from_str("0x0000000000000000000000000000000000000000").unwrap()
             {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&"Auction not found!"),
-                    StatusCode::OK,
-                ));
+                return Err(warp::reject::custom(ServerError::NotFound(format!(
+                    "Auction not found for token {}",
+                    params.token_id
+                ))));
             }
 
             let token_id_str = params.token_id.to_string();
@@ -64,21 +63,23 @@ pub async fn get_auction(
                 end_time_str.as_str(),
             );
 
-            let res = reqwest_graphql_query(query, config.graph_url_auction.as_str()).await?;
+            let res =
+                reqwest_graphql_query(&state.http_client, query, state.config.graph_url_auction())
+                    .await?;
 
-            let bids = res["data"]["bids"]
-                .as_array()
-                .ok_or("Invalid response format")
-                .map_err(|e| warp::reject::custom(ServerError::from(anyhow!(e))))?;
+            let bids = res["data"]["bids"].as_array().ok_or_else(|| {
+                ServerError::upstream(UpstreamService::Graph, "Invalid response format")
+            })?;
 
             let bid_history: Vec<Bid> = bids
                 .iter()
                 .map(|bid_value| {
                     // Attempt to deserialize each serde_json::Value into a Bid
-                    serde_json::from_value(bid_value.clone()).expect("Failed to deserialize Bid")
+                    serde_json::from_value::<GraphResultBid>(bid_value.clone())
+                        .map(convert_camel_to_snake_bid)
+                        .map_err(|e| ServerError::upstream(UpstreamService::Graph, e))
                 })
-                .map(|camel_value| convert_camel_to_snake_bid(camel_value))
-                .collect();
+                .collect::<Result<_, _>>()?;
 
             let auction_result = GetAuctionResult {
                 auction_data,
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use mongodb::bson::oid::ObjectId;
+use warp::http::StatusCode;
+
+use crate::auth::AuthContext;
+use crate::db::mint_jobs::find_mint_job;
+use crate::error::ServerError;
+use crate::handlers::mint_nft::MintJobResponse;
+use crate::state::AppState;
+
+#[utoipa::path(
+    get,
+    path = "/api/mint/jobs/{job_id}",
+    params(
+        ("job_id" = String, Path, description = "Id returned by POST /api/mint")
+    ),
+    responses(
+        (status = 200, description = "Current state of the mint job", body = MintJobResponse),
+        (status = 400, description = "Malformed job id", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Job of a collection the key has no access to", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Job not found", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn get_mint_job_handler(
+    job_id: String,
+    state: Arc<AppState>,
+    auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = ObjectId::parse_str(&job_id).map_err(|_| {
+        warp::reject::custom(ServerError::Validation(format!(
+            "Invalid job id: {}",
+            job_id
+        )))
+    })?;
+
+    let job = find_mint_job(state.mongo_client.clone(), id)
+        .await
+        .map_err(|e| warp::reject::custom(ServerError::from(e)))?;
+
+    // Callers only see their own jobs unless they are admins.
+    match job {
+        Some(job) if auth.is_admin() || job.requested_by == auth.subject => {
+            auth.ensure_collection(&job.collection)
+                .map_err(warp::reject::custom)?;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&MintJobResponse::from(job)),
+                StatusCode::OK,
+            ))
+        }
+        _ => Err(warp::reject::custom(ServerError::NotFound(format!(
+            "Mint job {} not found",
+            job_id
+        )))),
+    }
+}
@@ -1,14 +1,14 @@
 use crate::db::mongo::{contract_metadata, find_one_nft, MetadataAttribute};
 use crate::graph::graph::{graphql_token_owner_query, reqwest_graphql_query};
-use anyhow::anyhow;
-use mongodb::Client;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use utoipa::{IntoParams, ToResponse, ToSchema};
 use warp::http::StatusCode;
 
-use crate::constants::Constants;
-use crate::error::ServerError;
+use crate::auth::AuthContext;
+use crate::collections::Collection;
+use crate::error::{ServerError, UpstreamService};
+use crate::state::AppState;
 
 #[derive(Deserialize, IntoParams)]
 #[into_params(parameter_in = Query)]
@@ -30,39 +30,44 @@ pub struct GetNFTResult {
 
 #[utoipa::path(
     get,
-    path = "/api/token/{id}.json",
+    path = "/api/collections/{slug}/token/{id}.json",
     params(
-        ("id" = i32, Path, description = "NFT ID"),
+        ("slug" = String, Path, description = "Collection of the token. `/api/token/{id}.json` serves the default collection"),
+        ("id" = i32, Path, description = "NFT ID, or `contract-metadata` for the metadata of the collection"),
         GetNftQueryParams
     ),
     responses(
-        (status = 200, description = "Returns NFT Detail", body = [GetNFTResult])
+        (status = 200, description = "Returns NFT Detail", body = [GetNFTResult]),
+        (status = 403, description = "API key not allowed to access the collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "NFT or collection not found", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
     ),
     security(
         ("api_key" = [])
     )
 )]
 pub async fn get_nft_handler(
-    client: Arc<Client>,
+    collection: Arc<Collection>,
+    state: Arc<AppState>,
     id_json: String, // Ensure this matches the type expected by your MongoDB function
     params: GetNftQueryParams,
-    config: Arc<Constants>,
-    _auth_id: String,
+    auth: AuthContext,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    auth.ensure_collection(collection.slug())
+        .map_err(warp::reject::custom)?;
     let with_id = params.with_id.map(|v| v == "true").unwrap_or(false);
     let with_owner = params.with_owner.map(|v| v == "true").unwrap_or(false);
 
     let id_str = id_json.trim_end_matches(".json");
 
     if id_str == "contract-metadata" {
-        return match contract_metadata(client.clone()).await {
+        return match contract_metadata(state.mongo_client.clone(), collection.slug()).await {
             Ok(Some(metadata)) => Ok(warp::reply::with_status(
                 warp::reply::json(&metadata),
                 StatusCode::OK,
             )),
-            Ok(None) => Err(warp::reject::custom(ServerError::from(anyhow!(
-                "Contract metadata not found"
-            )))),
+            Ok(None) => Err(warp::reject::custom(ServerError::NotFound(
+                "Contract metadata not found".to_string(),
+            ))),
             Err(e) => Err(warp::reject::custom(ServerError::from(e))),
         };
     }
@@ -71,8 +76,13 @@ pub async fn get_nft_handler(
     match id_str.parse::<u64>() {
         Ok(token_id) => {
             // If parsing succeeds, proceed with your logic using `token_id`
-            match find_one_nft(client, token_id as u64).await {
-                // Cast to u64 if needed
+            match find_one_nft(
+                state.mongo_client.clone(),
+                &collection.config.mongo_collection,
+                token_id,
+            )
+            .await
+            {
                 Ok(Some(token)) => {
                     let mut get_nft_result = GetNFTResult {
                         token_id: None,
@@ -90,15 +100,22 @@ pub async fn get_nft_handler(
                     if with_owner {
                         let query = graphql_token_owner_query(id_str);
 
-                        let res =
-                            reqwest_graphql_query(query, config.graph_url_nft.as_str()).await?;
+                        let res = reqwest_graphql_query(
+                            &state.http_client,
+                            query,
+                            &collection.config.graph_url,
+                        )
+                        .await?;
 
-                        let token_balances = res["data"]["tokenOwnerships"]
-                            .as_array()
-                            .ok_or("Invalid response format")
-                            .map_err(|e| warp::reject::custom(ServerError::from(anyhow!(e))))?;
+                        let token_balances =
+                            res["data"]["tokenOwnerships"].as_array().ok_or_else(|| {
+                                ServerError::upstream(
+                                    UpstreamService::Graph,
+                                    "Invalid response format",
+                                )
+                            })?;
 
-                        let owner_address: &str = match token_balances.get(0) {
+                        let owner_address: &str = match token_balances.first() {
                             Some(tb) => tb["owner"].as_str().unwrap_or("default"),
                             None => "default",
                         };
@@ -110,16 +127,19 @@ pub async fn get_nft_handler(
                         StatusCode::OK,
                     ))
                 }
-                Ok(None) => Ok(warp::reply::with_status(
-                    warp::reply::json(&"NFT not found"),
-                    StatusCode::NOT_FOUND,
-                )),
+                Ok(None) => Err(warp::reject::custom(ServerError::NotFound(format!(
+                    "NFT {} not found",
+                    token_id
+                )))),
                 Err(e) => Err(warp::reject::custom(ServerError::from(e))),
             }
         }
         Err(_) => {
             // If parsing fails, reject the request
-            Err(warp::reject::not_found())
+            Err(warp::reject::custom(ServerError::NotFound(format!(
+                "Token {} not found",
+                id_str
+            ))))
         }
     }
 }
@@ -5,8 +5,8 @@ use std::sync::Arc;
 use warp::http::StatusCode;
 
 use crate::alchemy::alchemy::{alchemy_nft_sales_request, AlchemyNftSalesEndpointQueryParams};
-use crate::constants::Constants;
-use crate::error::ServerError;
+use crate::auth::AuthContext;
+use crate::state::AppState;
 
 #[derive(Deserialize, IntoParams)]
 #[into_params(parameter_in = Query)]
@@ -28,19 +28,15 @@ pub struct GetNFTMarketSalesQueryParams {
     )
 )]
 pub async fn get_nft_sales_handler(
-    // client: Arc<Client>,
+    state: Arc<AppState>,
     params: GetNFTMarketSalesQueryParams,
-    config: Arc<Constants>,
-    _auth_id: String,
+    _auth: AuthContext,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let contract_deploy_block: String = "5484602".to_string();
 
     let result_limit = params.limit.unwrap_or(10);
 
-    let token_id: Option<String> = match params.token_id {
-        Some(id) => Some(id.to_string()),
-        None => None,
-    };
+    let token_id: Option<String> = params.token_id.map(|id| id.to_string());
 
     let query_params = AlchemyNftSalesEndpointQueryParams {
         from_block: Some(contract_deploy_block),
@@ -55,11 +51,11 @@ pub async fn get_nft_sales_handler(
         page_key: params.page_key,
     };
 
-    match alchemy_nft_sales_request(query_params, config).await {
+    match alchemy_nft_sales_request(&state.http_client, query_params, state.config.clone()).await {
         Ok(response) => Ok(warp::reply::with_status(
             warp::reply::json(&response),
             StatusCode::OK,
         )),
-        Err(e) => Err(warp::reject::custom(ServerError::from(e))),
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
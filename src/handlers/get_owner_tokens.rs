@@ -1,11 +1,11 @@
-use crate::constants::Constants;
+use crate::auth::AuthContext;
+use crate::collections::Collection;
 use crate::db::mongo::find_nfts;
-use crate::error::ServerError;
+use crate::error::{ServerError, UpstreamService};
 use crate::graph::graph::{graphql_owner_tokens_query, reqwest_graphql_query};
+use crate::state::AppState;
 use std::sync::Arc;
 
-use anyhow::anyhow;
-use mongodb::Client;
 use serde::Deserialize;
 use serde_json::{self, Value};
 use utoipa::IntoParams;
@@ -14,35 +14,50 @@ use warp::http::StatusCode;
 #[derive(Deserialize, IntoParams)]
 #[into_params(parameter_in = Query)]
 pub struct GetOwnerTokensQueryParams {
-    owner_address: String,
+    /// Defaults to the caller's wallet when signed in with Ethereum.
+    owner_address: Option<String>,
 }
 
 #[utoipa::path(
     get,
-    path = "/api/owner-tokens",
-    params(GetOwnerTokensQueryParams),
+    path = "/api/collections/{slug}/owner-tokens",
+    params(
+        ("slug" = String, Path, description = "Collection to list. `/api/owner-tokens` lists the default collection"),
+        GetOwnerTokensQueryParams
+    ),
     responses(
-        (status = 200, description = "Returns all NFTs owned by address", body = [Value])
+        (status = 200, description = "Returns all NFTs of the collection owned by address", body = [Value]),
+        (status = 403, description = "API key not allowed to access the collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Unknown collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
     ),
     security(
         ("api_key" = [])
     )
 )]
 pub async fn get_owner_tokens_handler(
-    client: Arc<Client>,
+    collection: Arc<Collection>,
+    state: Arc<AppState>,
     params: GetOwnerTokensQueryParams,
-    config: Arc<Constants>,
-    _auth_id: String,
+    auth: AuthContext,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let owner_address = params.owner_address;
+    auth.ensure_collection(collection.slug())
+        .map_err(warp::reject::custom)?;
+    let owner_address = params
+        .owner_address
+        .or(auth.wallet_address)
+        .ok_or_else(|| {
+            warp::reject::custom(ServerError::Validation(
+                "owner_address is required".to_string(),
+            ))
+        })?;
     let query = graphql_owner_tokens_query(&owner_address);
 
-    let res = reqwest_graphql_query(query, config.graph_url_nft.as_str()).await?;
+    let res =
+        reqwest_graphql_query(&state.http_client, query, &collection.config.graph_url).await?;
 
     let token_balances = res["data"]["tokenOwnerships"]
         .as_array()
-        .ok_or("Invalid response format")
-        .map_err(|e| warp::reject::custom(ServerError::from(anyhow!(e))))?;
+        .ok_or_else(|| ServerError::upstream(UpstreamService::Graph, "Invalid response format"))?;
 
     // Extract token IDs from token_balances
     let token_ids: Vec<u64> = token_balances
@@ -52,9 +67,13 @@ pub async fn get_owner_tokens_handler(
         .collect();
 
     // Call find_nfts with the extracted token IDs
-    let nfts = find_nfts(client, token_ids)
-        .await
-        .map_err(|e| warp::reject::custom(ServerError::from(e)))?;
+    let nfts = find_nfts(
+        state.mongo_client.clone(),
+        &collection.config.mongo_collection,
+        token_ids,
+    )
+    .await
+    .map_err(|e| warp::reject::custom(ServerError::from(e)))?;
 
     let transformed: Vec<Value> = nfts
         .iter()
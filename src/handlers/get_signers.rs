@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use ethers::utils::format_ether;
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+
+use crate::auth::AuthContext;
+use crate::signer_balance::signer_balances;
+use crate::state::AppState;
+
+#[derive(Serialize, ToSchema)]
+pub struct SignerInfo {
+    address: String,
+    balance_wei: String,
+    balance_eth: String,
+    /// Unset when no `LOW_BALANCE_THRESHOLD_ETH` is configured.
+    low_balance_threshold_eth: Option<String>,
+    low: bool,
+    /// Mints sent from this wallet that are not settled yet.
+    pending: usize,
+    /// The wallet has a stuck transaction and gets no new mints.
+    stuck: bool,
+    checked_at: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/signers",
+    responses(
+        (status = 200, description = "Wallets of the signer pool with their last known balance", body = [SignerInfo]),
+        (status = 502, description = "RPC error", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn get_signers_handler(
+    state: Arc<AppState>,
+    _auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let pool = state.signers().map_err(warp::reject::custom)?;
+    let balances = signer_balances(&state)
+        .await
+        .map_err(warp::reject::custom)?;
+    let threshold = state.config.low_balance_threshold;
+
+    let signers: Vec<SignerInfo> = balances
+        .into_iter()
+        .map(|balance| {
+            let signer = pool.by_address(balance.address);
+            SignerInfo {
+                address: format!("{:?}", balance.address),
+                balance_wei: balance.balance.to_string(),
+                balance_eth: format_ether(balance.balance),
+                low_balance_threshold_eth: threshold.map(format_ether),
+                low: balance.is_low(threshold),
+                pending: signer.map(|signer| signer.pending()).unwrap_or_default(),
+                stuck: signer.is_some_and(|signer| signer.is_stuck()),
+                checked_at: balance.checked_at.to_rfc3339(),
+            }
+        })
+        .collect();
+    Ok(warp::reply::with_status(
+        warp::reply::json(&signers),
+        StatusCode::OK,
+    ))
+}
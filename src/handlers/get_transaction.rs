@@ -0,0 +1,154 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ethers::providers::Middleware;
+use ethers::types::H256;
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+
+use crate::auth::AuthContext;
+use crate::chain::chain::{confirmations, TransactionReceiptSchema, TxHashSchema};
+use crate::chain::registry::Chain;
+use crate::db::transactions::{
+    find_transaction, mark_transaction_mined, TransactionRecord, TxPurpose, TxStatus,
+};
+use crate::error::ServerError;
+use crate::handlers::mint_nft::format_datetime;
+use crate::state::AppState;
+
+/// A transaction sent by the server. Amounts are decimal strings in wei.
+#[derive(Serialize, ToSchema)]
+pub struct TransactionResponse {
+    hash: TxHashSchema,
+    purpose: TxPurpose,
+    /// Slug of the NFT collection the transaction mints in.
+    collection: String,
+    token_id: Option<u64>,
+    mint_job_id: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    nonce: Option<u64>,
+    gas_limit: Option<String>,
+    /// Set for legacy transactions.
+    gas_price: Option<String>,
+    max_fee_per_gas: Option<String>,
+    max_priority_fee_per_gas: Option<String>,
+    status: TxStatus,
+    block_number: Option<u64>,
+    /// Blocks on top of and including the one that mined the transaction, 0 while pending.
+    confirmations: u64,
+    receipt: Option<TransactionReceiptSchema>,
+    /// Hash of the stuck transaction this one replaces.
+    replaces: Option<String>,
+    /// Set once the transaction was sped up or cancelled.
+    replaced_by: Option<String>,
+    /// Link to the transaction on the block explorer of the NFT chain, when one is configured.
+    explorer_url: Option<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+impl TransactionResponse {
+    pub fn new(record: TransactionRecord, chain: &Chain) -> Self {
+        // Stored by this server from an `H256`, so always valid.
+        let tx_hash = H256::from_str(&record.tx_hash).unwrap_or_default();
+        TransactionResponse {
+            hash: TxHashSchema::from(tx_hash),
+            purpose: record.purpose,
+            collection: record.collection,
+            token_id: record.token_id,
+            mint_job_id: record.mint_job_id.map(|id| id.to_hex()),
+            from: record.from,
+            to: record.to,
+            nonce: record.nonce,
+            gas_limit: record.gas_limit,
+            gas_price: record.gas_price,
+            max_fee_per_gas: record.max_fee_per_gas,
+            max_priority_fee_per_gas: record.max_priority_fee_per_gas,
+            status: record.status,
+            block_number: record.block_number,
+            confirmations: record.confirmations,
+            receipt: record.receipt,
+            replaces: record.replaces,
+            replaced_by: record.replaced_by,
+            explorer_url: chain.tx_url(tx_hash),
+            created_at: format_datetime(record.created_at),
+            updated_at: format_datetime(record.updated_at),
+        }
+    }
+}
+
+pub fn parse_tx_hash(hash: &str) -> Result<H256, ServerError> {
+    H256::from_str(hash)
+        .map_err(|_| ServerError::Validation(format!("Invalid transaction hash: {}", hash)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tx/{hash}",
+    params(
+        ("hash" = String, Path, description = "0x-prefixed transaction hash")
+    ),
+    responses(
+        (status = 200, description = "Stored transaction with its live status", body = TransactionResponse),
+        (status = 400, description = "Malformed hash", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Transaction of a collection the key has no access to", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Transaction not sent by this server", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "RPC error", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn get_transaction_handler(
+    hash: String,
+    state: Arc<AppState>,
+    auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let tx_hash = parse_tx_hash(&hash).map_err(warp::reject::custom)?;
+    let tx_hash_hex = format!("{:?}", tx_hash);
+
+    let mut record = find_transaction(state.mongo_client.clone(), &tx_hash_hex)
+        .await
+        .map_err(|e| warp::reject::custom(ServerError::from(e)))?
+        .ok_or_else(|| {
+            warp::reject::custom(ServerError::NotFound(format!(
+                "Transaction {} not found",
+                tx_hash_hex
+            )))
+        })?;
+    auth.ensure_collection(&record.collection)
+        .map_err(warp::reject::custom)?;
+
+    // The stored record lags behind the chain, so refresh it from the node.
+    let chain = state.chains.nft();
+    let receipt = chain
+        .provider
+        .get_transaction_receipt(tx_hash)
+        .await
+        .map_err(|e| warp::reject::custom(ServerError::from(e)))?;
+
+    if let Some(receipt) = receipt {
+        let depth = confirmations(&chain.provider, &receipt)
+            .await
+            .map_err(warp::reject::custom)?;
+        let receipt = TransactionReceiptSchema::from(receipt);
+
+        if let Err(e) =
+            mark_transaction_mined(state.mongo_client.clone(), &tx_hash_hex, &receipt, depth).await
+        {
+            eprintln!("Failed to update transaction {}: {}", tx_hash_hex, e);
+        }
+
+        record.status = TxStatus::of_receipt(&receipt);
+        record.block_number = receipt.block_number;
+        record.confirmations = depth;
+        record.receipt = Some(receipt);
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&TransactionResponse::new(record, chain)),
+        StatusCode::OK,
+    ))
+}
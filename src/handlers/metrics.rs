@@ -0,0 +1,113 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use ethers::types::Address;
+use ethers::utils::format_ether;
+
+use crate::auth::AuthContext;
+use crate::state::AppState;
+
+/// Appends one gauge with a sample per `(labels, value)`, the labels already rendered
+/// as `name="value",...`.
+fn write_gauge(body: &mut String, name: &str, help: &str, samples: &[(String, String)]) {
+    let _ = writeln!(body, "# HELP {} {}", name, help);
+    let _ = writeln!(body, "# TYPE {} gauge", name);
+    for (labels, value) in samples {
+        let _ = writeln!(body, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+fn address_label(address: &Address) -> String {
+    format!("address=\"{:?}\"", address)
+}
+
+/// Prometheus text exposition of the signer pool. Balances are reported once the
+/// balance monitor has run. Admin only, since it lists the wallets and RPC endpoints.
+pub async fn metrics_handler(
+    state: Arc<AppState>,
+    _auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let balances = state.signer_balances.read().unwrap().clone();
+    let signers = state
+        .signers()
+        .map(|pool| pool.signers())
+        .unwrap_or_default();
+
+    let mut body = String::new();
+    // Wei do not fit in an f64 exactly, so the gauge is in ETH.
+    write_gauge(
+        &mut body,
+        "snapit_signer_balance_eth",
+        "Balance of the signing wallet in ETH.",
+        &balances
+            .iter()
+            .map(|b| (address_label(&b.address), format_ether(b.balance)))
+            .collect::<Vec<_>>(),
+    );
+    write_gauge(
+        &mut body,
+        "snapit_signer_balance_checked_timestamp_seconds",
+        "When the balance was last read.",
+        &balances
+            .iter()
+            .map(|b| {
+                (
+                    address_label(&b.address),
+                    b.checked_at.timestamp().to_string(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    write_gauge(
+        &mut body,
+        "snapit_signer_pending_mints",
+        "Mints sent from the wallet that are not settled yet.",
+        &signers
+            .iter()
+            .map(|s| (address_label(&s.address), s.pending().to_string()))
+            .collect::<Vec<_>>(),
+    );
+    write_gauge(
+        &mut body,
+        "snapit_signer_stuck",
+        "1 while the wallet has a stuck transaction and gets no new mints.",
+        &signers
+            .iter()
+            .map(|s| {
+                (
+                    address_label(&s.address),
+                    u8::from(s.is_stuck()).to_string(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    // Endpoints are named by their position in `rpc_urls`; the URLs may hold API keys.
+    write_gauge(
+        &mut body,
+        "snapit_rpc_endpoint_healthy",
+        "0 while the RPC endpoint is skipped after failing.",
+        &state
+            .chains
+            .all()
+            .flat_map(|chain| {
+                chain
+                    .endpoint_health()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, healthy)| {
+                        (
+                            format!("chain=\"{}\",endpoint=\"{}\"", chain.config.name, index),
+                            u8::from(healthy).to_string(),
+                        )
+                    })
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    Ok(warp::reply::with_header(
+        body,
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
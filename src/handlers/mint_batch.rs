@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ethers::types::Address;
+use futures::future::join_all;
+use mongodb::bson::oid::ObjectId;
+use mongodb::error::BulkWriteError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+
+use crate::auth::AuthContext;
+use crate::chain::chain::refuse_if_fee_above_cap;
+use crate::collections::Collection;
+use crate::db::mint_jobs::{insert_mint_jobs, MintJob};
+use crate::db::mongo::{add_pending_nfts, remove_pending_nft, AddNFTInput, DUPLICATE_KEY_CODE};
+use crate::db::token_ids::{delete_token_id_reservation, expire_token_id_reservation};
+use crate::error::{ProblemDetails, ServerError, UpstreamService};
+use crate::handlers::mint_nft::{mint_mock_response, MintJobResponse, MintUniqueTokenRequest};
+use crate::idempotency::idempotent;
+use crate::signer_balance::ensure_signer_can_afford;
+use crate::state::AppState;
+use crate::token_ids::{allocate_token_id, ensure_token_id_available};
+
+const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct MintBatchRequest {
+    pub items: Vec<MintUniqueTokenRequest>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MintBatchItemResult {
+    /// Position of the item in the request.
+    index: usize,
+    /// The requested id, or the allocated one when the item did not name one.
+    token_id: Option<u64>,
+    /// The queued job, when the item was accepted.
+    job: Option<MintJobResponse>,
+    /// Why the item was rejected.
+    error: Option<ProblemDetails>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MintBatchResponse {
+    queued: usize,
+    rejected: usize,
+    results: Vec<MintBatchItemResult>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/collections/{slug}/mint/batch",
+    request_body = MintBatchRequest,
+    params(
+        ("slug" = String, Path, description = "Collection to mint in. `/api/mint/batch` mints in the default collection"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key and body replay the first response instead of minting again")
+    ),
+    responses(
+        (status = 202, description = "Valid items queued, invalid ones reported per item", body = MintBatchResponse),
+        (status = 400, description = "Empty or oversized batch", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "API key not allowed to access the collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Unknown collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Idempotency-Key reused with a different body or still in progress", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Network fee above the cap (`FEE_ABOVE_CAP`) or signer balance too low for the gas (`INSUFFICIENT_SIGNER_FUNDS`)", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn mint_batch_handler(
+    collection: Arc<Collection>,
+    req: MintBatchRequest,
+    idempotency_key: Option<String>,
+    state: Arc<AppState>,
+    auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    auth.ensure_collection(collection.slug())
+        .map_err(warp::reject::custom)?;
+    if req.items.is_empty() || req.items.len() > MAX_BATCH_SIZE {
+        return Err(warp::reject::custom(ServerError::Validation(format!(
+            "A batch must contain 1 to {} items",
+            MAX_BATCH_SIZE
+        ))));
+    }
+
+    if auth.is_sandbox() {
+        let (status, response) = mint_batch(req.items, &state, &collection, &auth)
+            .await
+            .map_err(warp::reject::custom)?;
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&response),
+            status,
+        ));
+    }
+
+    let (status, response) = idempotent(
+        &state,
+        &auth.subject,
+        idempotency_key,
+        &format!("POST /api/collections/{}/mint/batch", collection.slug()),
+        &req,
+        || mint_batch(req.items.clone(), &state, &collection, &auth),
+    )
+    .await
+    .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
+}
+
+async fn mint_batch(
+    items: Vec<MintUniqueTokenRequest>,
+    state: &AppState,
+    collection: &Collection,
+    auth: &AuthContext,
+) -> Result<(StatusCode, Value), ServerError> {
+    if !auth.is_sandbox() {
+        collection.ensure_mintable()?;
+        refuse_if_fee_above_cap(&state.chains.nft().provider, &state.config.gas_policy).await?;
+        ensure_signer_can_afford(state, items.len()).await?;
+    }
+
+    let mut errors = validate_items(&items);
+
+    // Sandbox batches are never queued, so there is nothing to check on chain.
+    if !auth.is_sandbox() {
+        check_already_minted(&items, &mut errors, state, collection).await;
+    }
+
+    let mut token_ids: Vec<Option<u64>> = items.iter().map(|item| item.token_id).collect();
+    if !auth.is_sandbox() {
+        allocate_missing_token_ids(
+            &mut token_ids,
+            &mut errors,
+            state,
+            collection,
+            &auth.subject,
+        )
+        .await;
+    }
+
+    let accepted: Vec<usize> = (0..items.len()).filter(|i| errors[*i].is_none()).collect();
+    let mut jobs: HashMap<usize, MintJob> = accepted
+        .iter()
+        .map(|&i| {
+            let item = items[i].clone();
+            let job = MintJob::new(
+                collection.slug().to_string(),
+                item.owner_address,
+                token_ids[i].unwrap_or_default(),
+                item.metadata,
+                item.data,
+                auth.subject.clone(),
+            );
+            (i, job)
+        })
+        .collect();
+
+    if !auth.is_sandbox() && !accepted.is_empty() {
+        let allocated: Vec<bool> = items.iter().map(|item| item.token_id.is_none()).collect();
+        enqueue_jobs(
+            &accepted,
+            &allocated,
+            &mut jobs,
+            &mut errors,
+            state,
+            collection,
+        )
+        .await;
+    }
+
+    let results: Vec<MintBatchItemResult> = items
+        .iter()
+        .enumerate()
+        .map(|(index, _)| {
+            let error = errors[index].take();
+            let job = match error {
+                Some(_) => None,
+                None if auth.is_sandbox() => jobs.remove(&index).map(mint_mock_response),
+                None => jobs.remove(&index).map(MintJobResponse::from),
+            };
+            MintBatchItemResult {
+                index,
+                token_id: token_ids[index],
+                job,
+                error: error.as_ref().map(ProblemDetails::from),
+            }
+        })
+        .collect();
+
+    let queued = results.iter().filter(|r| r.job.is_some()).count();
+    let response = MintBatchResponse {
+        queued,
+        rejected: results.len() - queued,
+        results,
+    };
+    let response =
+        serde_json::to_value(response).map_err(|e| ServerError::Internal(e.to_string()))?;
+    Ok((StatusCode::ACCEPTED, response))
+}
+
+/// Checks that need neither the database nor the chain.
+fn validate_items(items: &[MintUniqueTokenRequest]) -> Vec<Option<ServerError>> {
+    let mut occurrences: HashMap<u64, usize> = HashMap::new();
+    for token_id in items.iter().filter_map(|item| item.token_id) {
+        *occurrences.entry(token_id).or_default() += 1;
+    }
+
+    items
+        .iter()
+        .map(|item| {
+            if Address::from_str(&item.owner_address).is_err() {
+                Some(ServerError::Validation(format!(
+                    "Invalid owner address: {}",
+                    item.owner_address
+                )))
+            } else if let Some(Err(e)) = item.data.as_ref().map(|data| data.to_bytes()) {
+                Some(e)
+            } else if item.dry_run.unwrap_or(false) {
+                Some(ServerError::Validation(
+                    "dry_run is not supported in batches, use /api/mint/simulate".to_string(),
+                ))
+            } else {
+                item.token_id
+                    .filter(|id| occurrences[id] > 1)
+                    .map(|token_id| {
+                        ServerError::Validation(format!(
+                            "Token {} appears more than once in the batch",
+                            token_id
+                        ))
+                    })
+            }
+        })
+        .collect()
+}
+
+/// Rejects requested tokens that are owned on chain, have metadata or a mint in flight.
+async fn check_already_minted(
+    items: &[MintUniqueTokenRequest],
+    errors: &mut [Option<ServerError>],
+    state: &AppState,
+    collection: &Collection,
+) {
+    let checks = join_all(items.iter().zip(errors.iter()).map(|(item, error)| {
+        let token_id = item.token_id.filter(|_| error.is_none());
+        async move {
+            ensure_token_id_available(state, collection, token_id?)
+                .await
+                .err()
+        }
+    }))
+    .await;
+
+    for (error, check) in errors.iter_mut().zip(checks) {
+        if check.is_some() {
+            *error = check;
+        }
+    }
+}
+
+/// Allocates ids, one at a time, for the valid items that did not name one.
+async fn allocate_missing_token_ids(
+    token_ids: &mut [Option<u64>],
+    errors: &mut [Option<ServerError>],
+    state: &AppState,
+    collection: &Collection,
+    reserved_by: &str,
+) {
+    for (token_id, error) in token_ids.iter_mut().zip(errors.iter_mut()) {
+        if token_id.is_some() || error.is_some() {
+            continue;
+        }
+        match allocate_token_id(state, collection, reserved_by).await {
+            Ok(allocated) => *token_id = Some(allocated),
+            Err(e) => *error = Some(e),
+        }
+    }
+}
+
+/// Writes pending metadata in bulk, then queues a job for every token that was written.
+/// Ids allocated for items that could not be queued are released again.
+async fn enqueue_jobs(
+    accepted: &[usize],
+    allocated: &[bool],
+    jobs: &mut HashMap<usize, MintJob>,
+    errors: &mut [Option<ServerError>],
+    state: &AppState,
+    collection: &Collection,
+) {
+    let tokens: Vec<(ObjectId, AddNFTInput)> = accepted
+        .iter()
+        .map(|i| {
+            let token = AddNFTInput {
+                token_id: jobs[i].token_id,
+                metadata: jobs[i].metadata.clone(),
+            };
+            (jobs[i].id, token)
+        })
+        .collect();
+
+    let failed: HashMap<usize, Option<BulkWriteError>> = match add_pending_nfts(
+        state.mongo_client.clone(),
+        &collection.config.mongo_collection,
+        &tokens,
+    )
+    .await
+    {
+        Ok(failed) => failed
+            .into_iter()
+            .map(|(position, error)| (position, Some(error)))
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to write pending metadata for the batch: {}", e);
+            (0..tokens.len()).map(|position| (position, None)).collect()
+        }
+    };
+    for (position, write_error) in failed {
+        let index = accepted[position];
+        let Some(job) = jobs.remove(&index) else {
+            continue;
+        };
+        let taken = match &write_error {
+            Some(write_error) if write_error.code == DUPLICATE_KEY_CODE => true,
+            Some(write_error) => {
+                eprintln!(
+                    "Failed to write pending metadata for batch item {}: {}",
+                    index, write_error.message
+                );
+                false
+            }
+            None => false,
+        };
+        errors[index] = Some(if taken {
+            ServerError::Conflict(format!(
+                "Token {} already has metadata from another mint",
+                job.token_id
+            ))
+        } else {
+            ServerError::upstream(
+                UpstreamService::Database,
+                "Could not write the pending metadata",
+            )
+        });
+        if allocated[index] {
+            release_token_id(state, collection, job.token_id, taken).await;
+        }
+    }
+
+    // One wallet sends the whole batch, so its nonces follow the order of the items.
+    let batch_id = ObjectId::new();
+    let signer = state.signers().ok().map(|signers| {
+        signers
+            .select()
+            .unwrap_or_else(|| signers.primary().clone())
+    });
+    for job in jobs.values_mut() {
+        job.batch_id = Some(batch_id);
+        job.signer = signer
+            .as_ref()
+            .map(|signer| format!("{:?}", signer.address));
+    }
+
+    let mut written: Vec<usize> = jobs.keys().copied().collect();
+    written.sort_unstable();
+    let batch: Vec<MintJob> = written.iter().map(|i| jobs[i].clone()).collect();
+
+    match insert_mint_jobs(state.mongo_client.clone(), &batch).await {
+        Ok(()) => state.mint_job_notify.notify_one(),
+        Err(e) => {
+            eprintln!("Failed to queue mint jobs: {}", e);
+            for index in written {
+                if let Some(job) = jobs.remove(&index) {
+                    if let Err(e) = remove_pending_nft(
+                        state.mongo_client.clone(),
+                        &collection.config.mongo_collection,
+                        job.token_id,
+                    )
+                    .await
+                    {
+                        eprintln!(
+                            "Failed to roll back pending metadata for token {}: {}",
+                            job.token_id, e
+                        );
+                    }
+                    if allocated[index] {
+                        release_token_id(state, collection, job.token_id, false).await;
+                    }
+                }
+                errors[index] = Some(ServerError::upstream(
+                    UpstreamService::Database,
+                    "Could not queue the mint job",
+                ));
+            }
+        }
+    }
+}
+
+/// Hands an allocated id out again, unless another document already `taken` it.
+async fn release_token_id(state: &AppState, collection: &Collection, token_id: u64, taken: bool) {
+    let released = if taken {
+        delete_token_id_reservation(state.mongo_client.clone(), collection.slug(), token_id).await
+    } else {
+        expire_token_id_reservation(state.mongo_client.clone(), collection.slug(), token_id).await
+    };
+    if let Err(e) = released {
+        eprintln!(
+            "Failed to release the reservation of token {}: {}",
+            token_id, e
+        );
+    }
+}
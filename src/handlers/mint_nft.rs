@@ -1,111 +1,239 @@
-use crate::chain::chain::{SendTransactionResult, TransactionReceiptSchema, TxHashSchema};
-use crate::chain::mint::mint_nft;
-use crate::constants::Constants;
-use crate::db::mongo::{add_nft, AddNFTInput, Metadata};
+use crate::auth::AuthContext;
+use crate::chain::chain::{refuse_if_fee_above_cap, TransactionReceiptSchema};
+use crate::chain::mint::{simulate_mint, MintData};
+use crate::collections::Collection;
+use crate::db::mint_jobs::{insert_mint_job, MintJob, MintJobStatus};
+use crate::db::mongo::Metadata;
+use crate::db::token_ids::expire_token_id_reservation;
 use crate::error::ServerError;
-use ethers::types::H256;
-use mongodb::bson::doc;
-use mongodb::Client;
+use crate::idempotency::idempotent;
+use crate::signer_balance::ensure_signer_can_afford;
+use crate::state::AppState;
+use crate::token_ids::{allocate_token_id, ensure_token_id_available};
+use ethers::types::Address;
+use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::str::FromStr;
 use std::sync::Arc;
 use utoipa::ToSchema;
 use warp::http::StatusCode;
 
-#[derive(Deserialize, Clone, ToSchema)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct MintUniqueTokenRequest {
     pub owner_address: String,
-    pub token_id: u64,
+    /// Omit to mint the next free id; the chosen id is returned in the response.
+    pub token_id: Option<u64>,
     pub metadata: Metadata, // Accepting metadata as a structured object
-    pub wait_confirmation: Option<bool>,
+    /// Forwarded to the receiver's `onERC721Received` through `mint(address,uint256,bytes)`.
+    pub data: Option<MintData>,
+    /// Only simulate the mint and report whether it would succeed and what it would cost.
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MintJobResponse {
+    /// Poll `GET /api/mint/jobs/{job_id}` for progress.
+    job_id: String,
+    status: MintJobStatus,
+    /// Slug of the collection the token is minted in.
+    collection: String,
+    owner_address: String,
+    token_id: u64,
+    /// Wallet that sent the mint.
+    signer: Option<String>,
+    tx_hash: Option<String>,
+    receipt: Option<TransactionReceiptSchema>,
+    error_code: Option<String>,
+    error: Option<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+impl From<MintJob> for MintJobResponse {
+    fn from(job: MintJob) -> Self {
+        MintJobResponse {
+            job_id: job.id.to_hex(),
+            status: job.status,
+            collection: job.collection,
+            owner_address: job.owner_address,
+            token_id: job.token_id,
+            signer: job.signer,
+            tx_hash: job.tx_hash,
+            receipt: job.receipt,
+            error_code: job.error_code,
+            error: job.error,
+            created_at: format_datetime(job.created_at),
+            updated_at: format_datetime(job.updated_at),
+        }
+    }
+}
+
+pub fn format_datetime(datetime: DateTime) -> String {
+    datetime.try_to_rfc3339_string().unwrap_or_default()
 }
 
 #[utoipa::path(
     post,
-    path = "/api/mint",
+    path = "/api/collections/{slug}/mint",
     request_body = MintUniqueTokenRequest,
+    params(
+        ("slug" = String, Path, description = "Collection to mint in. `/api/mint` mints in the default collection"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key and body replay the first response instead of minting again")
+    ),
     responses(
-        (status = 200, description = "Mint NFT successfully", body = MintNFTSuccessResponse), // Define MintNftResponse struct with ToSchema
-        (status = 400, description = "Bad Request"),
+        (status = 200, description = "Dry run result, nothing was sent or stored", body = crate::chain::mint::MintSimulation),
+        (status = 202, description = "Mint queued", body = MintJobResponse),
+        (status = 400, description = "Bad Request", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "API key not allowed to access the collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Unknown collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Token already minted (the owner is in `args`), already being minted, or Idempotency-Key reused with a different body", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Network fee above the cap (`FEE_ABOVE_CAP`) or signer balance too low for the gas (`INSUFFICIENT_SIGNER_FUNDS`)", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
     ),
     security(
         ("api_key" = [])
     )
 )]
 pub async fn mint_nft_handler(
+    collection: Arc<Collection>,
     req: MintUniqueTokenRequest,
-    mongo_client: Arc<Client>,
-    config: Arc<Constants>,
-    auth_id: String,
+    idempotency_key: Option<String>,
+    state: Arc<AppState>,
+    auth: AuthContext,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    if auth_id == "test" {
-        let mock_response = mint_mock_response(req.clone());
+    auth.ensure_collection(collection.slug())
+        .map_err(warp::reject::custom)?;
+    if Address::from_str(&req.owner_address).is_err() {
+        return Err(warp::reject::custom(ServerError::Validation(format!(
+            "Invalid owner address: {}",
+            req.owner_address
+        ))));
+    }
+    if let Some(data) = &req.data {
+        data.to_bytes().map_err(warp::reject::custom)?;
+    }
+
+    if req.dry_run.unwrap_or(false) {
+        // Allocating an id would write to Mongo, which a dry run must not do.
+        let token_id = req.token_id.ok_or_else(|| {
+            warp::reject::custom(ServerError::Validation(
+                "token_id is required for a dry run".to_string(),
+            ))
+        })?;
+        collection.ensure_mintable().map_err(warp::reject::custom)?;
+        let simulation = simulate_mint(
+            &collection,
+            &req.owner_address,
+            token_id,
+            req.data.as_ref(),
+            state.config.clone(),
+            state.ethers_client().map_err(warp::reject::custom)?.clone(),
+        )
+        .await
+        .map_err(warp::reject::custom)?;
         return Ok(warp::reply::with_status(
-            warp::reply::json(&mock_response),
-            StatusCode::CREATED,
+            warp::reply::json(&simulation),
+            StatusCode::OK,
         ));
     }
-    let tx_result = mint_nft(req.clone(), config)
-        .await
-        .map_err(|e| warp::reject::custom(ServerError::from(e)))?;
-
-    let token_nft = AddNFTInput {
-        token_id: req.token_id,
-        metadata: req.metadata,
-    };
 
-    match add_nft(mongo_client, token_nft.clone()).await {
-        Ok(()) => {
-            let success_response = MintNFTSuccessResponse {
-                nft_details: token_nft,
-                tx_result,
-            };
-            Ok(warp::reply::with_status(
-                warp::reply::json(&success_response),
-                StatusCode::CREATED,
-            ))
-        }
-        Err(e) => Err(warp::reject::custom(ServerError::from(e))),
+    if auth.is_sandbox() {
+        let job = MintJob::new(
+            collection.slug().to_string(),
+            req.owner_address,
+            req.token_id.unwrap_or_default(),
+            req.metadata,
+            req.data,
+            auth.subject,
+        );
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&mint_mock_response(job)),
+            StatusCode::ACCEPTED,
+        ));
     }
-}
 
-#[derive(Serialize, ToSchema)]
-pub struct MintNFTSuccessResponse {
-    nft_details: AddNFTInput, // Replace `YourNftDetailsType` with the actual type
-    tx_result: SendTransactionResult,
+    let (status, response) = idempotent(
+        &state,
+        &auth.subject,
+        idempotency_key,
+        &format!("POST /api/collections/{}/mint", collection.slug()),
+        &req,
+        || enqueue_mint(req.clone(), &state, &collection, &auth.subject),
+    )
+    .await
+    .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
 }
 
-fn mint_mock_response(req: MintUniqueTokenRequest) -> MintNFTSuccessResponse {
-    let tx_result: SendTransactionResult;
-    let wait_confirm_for_mock = req.wait_confirmation.clone().unwrap_or(false);
-    if wait_confirm_for_mock {
-        let mock_receipt = TransactionReceiptSchema {
-            transaction_hash: "0xTRXHASH".to_string(),
-            transaction_index: 1,
-            block_hash: Some("0xBLOCKHASH".to_string()),
-            block_number: Some(1),
-            from: "0xTRXSENDER".to_string(),
-            to: Some("0xTRXRECEIVER".to_string()),
-            cumulative_gas_used: "CUMULATIVEGASUSED".to_string(),
-            gas_used: Some("GASUSED".to_string()),
-            contract_address: Some("0xCONTRACTADDRESS".to_string()),
-            status: Some(0),
-            effective_gas_price: Some("GASPRICEinGWEI".to_string()),
-        };
+async fn enqueue_mint(
+    req: MintUniqueTokenRequest,
+    state: &AppState,
+    collection: &Collection,
+    requested_by: &str,
+) -> Result<(StatusCode, Value), ServerError> {
+    collection.ensure_mintable()?;
+    refuse_if_fee_above_cap(&state.chains.nft().provider, &state.config.gas_policy).await?;
+    ensure_signer_can_afford(state, 1).await?;
+
+    let token_id = match req.token_id {
+        Some(token_id) => {
+            ensure_token_id_available(state, collection, token_id).await?;
+            token_id
+        }
+        None => allocate_token_id(state, collection, requested_by).await?,
+    };
+    let allocated = req.token_id.is_none();
+    let job = MintJob::new(
+        collection.slug().to_string(),
+        req.owner_address,
+        token_id,
+        req.metadata,
+        req.data,
+        requested_by.to_string(),
+    );
 
-        tx_result = SendTransactionResult::Receipt(mock_receipt);
-    } else {
-        tx_result =
-            SendTransactionResult::Hash(TxHashSchema::from(H256::from_low_u64_be(123456789)));
+    if let Err(e) = insert_mint_job(state.mongo_client.clone(), &job).await {
+        // Hand the allocated id out again right away instead of holding it for an hour.
+        if allocated {
+            if let Err(e) =
+                expire_token_id_reservation(state.mongo_client.clone(), collection.slug(), token_id)
+                    .await
+            {
+                eprintln!(
+                    "Failed to release the reservation of token {}: {}",
+                    token_id, e
+                );
+            }
+        }
+        return Err(e.into());
     }
+    state.mint_job_notify.notify_one();
 
-    let token_nft = AddNFTInput {
-        token_id: req.token_id,
-        metadata: req.metadata,
-    };
+    let response = serde_json::to_value(MintJobResponse::from(job))
+        .map_err(|e| ServerError::Internal(e.to_string()))?;
+    Ok((StatusCode::ACCEPTED, response))
+}
 
-    let mock_response = MintNFTSuccessResponse {
-        nft_details: token_nft.clone(),
-        tx_result,
-    };
-    mock_response
+/// Sandbox mints are never queued; they report an already confirmed job.
+pub fn mint_mock_response(mut job: MintJob) -> MintJobResponse {
+    job.status = MintJobStatus::Confirmed;
+    job.tx_hash = Some("0xTRXHASH".to_string());
+    job.receipt = Some(TransactionReceiptSchema {
+        transaction_hash: "0xTRXHASH".to_string(),
+        transaction_index: 1,
+        block_hash: Some("0xBLOCKHASH".to_string()),
+        block_number: Some(1),
+        from: "0xTRXSENDER".to_string(),
+        to: Some("0xTRXRECEIVER".to_string()),
+        cumulative_gas_used: "CUMULATIVEGASUSED".to_string(),
+        gas_used: Some("GASUSED".to_string()),
+        contract_address: Some("0xCONTRACTADDRESS".to_string()),
+        status: Some(1),
+        effective_gas_price: Some("GASPRICEinGWEI".to_string()),
+    });
+    MintJobResponse::from(job)
 }
@@ -1,5 +1,15 @@
+pub mod api_keys;
+pub mod events;
 pub mod get_auction;
+pub mod get_mint_job;
 pub mod get_nft;
 pub mod get_nft_sales;
 pub mod get_owner_tokens;
+pub mod get_signers;
+pub mod get_transaction;
+pub mod metrics;
+pub mod mint_batch;
 pub mod mint_nft;
+pub mod replace_transaction;
+pub mod simulate_mint;
+pub mod siwe;
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use warp::http::StatusCode;
+
+use crate::auth::AuthContext;
+use crate::chain::chain::Replacement;
+use crate::handlers::get_transaction::{parse_tx_hash, TransactionResponse};
+use crate::state::AppState;
+use crate::tx_replacement::replace_stuck_transaction;
+
+async fn replace(
+    hash: String,
+    state: Arc<AppState>,
+    replacement: Replacement,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    let tx_hash = parse_tx_hash(&hash).map_err(warp::reject::custom)?;
+
+    let record = replace_stuck_transaction(&state, tx_hash, replacement)
+        .await
+        .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&TransactionResponse::new(record, state.chains.nft())),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/tx/{hash}/speed-up",
+    params(
+        ("hash" = String, Path, description = "Hash of a pending transaction sent by this server")
+    ),
+    responses(
+        (status = 202, description = "Replacement with the same nonce and higher fees sent", body = TransactionResponse),
+        (status = 400, description = "Malformed hash", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Transaction not sent by this server", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Transaction already mined, replaced or dropped", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn speed_up_transaction_handler(
+    hash: String,
+    state: Arc<AppState>,
+    _auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    replace(hash, state, Replacement::SpeedUp).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/tx/{hash}/cancel",
+    params(
+        ("hash" = String, Path, description = "Hash of a pending transaction sent by this server")
+    ),
+    responses(
+        (status = 202, description = "Zero-value self-transfer with the same nonce and higher fees sent", body = TransactionResponse),
+        (status = 400, description = "Malformed hash", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Transaction not sent by this server", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Transaction already mined, replaced or dropped", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn cancel_transaction_handler(
+    hash: String,
+    state: Arc<AppState>,
+    _auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    replace(hash, state, Replacement::Cancel).await
+}
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+
+use crate::auth::AuthContext;
+use crate::chain::mint::{simulate_mint, MintData};
+use crate::collections::Collection;
+use crate::state::AppState;
+
+#[derive(Deserialize, ToSchema)]
+pub struct SimulateMintRequest {
+    pub owner_address: String,
+    pub token_id: u64,
+    pub data: Option<MintData>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/collections/{slug}/mint/simulate",
+    request_body = SimulateMintRequest,
+    params(
+        ("slug" = String, Path, description = "Collection to mint in. `/api/mint/simulate` uses the default collection")
+    ),
+    responses(
+        (status = 200, description = "Whether the mint would succeed, its gas estimate and cost", body = crate::chain::mint::MintSimulation),
+        (status = 400, description = "Bad Request", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "API key not allowed to access the collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Unknown collection", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "RPC request failed", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+pub async fn simulate_mint_handler(
+    collection: Arc<Collection>,
+    req: SimulateMintRequest,
+    state: Arc<AppState>,
+    auth: AuthContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    auth.ensure_collection(collection.slug())
+        .map_err(warp::reject::custom)?;
+    collection.ensure_mintable().map_err(warp::reject::custom)?;
+    let simulation = simulate_mint(
+        &collection,
+        &req.owner_address,
+        req.token_id,
+        req.data.as_ref(),
+        state.config.clone(),
+        state.ethers_client().map_err(warp::reject::custom)?.clone(),
+    )
+    .await
+    .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&simulation),
+        StatusCode::OK,
+    ))
+}
@@ -0,0 +1,132 @@
+use chrono::Utc;
+use ethers::core::rand::{thread_rng, RngCore};
+use ethers::utils::{hex, to_checksum};
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+
+use crate::auth::{create_session_jwt, SIWE_SESSION_TTL_SECONDS};
+use crate::db::siwe_nonces::{consume_siwe_nonce, insert_siwe_nonce};
+use crate::error::ServerError;
+use crate::siwe::SiweMessage;
+use crate::state::AppState;
+
+/// How long a nonce may be used to sign in.
+const NONCE_TTL_SECONDS: i64 = 600;
+
+#[derive(Serialize, ToSchema)]
+pub struct SiweNonceResponse {
+    nonce: String,
+    expires_at: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SiweVerifyRequest {
+    /// The EIP-4361 message exactly as it was signed.
+    pub message: String,
+    /// 0x-prefixed 65-byte signature of `message` (EIP-191 personal_sign).
+    pub signature: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SiweVerifyResponse {
+    /// Session JWT to send as `Authorization: Bearer <token>`.
+    token: String,
+    address: String,
+    expires_at: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/nonce",
+    responses(
+        (status = 200, description = "Returns a single-use nonce for a SIWE message", body = SiweNonceResponse)
+    )
+)]
+pub async fn siwe_nonce_handler(state: Arc<AppState>) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut bytes = [0u8; 16];
+    thread_rng().fill_bytes(&mut bytes);
+    let nonce = hex::encode(bytes);
+
+    let expires_at = Utc::now() + chrono::Duration::seconds(NONCE_TTL_SECONDS);
+
+    insert_siwe_nonce(
+        state.mongo_client.clone(),
+        &nonce,
+        DateTime::from_millis(expires_at.timestamp_millis()),
+    )
+    .await
+    .map_err(|e| warp::reject::custom(ServerError::from(e)))?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&SiweNonceResponse {
+            nonce,
+            expires_at: expires_at.to_rfc3339(),
+        }),
+        StatusCode::OK,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/siwe",
+    request_body = SiweVerifyRequest,
+    responses(
+        (status = 200, description = "Signature verified, session issued", body = SiweVerifyResponse),
+        (status = 400, description = "Malformed message or signature", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Verification failed", body = crate::error::ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn siwe_verify_handler(
+    req: SiweVerifyRequest,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let domain = state.config.siwe_domain.as_deref().ok_or_else(|| {
+        warp::reject::custom(ServerError::Unavailable(
+            "Sign-In With Ethereum is not configured".to_string(),
+        ))
+    })?;
+
+    let message = SiweMessage::from_str(&req.message).map_err(warp::reject::custom)?;
+    message
+        .verify(
+            &req.message,
+            &req.signature,
+            domain,
+            state.config.nft_chain().chain_id,
+        )
+        .map_err(warp::reject::custom)?;
+
+    // Consume the nonce last so a failed verification does not burn it.
+    let nonce_valid = consume_siwe_nonce(state.mongo_client.clone(), &message.nonce)
+        .await
+        .map_err(|e| warp::reject::custom(ServerError::from(e)))?;
+    if !nonce_valid {
+        return Err(warp::reject::custom(ServerError::Unauthorized(
+            "SIWE nonce is unknown, expired or already used".to_string(),
+        )));
+    }
+
+    let address = to_checksum(&message.address, None);
+
+    // The session never outlives the signed message.
+    let mut expires_at = Utc::now() + chrono::Duration::seconds(SIWE_SESSION_TTL_SECONDS);
+    if let Some(expiration_time) = message.expiration_time {
+        expires_at = expires_at.min(expiration_time.with_timezone(&Utc));
+    }
+
+    let token = create_session_jwt(&address, expires_at, &state.config.jwt_secret)
+        .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&SiweVerifyResponse {
+            token,
+            address,
+            expires_at: expires_at.to_rfc3339(),
+        }),
+        StatusCode::OK,
+    ))
+}
@@ -0,0 +1,111 @@
+use std::future::Future;
+
+use chrono::Utc;
+use ethers::utils::{hex, keccak256};
+use mongodb::bson::DateTime;
+use serde::Serialize;
+use serde_json::Value;
+use warp::http::StatusCode;
+
+use crate::db::idempotency_keys::{
+    complete_idempotency_key, release_idempotency_key, reserve_idempotency_key,
+};
+use crate::error::ServerError;
+use crate::state::AppState;
+
+/// How long a stored response can be replayed.
+const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// A request still holding its key after this long is taken to have died, e.g. with a
+/// restart, and a retry may run it again. Far longer than any request takes.
+const STALE_RESERVATION_MINUTES: i64 = 10;
+
+/// Runs `handler` at most once per `Idempotency-Key`. A retry with the same key and
+/// body gets the stored response back; reusing the key for another body is a conflict.
+/// Failed requests are not stored, so they can be retried with the same key, and
+/// neither are requests cut short by a crash once their reservation goes stale.
+pub async fn idempotent<T, F, Fut>(
+    state: &AppState,
+    subject: &str,
+    key: Option<String>,
+    route: &str,
+    request: &T,
+    handler: F,
+) -> Result<(StatusCode, Value), ServerError>
+where
+    T: Serialize,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<(StatusCode, Value), ServerError>>,
+{
+    let Some(key) = key else {
+        return handler().await;
+    };
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(ServerError::Validation(format!(
+            "Idempotency-Key must be 1 to {} characters",
+            MAX_IDEMPOTENCY_KEY_LENGTH
+        )));
+    }
+
+    let body = serde_json::to_vec(request).map_err(|e| ServerError::Internal(e.to_string()))?;
+    let fingerprint = hex::encode(keccak256([route.as_bytes(), b"\n", &body].concat()));
+    let now = Utc::now();
+    let stale_before = now - chrono::Duration::minutes(STALE_RESERVATION_MINUTES);
+    let expires_at = now + chrono::Duration::hours(IDEMPOTENCY_KEY_TTL_HOURS);
+
+    let existing = reserve_idempotency_key(
+        state.mongo_client.clone(),
+        subject,
+        &key,
+        &fingerprint,
+        DateTime::from_millis(stale_before.timestamp_millis()),
+        DateTime::from_millis(expires_at.timestamp_millis()),
+    )
+    .await?;
+
+    if let Some(record) = existing {
+        if record.fingerprint != fingerprint {
+            return Err(ServerError::Conflict(
+                "Idempotency-Key was already used with a different request".to_string(),
+            ));
+        }
+        return match (record.status_code, record.response) {
+            (Some(status_code), Some(response)) => Ok((
+                StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK),
+                response,
+            )),
+            _ => Err(ServerError::Conflict(
+                "A request with this Idempotency-Key is still in progress".to_string(),
+            )),
+        };
+    }
+
+    match handler().await {
+        Ok((status_code, response)) => {
+            if let Err(e) = complete_idempotency_key(
+                state.mongo_client.clone(),
+                subject,
+                &key,
+                status_code.as_u16(),
+                &response,
+            )
+            .await
+            {
+                eprintln!(
+                    "Failed to store response for Idempotency-Key {}: {}",
+                    key, e
+                );
+            }
+            Ok((status_code, response))
+        }
+        Err(err) => {
+            if let Err(e) = release_idempotency_key(state.mongo_client.clone(), subject, &key).await
+            {
+                eprintln!("Failed to release Idempotency-Key {}: {}", key, e);
+            }
+            Err(err)
+        }
+    }
+}
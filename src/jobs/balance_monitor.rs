@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers::types::Address;
+use ethers::utils::format_ether;
+use serde_json::json;
+
+use crate::signer_balance::{refresh_signer_balances, SignerBalance};
+use crate::state::AppState;
+
+/// How often the signer balance is read.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps the cached signer balances fresh and calls the low-balance webhook once each
+/// time a wallet drops under the threshold.
+pub async fn run_balance_monitor(state: Arc<AppState>) {
+    let mut alerted: HashSet<Address> = HashSet::new();
+    loop {
+        match refresh_signer_balances(&state).await {
+            Ok(signer_balances) => {
+                for signer_balance in signer_balances {
+                    let address = signer_balance.address;
+                    if !signer_balance.is_low(state.config.low_balance_threshold) {
+                        alerted.remove(&address);
+                        continue;
+                    }
+                    if alerted.contains(&address) {
+                        continue;
+                    }
+                    eprintln!(
+                        "Signer {:?} balance is low: {} ETH",
+                        address,
+                        format_ether(signer_balance.balance)
+                    );
+                    if notify_low_balance(&state, &signer_balance).await {
+                        alerted.insert(address);
+                    }
+                }
+            }
+            Err(e) => eprintln!("Failed to read the signer balances: {}", e),
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// Returns `true` once the webhook accepted the alert, or when none is configured.
+async fn notify_low_balance(state: &AppState, signer_balance: &SignerBalance) -> bool {
+    let Some(url) = &state.config.low_balance_webhook_url else {
+        return true;
+    };
+    let body = json!({
+        "event": "signer_balance_low",
+        "address": format!("{:?}", signer_balance.address),
+        "balanceWei": signer_balance.balance.to_string(),
+        "thresholdWei": state.config.low_balance_threshold.unwrap_or_default().to_string(),
+        "checkedAt": signer_balance.checked_at.to_rfc3339(),
+    });
+    let result = state
+        .http_client
+        .post(url)
+        .json(&body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    match result {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("Low balance webhook failed: {}", e);
+            false
+        }
+    }
+}
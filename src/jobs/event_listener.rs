@@ -0,0 +1,475 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use ethers::providers::{Middleware, Provider, StreamExt, Ws};
+use ethers::types::{Filter, Log, H256};
+
+use crate::chain::events::{ChainEvent, WatchedContracts};
+use crate::chain::registry::Chain;
+use crate::state::AppState;
+
+/// Wait before the first reconnect; it doubles while connecting keeps failing.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// How often a quiet connection is checked, and how long the check may take.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Blocks per `eth_getLogs` request when catching up after a disconnect.
+const BACKFILL_CHUNK: u64 = 2_000;
+/// Blocks below the synced one that are fetched again after a disconnect, to catch
+/// reorgs that happened meanwhile. Published events are remembered this far back.
+const REORG_DEPTH: u64 = 64;
+
+/// Subscribes to the events of the watched contracts on every chain with a `ws_url`
+/// and hands them to [`AppState::events`].
+pub fn spawn_event_listeners(state: Arc<AppState>) {
+    for chain in state.chains.all() {
+        let Some(ws_url) = chain.config.ws_url.clone() else {
+            continue;
+        };
+        let contracts = WatchedContracts::for_chain(&state, &chain.config.name);
+        if contracts.is_empty() {
+            continue;
+        }
+        tokio::spawn(listen(state.clone(), chain.clone(), ws_url, contracts));
+    }
+}
+
+/// Keeps one chain's subscription up. After a disconnect the logs of the blocks missed
+/// meanwhile are fetched with `eth_getLogs`, so subscribers see every event once.
+async fn listen(
+    state: Arc<AppState>,
+    chain: Arc<Chain>,
+    ws_url: String,
+    contracts: WatchedContracts,
+) {
+    let mut tracker: Option<LogTracker> = None;
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        let connected_at = Instant::now();
+        match stream_events(&state, &chain, &ws_url, &contracts, &mut tracker).await {
+            Ok(()) => eprintln!(
+                "Event subscription of chain {} closed, reconnecting in {:?}",
+                chain.config.name, delay
+            ),
+            Err(e) => eprintln!(
+                "Event subscription of chain {} failed: {}, reconnecting in {:?}",
+                chain.config.name, e, delay
+            ),
+        }
+        tokio::time::sleep(delay).await;
+        delay = if connected_at.elapsed() > MAX_RECONNECT_DELAY {
+            RECONNECT_DELAY
+        } else {
+            (delay * 2).min(MAX_RECONNECT_DELAY)
+        };
+    }
+}
+
+/// Runs one connection until it drops.
+async fn stream_events(
+    state: &AppState,
+    chain: &Chain,
+    ws_url: &str,
+    contracts: &WatchedContracts,
+    tracker: &mut Option<LogTracker>,
+) -> Result<()> {
+    // Reconnects are ours to make, so missed blocks get backfilled.
+    let provider = Provider::new(Ws::connect_with_reconnects(ws_url, 0).await?);
+    let filter = contracts.filter();
+    let tracker = match tracker {
+        Some(tracker) => tracker,
+        None => {
+            let head = provider.get_block_number().await?.as_u64();
+            println!(
+                "Listening to events of chain {} from block {}",
+                chain.config.name,
+                head + 1
+            );
+            tracker.insert(LogTracker::new(&chain.config.name, head))
+        }
+    };
+
+    // Subscribe before catching up: logs of blocks mined in between arrive on both
+    // and are told apart by their block hash and index.
+    let mut stream = provider.subscribe_logs(&filter).await?;
+    let head = provider.get_block_number().await?.as_u64();
+    backfill(state, contracts, &provider, &filter, head, tracker).await?;
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await;
+    let mut last_head = head;
+    loop {
+        tokio::select! {
+            log = stream.next() => match log {
+                Some(log) => publish(state, tracker.on_log(contracts, &log)),
+                None => return Ok(()),
+            },
+            _ = heartbeat.tick() => {
+                let head = tokio::time::timeout(HEARTBEAT_TIMEOUT, provider.get_block_number())
+                    .await??
+                    .as_u64();
+                // The logs of the head seen a heartbeat ago have been delivered by now.
+                tracker.mark_synced(last_head);
+                last_head = head;
+            }
+        }
+    }
+}
+
+/// Fetches the logs from a little below the synced block up to `to`, publishing the
+/// missed ones and retracting those of blocks that were reorged away.
+async fn backfill(
+    state: &AppState,
+    contracts: &WatchedContracts,
+    provider: &Provider<Ws>,
+    filter: &Filter,
+    to: u64,
+    tracker: &mut LogTracker,
+) -> Result<()> {
+    let from = tracker.rescan_from();
+    let mut published = 0;
+    let mut chunk_from = from;
+    while chunk_from <= to {
+        let chunk_to = (chunk_from + BACKFILL_CHUNK - 1).min(to);
+        let logs = provider
+            .get_logs(&filter.clone().from_block(chunk_from).to_block(chunk_to))
+            .await?;
+        let events = tracker.on_backfill(contracts, chunk_from, chunk_to, &logs);
+        published += events.len();
+        publish(state, events);
+        chunk_from = chunk_to + 1;
+    }
+    if published > 0 {
+        println!(
+            "Backfilled {} events of chain {} from blocks {} to {}",
+            published, tracker.chain, from, to
+        );
+    }
+    Ok(())
+}
+
+fn publish(state: &AppState, events: Vec<ChainEvent>) {
+    for event in events {
+        // Sending fails only while nobody listens.
+        let _ = state.events.send(event);
+    }
+}
+
+/// The events published for the recent blocks of one chain, by block. Logs are told
+/// apart by block hash and index, so a log re-included by a reorg is published again,
+/// and the events of blocks that were reorged away are retracted with `removed: true`.
+struct LogTracker {
+    chain: String,
+    /// Logs of earlier blocks are never published.
+    start: u64,
+    /// Every log up to this block has been handled.
+    synced_to: u64,
+    blocks: BTreeMap<u64, TrackedBlock>,
+}
+
+struct TrackedBlock {
+    hash: H256,
+    events: Vec<ChainEvent>,
+}
+
+impl LogTracker {
+    fn new(chain: &str, start: u64) -> Self {
+        LogTracker {
+            chain: chain.to_string(),
+            start,
+            synced_to: start,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    /// First block to fetch again after a disconnect.
+    fn rescan_from(&self) -> u64 {
+        self.synced_to
+            .saturating_sub(REORG_DEPTH)
+            .max(self.start + 1)
+    }
+
+    fn mark_synced(&mut self, block: u64) {
+        self.synced_to = self.synced_to.max(block);
+        let keep_from = self.synced_to.saturating_sub(REORG_DEPTH);
+        self.blocks = self.blocks.split_off(&keep_from);
+    }
+
+    /// Events to publish for a log of the subscription.
+    fn on_log(&mut self, contracts: &WatchedContracts, log: &Log) -> Vec<ChainEvent> {
+        let Some(event) = contracts.decode(&self.chain, log) else {
+            return Vec::new();
+        };
+        let Some(block_hash) = log.block_hash else {
+            return Vec::new();
+        };
+        if event.block_number <= self.start {
+            return Vec::new();
+        }
+
+        if event.removed {
+            // Only what was published needs retracting.
+            let Some(block) = self
+                .blocks
+                .get_mut(&event.block_number)
+                .filter(|block| block.hash == block_hash)
+            else {
+                return Vec::new();
+            };
+            let before = block.events.len();
+            block
+                .events
+                .retain(|published| published.log_index != event.log_index);
+            return if block.events.len() < before {
+                vec![event]
+            } else {
+                Vec::new()
+            };
+        }
+
+        let mut events = Vec::new();
+        if let Some(block) = self.blocks.get(&event.block_number) {
+            if block.hash == block_hash {
+                if block
+                    .events
+                    .iter()
+                    .any(|published| published.log_index == event.log_index)
+                {
+                    return Vec::new();
+                }
+            } else {
+                // The block was replaced, and so were the blocks on top of it.
+                events = self.retract_from(event.block_number);
+            }
+        }
+        self.blocks
+            .entry(event.block_number)
+            .or_insert_with(|| TrackedBlock {
+                hash: block_hash,
+                events: Vec::new(),
+            })
+            .events
+            .push(event.clone());
+        events.push(event);
+        events
+    }
+
+    /// Events to publish for `logs`, the canonical logs of the blocks `from` to `to`.
+    fn on_backfill(
+        &mut self,
+        contracts: &WatchedContracts,
+        from: u64,
+        to: u64,
+        logs: &[Log],
+    ) -> Vec<ChainEvent> {
+        let canonical: HashMap<u64, H256> = logs
+            .iter()
+            .filter_map(|log| Some((log.block_number?.as_u64(), log.block_hash?)))
+            .collect();
+        // A published block whose logs are gone or now come from another block was
+        // reorged away while the listener was offline.
+        let orphaned = self
+            .blocks
+            .range(from..=to)
+            .find(|(number, block)| canonical.get(number) != Some(&block.hash))
+            .map(|(number, _)| *number);
+        let mut events = match orphaned {
+            Some(number) => self.retract_from(number),
+            None => Vec::new(),
+        };
+        for log in logs {
+            events.extend(self.on_log(contracts, log));
+        }
+        self.mark_synced(to);
+        events
+    }
+
+    /// Forgets the blocks from `number` on and returns their events as removed.
+    fn retract_from(&mut self, number: u64) -> Vec<ChainEvent> {
+        self.blocks
+            .split_off(&number)
+            .into_values()
+            .flat_map(|block| block.events)
+            .map(|mut event| {
+                event.removed = true;
+                event
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::events::{ChainEventKind, TransferFilter};
+    use ethers::contract::EthEvent;
+    use ethers::types::{Address, U256, U64};
+
+    fn nft() -> Address {
+        Address::from_low_u64_be(0xaa)
+    }
+
+    fn contracts() -> WatchedContracts {
+        WatchedContracts::new(HashMap::from([(nft(), "snapit".to_string())]), None)
+    }
+
+    /// A mint of `token_id` in block `number`, whose hash is told apart by `fork`.
+    fn transfer(number: u64, fork: u64, log_index: u64, token_id: u64) -> Log {
+        let mut token_topic = [0u8; 32];
+        U256::from(token_id).to_big_endian(&mut token_topic);
+        Log {
+            address: nft(),
+            topics: vec![
+                TransferFilter::signature(),
+                H256::zero(),
+                H256::from(Address::from_low_u64_be(0xbb)),
+                H256::from(token_topic),
+            ],
+            block_number: Some(U64::from(number)),
+            block_hash: Some(H256::from_low_u64_be(number * 1000 + fork)),
+            transaction_hash: Some(H256::from_low_u64_be(token_id)),
+            log_index: Some(U256::from(log_index)),
+            removed: Some(false),
+            ..Default::default()
+        }
+    }
+
+    fn removed(mut log: Log) -> Log {
+        log.removed = Some(true);
+        log
+    }
+
+    fn summary(events: &[ChainEvent]) -> Vec<(u64, String, bool)> {
+        events
+            .iter()
+            .map(|event| match &event.event {
+                ChainEventKind::Transfer { token_id, .. } => {
+                    (event.block_number, token_id.clone(), event.removed)
+                }
+                _ => panic!("unexpected event {:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn publishes_a_log_once() {
+        let mut tracker = LogTracker::new("test", 10);
+        let log = transfer(11, 0, 0, 1);
+        assert_eq!(
+            summary(&tracker.on_log(&contracts(), &log)),
+            vec![(11, "1".to_string(), false)]
+        );
+        assert!(tracker.on_log(&contracts(), &log).is_empty());
+        // The same log again in a backfill.
+        assert!(tracker.on_backfill(&contracts(), 11, 11, &[log]).is_empty());
+    }
+
+    #[test]
+    fn ignores_logs_from_before_the_start() {
+        let mut tracker = LogTracker::new("test", 10);
+        assert!(tracker
+            .on_log(&contracts(), &transfer(10, 0, 0, 1))
+            .is_empty());
+        assert_eq!(tracker.rescan_from(), 11);
+    }
+
+    #[test]
+    fn republishes_logs_reincluded_after_a_reorg() {
+        let mut tracker = LogTracker::new("test", 10);
+        tracker.on_log(&contracts(), &transfer(11, 0, 0, 1));
+        tracker.on_log(&contracts(), &transfer(12, 0, 0, 2));
+
+        // The node retracts both blocks and re-includes the mints in the new ones.
+        let retracted = [
+            tracker.on_log(&contracts(), &removed(transfer(12, 0, 0, 2))),
+            tracker.on_log(&contracts(), &removed(transfer(11, 0, 0, 1))),
+        ]
+        .concat();
+        assert_eq!(
+            summary(&retracted),
+            vec![(12, "2".to_string(), true), (11, "1".to_string(), true)]
+        );
+        let reincluded = [
+            tracker.on_log(&contracts(), &transfer(11, 1, 0, 1)),
+            tracker.on_log(&contracts(), &transfer(11, 1, 1, 2)),
+        ]
+        .concat();
+        assert_eq!(
+            summary(&reincluded),
+            vec![(11, "1".to_string(), false), (11, "2".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn retracts_the_replaced_blocks_when_a_new_block_hash_shows_up() {
+        let mut tracker = LogTracker::new("test", 10);
+        tracker.on_log(&contracts(), &transfer(11, 0, 0, 1));
+        tracker.on_log(&contracts(), &transfer(12, 0, 0, 2));
+
+        // No removed logs came through, but block 11 now has another hash.
+        let events = tracker.on_log(&contracts(), &transfer(11, 1, 0, 3));
+        assert_eq!(
+            summary(&events),
+            vec![
+                (11, "1".to_string(), true),
+                (12, "2".to_string(), true),
+                (11, "3".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn removed_logs_that_were_never_published_are_dropped() {
+        let mut tracker = LogTracker::new("test", 10);
+        assert!(tracker
+            .on_log(&contracts(), &removed(transfer(11, 0, 0, 1)))
+            .is_empty());
+    }
+
+    #[test]
+    fn backfill_detects_reorgs_that_happened_while_offline() {
+        let mut tracker = LogTracker::new("test", 10);
+        tracker.on_log(&contracts(), &transfer(11, 0, 0, 1));
+        tracker.on_log(&contracts(), &transfer(12, 0, 0, 2));
+        tracker.mark_synced(12);
+
+        // Block 11 was replaced by one without the mint; block 12 by one with another.
+        let from = tracker.rescan_from();
+        assert_eq!(from, 11);
+        let events = tracker.on_backfill(&contracts(), from, 13, &[transfer(12, 1, 0, 2)]);
+        assert_eq!(
+            summary(&events),
+            vec![
+                (11, "1".to_string(), true),
+                (12, "2".to_string(), true),
+                (12, "2".to_string(), false),
+            ]
+        );
+        assert_eq!(tracker.synced_to, 13);
+    }
+
+    #[test]
+    fn backfill_keeps_blocks_that_were_not_reorged() {
+        let mut tracker = LogTracker::new("test", 10);
+        tracker.on_log(&contracts(), &transfer(11, 0, 0, 1));
+        let events = tracker.on_backfill(
+            &contracts(),
+            11,
+            12,
+            &[transfer(11, 0, 0, 1), transfer(12, 0, 0, 2)],
+        );
+        assert_eq!(summary(&events), vec![(12, "2".to_string(), false)]);
+    }
+
+    #[test]
+    fn forgets_blocks_below_the_reorg_depth() {
+        let mut tracker = LogTracker::new("test", 10);
+        tracker.on_log(&contracts(), &transfer(11, 0, 0, 1));
+        tracker.mark_synced(11 + REORG_DEPTH + 1);
+        assert!(tracker.blocks.is_empty());
+        assert_eq!(tracker.rescan_from(), 12);
+    }
+}
@@ -0,0 +1,556 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers::types::{Address, H256, U64};
+
+use crate::chain::chain::{
+    confirmations, suggest_fees, wait_for_receipt, Fees, SendError, SentTransaction,
+    TransactionReceiptSchema, TxOutcome,
+};
+use crate::chain::mint::{mint_nft, owner_of};
+use crate::chain::signer_pool::{PoolSigner, SignerPool};
+use crate::collections::Collection;
+use crate::constants::FeeCapAction;
+use crate::db::mint_jobs::{
+    claim_next_mint_job, find_mint_jobs_by_status, has_active_mint_job, mark_mint_job_confirmed,
+    mark_mint_job_failed, mark_mint_job_submitted, renew_mint_job_lease, requeue_expired_mint_jobs,
+    requeue_mint_job, MintJob, MintJobStatus,
+};
+use crate::db::mongo::{
+    add_pending_nft, find_pending_nft_token_ids, is_duplicate_key_error, mark_nft_minted,
+    remove_pending_nft, AddNFTInput,
+};
+use crate::db::token_ids::{delete_token_id_reservation, expire_token_id_reservation};
+use crate::db::transactions::{
+    find_transaction, insert_transaction, mark_transaction_dropped, mark_transaction_mined,
+    TransactionRecord, TxPurpose,
+};
+use crate::error::{ServerError, UpstreamService};
+use crate::state::AppState;
+use crate::token_ids::ensure_not_owned;
+
+/// How often the queue is polled when no enqueue notification arrives.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the queue is held before fees are checked again when they are above the cap.
+const FEE_CAP_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Processes queued mints one at a time, spreading them over the signer pool while the
+/// jobs of a batch follow each other on one wallet. Each wallet's nonces are assigned in
+/// order, and receipts are awaited concurrently, so a slow block does not hold up the
+/// queue.
+pub async fn run_mint_worker(state: Arc<AppState>) {
+    requeue_mint_jobs(&state).await;
+    recover_pending_nfts(&state).await;
+    resume_mint_jobs(state.clone()).await;
+
+    let mut batch = None;
+    loop {
+        match claim_next_mint_job(state.mongo_client.clone(), &state.instance_id, batch).await {
+            Ok(Some(job)) => {
+                batch = job.batch_id;
+                submit_mint_job(state.clone(), job).await
+            }
+            Ok(None) => {
+                requeue_mint_jobs(&state).await;
+                tokio::select! {
+                    _ = state.mint_job_notify.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to claim mint job: {}", e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Puts jobs abandoned by a stopped instance back in the queue.
+async fn requeue_mint_jobs(state: &AppState) {
+    match requeue_expired_mint_jobs(state.mongo_client.clone()).await {
+        Ok(0) => {}
+        Ok(count) => println!("Requeued {} interrupted mint jobs", count),
+        Err(e) => eprintln!("Failed to requeue interrupted mint jobs: {}", e),
+    }
+}
+
+/// Resolves pending metadata that no job will settle by asking the contract
+/// whether the token exists.
+async fn recover_pending_nfts(state: &AppState) {
+    for collection in state.collections.all() {
+        recover_pending_collection_nfts(state, collection).await;
+    }
+}
+
+async fn recover_pending_collection_nfts(state: &AppState, collection: &Collection) {
+    let slug = collection.slug();
+    let token_ids = match find_pending_nft_token_ids(
+        state.mongo_client.clone(),
+        &collection.config.mongo_collection,
+    )
+    .await
+    {
+        Ok(token_ids) => token_ids,
+        Err(e) => return eprintln!("Failed to load pending tokens of {}: {}", slug, e),
+    };
+
+    for token_id in token_ids {
+        match has_active_mint_job(state.mongo_client.clone(), slug, token_id).await {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                eprintln!("Failed to look up mint jobs for token {}: {}", token_id, e);
+                continue;
+            }
+        }
+
+        let result = match owner_of(collection, token_id).await {
+            Ok(Some(_)) => {
+                async {
+                    mark_nft_minted(
+                        state.mongo_client.clone(),
+                        &collection.config.mongo_collection,
+                        token_id,
+                    )
+                    .await?;
+                    delete_token_id_reservation(state.mongo_client.clone(), slug, token_id).await
+                }
+                .await
+            }
+            Ok(None) => {
+                async {
+                    remove_pending_nft(
+                        state.mongo_client.clone(),
+                        &collection.config.mongo_collection,
+                        token_id,
+                    )
+                    .await?;
+                    expire_token_id_reservation(state.mongo_client.clone(), slug, token_id).await
+                }
+                .await
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to check owner of token {} of {}: {}",
+                    token_id, slug, e
+                );
+                continue;
+            }
+        };
+        match result {
+            Ok(()) => println!("Recovered pending token {} of {}", token_id, slug),
+            Err(e) => eprintln!(
+                "Failed to recover pending token {} of {}: {}",
+                token_id, slug, e
+            ),
+        }
+    }
+}
+
+/// Waits for the receipts of transactions sent by a previous process.
+async fn resume_mint_jobs(state: Arc<AppState>) {
+    let Ok(signers) = state.signers() else {
+        return;
+    };
+    match find_mint_jobs_by_status(state.mongo_client.clone(), MintJobStatus::Submitted).await {
+        Ok(jobs) => {
+            for job in jobs {
+                let tx_hash = job.tx_hash.as_deref().and_then(|h| H256::from_str(h).ok());
+                // Jobs from before the pool have no signer; they were sent by the primary.
+                let signer = job
+                    .signer
+                    .as_deref()
+                    .and_then(|address| Address::from_str(address).ok())
+                    .and_then(|address| signers.by_address(address))
+                    .unwrap_or_else(|| signers.primary())
+                    .clone();
+                match tx_hash {
+                    Some(tx_hash) => spawn_confirm_mint_job(state.clone(), job, tx_hash, signer),
+                    None => {
+                        fail_mint_job(
+                            &state,
+                            &job,
+                            &ServerError::Internal("submitted job has no tx hash".to_string()),
+                            None,
+                        )
+                        .await
+                    }
+                }
+            }
+        }
+        Err(e) => eprintln!("Failed to load submitted mint jobs: {}", e),
+    }
+}
+
+/// Fees this job can be sent with, or `None` if it went back to the queue because the
+/// network fee is above the cap.
+async fn mint_fees(state: &AppState, job: &MintJob) -> Option<Fees> {
+    let policy = &state.config.gas_policy;
+    let result = match suggest_fees(&state.chains.nft().provider, policy).await {
+        Ok(fees) => fees.check_cap(policy).map(|()| fees),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(fees) => Some(fees),
+        Err(e @ ServerError::FeeAboveCap { .. })
+            if policy.on_fee_above_cap == FeeCapAction::Queue =>
+        {
+            hold_mint_job(state, job, &e.to_string(), FEE_CAP_RETRY_INTERVAL).await;
+            None
+        }
+        Err(e) => {
+            fail_mint_job(state, job, &e, None).await;
+            None
+        }
+    }
+}
+
+/// Puts the job back in the queue and pauses the worker, since the next job would be
+/// held for the same reason.
+async fn hold_mint_job(state: &AppState, job: &MintJob, reason: &str, wait: Duration) {
+    println!("Holding mint job {}: {}", job.id, reason);
+    if let Err(e) = requeue_mint_job(state.mongo_client.clone(), job.id, &state.instance_id).await {
+        eprintln!("Failed to requeue mint job {}: {}", job.id, e);
+    }
+    tokio::time::sleep(wait).await;
+}
+
+/// The collection the job mints in, which must still be configured and mintable.
+fn job_collection<'a>(
+    state: &'a AppState,
+    job: &MintJob,
+) -> Result<&'a Arc<Collection>, ServerError> {
+    let collection = state.collections.get(&job.collection).ok_or_else(|| {
+        ServerError::Internal(format!("collection {} is not configured", job.collection))
+    })?;
+    collection.ensure_mintable()?;
+    Ok(collection)
+}
+
+/// The wallet a batch job was queued for, or else the pool's pick. A batch stays on its
+/// wallet even while that one is stuck, so that its nonces keep the batch order.
+fn job_signer(signers: &SignerPool, job: &MintJob) -> Option<Arc<PoolSigner>> {
+    let pinned = job
+        .signer
+        .as_deref()
+        .and_then(|address| Address::from_str(address).ok())
+        .and_then(|address| signers.by_address(address));
+    match pinned {
+        Some(signer) => Some(signer.clone()),
+        None => signers.select(),
+    }
+}
+
+async fn submit_mint_job(state: Arc<AppState>, job: MintJob) {
+    let collection = match job_collection(&state, &job) {
+        Ok(collection) => collection.clone(),
+        Err(e) => return fail_mint_job(&state, &job, &e, None).await,
+    };
+    let Some(fees) = mint_fees(&state, &job).await else {
+        return;
+    };
+    let Ok(signers) = state.signers() else {
+        return;
+    };
+    let Some(signer) = job_signer(signers, &job) else {
+        let reason = "every signer has a stuck transaction";
+        return hold_mint_job(&state, &job, reason, POLL_INTERVAL).await;
+    };
+
+    // The token may have been minted since the job was queued; do not pay for a revert.
+    if let Err(e) = ensure_not_owned(&collection, job.token_id).await {
+        if let ServerError::TokenAlreadyMinted { owner, .. } = &e {
+            // Sent for this job by an instance that stopped before recording it.
+            if owner.eq_ignore_ascii_case(&job.owner_address) {
+                println!("Mint job {} was already minted on chain", job.id);
+                return complete_mint_job(&state, &collection, &job, None).await;
+            }
+        }
+        return fail_mint_job(&state, &job, &e, None).await;
+    }
+
+    // A claim held up past its lease may have been taken over; only the owner sends.
+    match renew_mint_job_lease(state.mongo_client.clone(), job.id, &state.instance_id).await {
+        Ok(true) => {}
+        Ok(false) => return println!("Mint job {} was taken over by another instance", job.id),
+        Err(e) => {
+            eprintln!("Failed to renew the lease on mint job {}: {}", job.id, e);
+            return hold_mint_job(
+                &state,
+                &job,
+                "its lease could not be renewed",
+                POLL_INTERVAL,
+            )
+            .await;
+        }
+    }
+
+    // Metadata goes in first so a minted token never lacks it.
+    let token_nft = AddNFTInput {
+        token_id: job.token_id,
+        metadata: job.metadata.clone(),
+    };
+    match add_pending_nft(
+        state.mongo_client.clone(),
+        &collection.config.mongo_collection,
+        token_nft,
+        job.id,
+    )
+    .await
+    {
+        Ok(()) => {}
+        Err(e) if is_duplicate_key_error(&e) => {
+            let error = ServerError::Conflict(format!(
+                "Token {} already has metadata from another mint",
+                job.token_id
+            ));
+            return fail_mint_job(&state, &job, &error, None).await;
+        }
+        Err(e) => return fail_mint_job(&state, &job, &ServerError::from(e), None).await,
+    }
+
+    let result = mint_nft(
+        &collection,
+        &job.owner_address,
+        job.token_id,
+        job.data.as_ref(),
+        &fees,
+        state.config.clone(),
+        signer.client.clone(),
+    )
+    .await;
+
+    let sent = match result {
+        Ok(sent) => sent,
+        // The node may still mine it, so the receipt settles the job like any other.
+        Err(SendError::Unclear(sent, e)) => {
+            eprintln!(
+                "Mint job {} may not have reached the node, waiting for {:?}: {}",
+                job.id, sent.hash, e
+            );
+            *sent
+        }
+        Err(SendError::NotSent(e)) => {
+            rollback_pending_nft(&state, &collection, job.token_id).await;
+            return fail_mint_job(&state, &job, &e, None).await;
+        }
+    };
+
+    let tx_hash = sent.hash;
+    record_transaction(&state, &job, &sent).await;
+    if let Err(e) = mark_mint_job_submitted(
+        state.mongo_client.clone(),
+        job.id,
+        &format!("{:?}", signer.address),
+        &format!("{:?}", tx_hash),
+    )
+    .await
+    {
+        eprintln!("Failed to mark mint job {} as submitted: {}", job.id, e);
+    }
+    spawn_confirm_mint_job(state, job, tx_hash, signer);
+}
+
+/// Stores the sent transaction; the mint goes ahead even if this fails.
+async fn record_transaction(state: &AppState, job: &MintJob, sent: &SentTransaction) {
+    let mut record = TransactionRecord::new(
+        sent.hash,
+        TxPurpose::Mint,
+        &job.collection,
+        sent.transaction.as_ref(),
+    );
+    record.token_id = Some(job.token_id);
+    record.mint_job_id = Some(job.id);
+    if let Err(e) = insert_transaction(state.mongo_client.clone(), &record).await {
+        eprintln!("Failed to record transaction {}: {}", record.tx_hash, e);
+    }
+}
+
+/// Counts the mint against its wallet until the receipt settles it.
+fn spawn_confirm_mint_job(
+    state: Arc<AppState>,
+    job: MintJob,
+    tx_hash: H256,
+    signer: Arc<PoolSigner>,
+) {
+    signer.begin();
+    tokio::spawn(async move {
+        confirm_mint_job(state, job, tx_hash).await;
+        signer.finish();
+    });
+}
+
+/// Waits for `tx_hash` or, once it has left the mempool, for the transaction that
+/// replaced it. Returns the hash that was waited for last.
+async fn wait_for_mint_receipt(state: &AppState, mut tx_hash: H256) -> (H256, TxOutcome) {
+    let chain = state.chains.nft();
+    loop {
+        let outcome = wait_for_receipt(&chain.provider, tx_hash, chain.config.confirmations).await;
+        if let TxOutcome::Mined(_) = outcome {
+            return (tx_hash, outcome);
+        }
+        let replaced_by = find_transaction(state.mongo_client.clone(), &format!("{:?}", tx_hash))
+            .await
+            .ok()
+            .flatten()
+            .and_then(|record| record.replaced_by)
+            .and_then(|hash| H256::from_str(&hash).ok());
+        match replaced_by {
+            Some(replacement) => tx_hash = replacement,
+            None => return (tx_hash, outcome),
+        }
+    }
+}
+
+async fn confirm_mint_job(state: Arc<AppState>, job: MintJob, tx_hash: H256) {
+    let collection = match job_collection(&state, &job) {
+        Ok(collection) => collection.clone(),
+        Err(e) => return fail_mint_job(&state, &job, &e, None).await,
+    };
+    let (tx_hash, outcome) = wait_for_mint_receipt(&state, tx_hash).await;
+    let tx_hash_hex = format!("{:?}", tx_hash);
+    let receipt = match outcome {
+        TxOutcome::Mined(receipt) => *receipt,
+        // The node no longer knows the transaction, so the token was not minted by it.
+        TxOutcome::Dropped => {
+            if let Err(e) = mark_transaction_dropped(state.mongo_client.clone(), &tx_hash_hex).await
+            {
+                eprintln!(
+                    "Failed to mark transaction {} as dropped: {}",
+                    tx_hash_hex, e
+                );
+            }
+            rollback_pending_nft(&state, &collection, job.token_id).await;
+            let error = ServerError::upstream(
+                UpstreamService::Rpc,
+                format!(
+                    "mint transaction {:?} was dropped from the mempool",
+                    tx_hash
+                ),
+            );
+            return fail_mint_job(&state, &job, &error, None).await;
+        }
+    };
+
+    let succeeded = receipt.status == Some(U64::from(1));
+    // The receipt was just mined, so a failed lookup of the head is not worth retrying.
+    let depth = confirmations(&state.chains.nft().provider, &receipt)
+        .await
+        .unwrap_or(1);
+    let receipt = TransactionReceiptSchema::from(receipt);
+
+    if let Err(e) =
+        mark_transaction_mined(state.mongo_client.clone(), &tx_hash_hex, &receipt, depth).await
+    {
+        eprintln!("Failed to update transaction {}: {}", tx_hash_hex, e);
+    }
+
+    let cancelled = find_transaction(state.mongo_client.clone(), &tx_hash_hex)
+        .await
+        .ok()
+        .flatten()
+        .is_some_and(|record| record.purpose == TxPurpose::Cancel);
+    if cancelled {
+        rollback_pending_nft(&state, &collection, job.token_id).await;
+        let error =
+            ServerError::Conflict(format!("mint transaction was cancelled by {:?}", tx_hash));
+        return fail_mint_job(&state, &job, &error, Some(&receipt)).await;
+    }
+
+    if !succeeded {
+        // An earlier send of this job that was never recorded may have minted it.
+        if let Ok(Some(owner)) = owner_of(&collection, job.token_id).await {
+            if format!("{:?}", owner).eq_ignore_ascii_case(&job.owner_address) {
+                return complete_mint_job(&state, &collection, &job, None).await;
+            }
+        }
+        rollback_pending_nft(&state, &collection, job.token_id).await;
+        let error = ServerError::Conflict(format!("mint transaction {:?} reverted", tx_hash));
+        return fail_mint_job(&state, &job, &error, Some(&receipt)).await;
+    }
+
+    complete_mint_job(&state, &collection, &job, Some(&receipt)).await;
+}
+
+/// Publishes the metadata of a minted token and closes its job.
+async fn complete_mint_job(
+    state: &AppState,
+    collection: &Collection,
+    job: &MintJob,
+    receipt: Option<&TransactionReceiptSchema>,
+) {
+    if let Err(e) = mark_nft_minted(
+        state.mongo_client.clone(),
+        &collection.config.mongo_collection,
+        job.token_id,
+    )
+    .await
+    {
+        eprintln!(
+            "Token {} of {} minted but its metadata is still pending: {}",
+            job.token_id, job.collection, e
+        );
+    }
+    if let Err(e) =
+        delete_token_id_reservation(state.mongo_client.clone(), &job.collection, job.token_id).await
+    {
+        eprintln!(
+            "Failed to drop the reservation of minted token {} of {}: {}",
+            job.token_id, job.collection, e
+        );
+    }
+
+    if let Err(e) = mark_mint_job_confirmed(state.mongo_client.clone(), job.id, receipt).await {
+        eprintln!("Failed to mark mint job {} as confirmed: {}", job.id, e);
+    }
+}
+
+/// Undoes the side effects of a mint that did not happen, freeing an allocated id.
+async fn rollback_pending_nft(state: &AppState, collection: &Collection, token_id: u64) {
+    if let Err(e) = remove_pending_nft(
+        state.mongo_client.clone(),
+        &collection.config.mongo_collection,
+        token_id,
+    )
+    .await
+    {
+        eprintln!(
+            "Failed to roll back pending metadata for token {} of {}: {}",
+            token_id,
+            collection.slug(),
+            e
+        );
+    }
+    if let Err(e) =
+        expire_token_id_reservation(state.mongo_client.clone(), collection.slug(), token_id).await
+    {
+        eprintln!(
+            "Failed to release the reservation of token {} of {}: {}",
+            token_id,
+            collection.slug(),
+            e
+        );
+    }
+}
+
+async fn fail_mint_job(
+    state: &AppState,
+    job: &MintJob,
+    error: &ServerError,
+    receipt: Option<&TransactionReceiptSchema>,
+) {
+    eprintln!("Mint job {} failed: {}", job.id, error);
+    if let Err(e) = mark_mint_job_failed(
+        state.mongo_client.clone(),
+        job.id,
+        error.code(),
+        &error.to_string(),
+        receipt,
+    )
+    .await
+    {
+        eprintln!("Failed to mark mint job {} as failed: {}", job.id, e);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod balance_monitor;
+pub mod event_listener;
+pub mod mint_worker;
+pub mod tx_monitor;
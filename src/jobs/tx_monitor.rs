@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use ethers::types::{Address, H256};
+use mongodb::bson::DateTime;
+
+use crate::chain::chain::Replacement;
+use crate::db::transactions::{
+    find_pending_transactions, find_stuck_transactions, TransactionRecord, TxStatus,
+};
+use crate::error::ServerError;
+use crate::state::AppState;
+use crate::tx_replacement::replace_stuck_transaction;
+
+/// How often pending transactions are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Speeds up server transactions that have been pending for longer than
+/// `gas.stuck_after_minutes`, and keeps new mints away from the wallets that sent them
+/// until they are mined. Replacements start a new record, so a transaction that stays
+/// stuck is bumped again after the same delay.
+pub async fn run_tx_monitor(state: Arc<AppState>) {
+    let Ok(signers) = state.signers() else {
+        return;
+    };
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+
+        let stuck_after =
+            chrono::Duration::minutes(state.config.gas_policy.stuck_after_minutes as i64);
+        let sent_before = DateTime::from_millis((Utc::now() - stuck_after).timestamp_millis());
+        match find_stuck_transactions(state.mongo_client.clone(), sent_before).await {
+            Ok(records) => {
+                for record in records {
+                    speed_up(&state, &record).await;
+                }
+            }
+            Err(e) => eprintln!("Failed to load stuck transactions: {}", e),
+        }
+
+        // Read again, since the pass above replaced or settled some of them.
+        let pending = match find_pending_transactions(state.mongo_client.clone()).await {
+            Ok(pending) => pending,
+            Err(e) => {
+                eprintln!("Failed to load pending transactions: {}", e);
+                continue;
+            }
+        };
+        let stuck_signers = stuck_signers(&pending, sent_before);
+        for signer in signers.signers() {
+            let stuck = stuck_signers.contains(&signer.address);
+            if stuck != signer.is_stuck() {
+                println!(
+                    "Signer {:?} {}",
+                    signer.address,
+                    if stuck {
+                        "is stuck, skipping it for new mints"
+                    } else {
+                        "recovered"
+                    }
+                );
+            }
+            signer.set_stuck(stuck);
+        }
+    }
+}
+
+async fn speed_up(state: &AppState, record: &TransactionRecord) {
+    let Ok(tx_hash) = H256::from_str(&record.tx_hash) else {
+        return;
+    };
+    match replace_stuck_transaction(state, tx_hash, Replacement::SpeedUp).await {
+        // Mined, replaced or dropped since the query.
+        Ok(_) | Err(ServerError::Conflict(_)) => {}
+        Err(e) => eprintln!("Failed to speed up transaction {}: {}", record.tx_hash, e),
+    }
+}
+
+/// Wallets whose oldest unconfirmed nonce has been waiting since before `sent_before`.
+/// Every later transaction of a wallet waits for that nonce, and a replacement keeps
+/// it, so the wallet stays stuck until the nonce is mined or dropped.
+fn stuck_signers(pending: &[TransactionRecord], sent_before: DateTime) -> HashSet<Address> {
+    let mut oldest: HashMap<Address, &TransactionRecord> = HashMap::new();
+    for record in pending
+        .iter()
+        .filter(|record| record.status == TxStatus::Pending)
+    {
+        let Some(from) = record
+            .from
+            .as_deref()
+            .and_then(|from| Address::from_str(from).ok())
+        else {
+            continue;
+        };
+        let nonce = record.nonce.unwrap_or(u64::MAX);
+        oldest
+            .entry(from)
+            .and_modify(|current| {
+                if nonce < current.nonce.unwrap_or(u64::MAX) {
+                    *current = record;
+                }
+            })
+            .or_insert(record);
+    }
+    oldest
+        .into_iter()
+        .filter(|(_, record)| record.nonce_pending_since() <= sent_before)
+        .map(|(from, _)| from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::transactions::TxPurpose;
+
+    /// A transaction with `nonce` whose nonce was first sent `minutes` ago.
+    fn pending_from(from: Address, nonce: u64, minutes: i64) -> TransactionRecord {
+        let mut record = TransactionRecord::new(
+            H256::from_low_u64_be(nonce),
+            TxPurpose::Mint,
+            "snapit",
+            None,
+        );
+        record.from = Some(format!("{:?}", from));
+        record.nonce = Some(nonce);
+        record.nonce_sent_at = Some(minutes_ago(minutes));
+        record
+    }
+
+    fn minutes_ago(minutes: i64) -> DateTime {
+        DateTime::from_millis((Utc::now() - chrono::Duration::minutes(minutes)).timestamp_millis())
+    }
+
+    #[test]
+    fn long_pending_transactions_hold_their_wallet() {
+        let wallet = Address::from_low_u64_be(1);
+        let other = Address::from_low_u64_be(2);
+        let records = vec![pending_from(wallet, 1, 15), pending_from(other, 1, 1)];
+        assert_eq!(
+            stuck_signers(&records, minutes_ago(10)),
+            HashSet::from([wallet])
+        );
+    }
+
+    #[test]
+    fn dropped_transactions_release_their_wallet() {
+        let wallet = Address::from_low_u64_be(1);
+        let other = Address::from_low_u64_be(2);
+        let mut records = vec![pending_from(wallet, 1, 15), pending_from(other, 2, 15)];
+        // What the monitor sees after the node no longer knows the first one.
+        records[0].status = TxStatus::Dropped;
+        assert_eq!(
+            stuck_signers(&records, minutes_ago(10)),
+            HashSet::from([other])
+        );
+    }
+
+    #[test]
+    fn a_fresh_replacement_keeps_its_wallet_stuck() {
+        let wallet = Address::from_low_u64_be(1);
+        let mut replacement = pending_from(wallet, 1, 15);
+        replacement.created_at = DateTime::now();
+        assert_eq!(
+            stuck_signers(&[replacement], minutes_ago(10)),
+            HashSet::from([wallet])
+        );
+    }
+
+    #[test]
+    fn a_wallet_stays_stuck_while_a_later_nonce_is_pending() {
+        let wallet = Address::from_low_u64_be(1);
+        let mut records = vec![pending_from(wallet, 1, 15), pending_from(wallet, 2, 12)];
+        records[0].status = TxStatus::Dropped;
+        assert_eq!(
+            stuck_signers(&records, minutes_ago(10)),
+            HashSet::from([wallet])
+        );
+    }
+}
@@ -1,42 +1,101 @@
+#![allow(clippy::module_inception)]
+
 mod alchemy;
 mod auth;
 mod chain;
+mod collections;
 mod constants;
 mod db;
 mod error;
 mod graph;
 mod handlers;
+mod idempotency;
+mod jobs;
 mod openapi;
 mod routes;
+mod settings;
+mod signer_balance;
+mod siwe;
+mod state;
+mod token_ids;
+mod tx_replacement;
 
 use std::sync::Arc;
 
-use ethers::middleware::SignerMiddleware;
-use ethers::providers::{Http, Provider};
-use ethers::signers::{LocalWallet, Signer};
-
 #[tokio::main]
 async fn main() {
-    let config = constants::Constants::new();
-    let config = Arc::new(config);
+    let config = constants::Constants::new().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let bind_address = config.bind_address;
 
-    let mongo_client = db::mongo::init_db(config.clone())
+    let state = state::AppState::new(config)
         .await
-        .expect("Failed to initialize DB");
-    let mongo_client = Arc::new(mongo_client);
+        .expect("Failed to initialize application state");
+    let state = Arc::new(state);
 
-    let provider = Provider::<Http>::try_from(config.chain_url.as_str()).unwrap();
+    // `snapit-api create-api-key <name> <scope,...> [collection,...]` bootstraps keys,
+    // e.g. the first admin key.
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("create-api-key") {
+        create_api_key_command(&state, &args[2..]).await;
+        return;
+    }
 
-    let wallet: LocalWallet = config
-        .private_key
-        .parse::<LocalWallet>()
-        .unwrap()
-        .with_chain_id(config.chain_id); // Set this to the chain ID of your network
+    // Events are read through the chains' WebSocket endpoints, so read-only servers
+    // serve them too.
+    jobs::event_listener::spawn_event_listeners(state.clone());
 
-    let ethers_client = Arc::new(SignerMiddleware::new(provider, wallet));
+    // Read-only servers send no transactions, so they leave the jobs to the others.
+    if !state.config.read_only {
+        tokio::spawn(jobs::mint_worker::run_mint_worker(state.clone()));
+        tokio::spawn(jobs::tx_monitor::run_tx_monitor(state.clone()));
+        tokio::spawn(jobs::balance_monitor::run_balance_monitor(state.clone()));
+    }
 
-    let api_routes = routes::routes(config, mongo_client, ethers_client);
+    let api_routes = routes::routes(state);
 
     // Start the server
-    warp::serve(api_routes).run(([127, 0, 0, 1], 3030)).await;
+    warp::serve(api_routes).run(bind_address).await;
+}
+
+async fn create_api_key_command(state: &state::AppState, args: &[String]) {
+    let (name, scopes, collections) = match args {
+        [name, scopes] => (name.clone(), scopes, None),
+        [name, scopes, collections] => (name.clone(), scopes, Some(collections)),
+        _ => {
+            eprintln!("Usage: snapit-api create-api-key <name> <scope,...> [collection,...]");
+            std::process::exit(2);
+        }
+    };
+    let scopes: Vec<auth::Scope> = scopes
+        .split(',')
+        .map(|scope| {
+            serde_json::from_value(serde_json::Value::String(scope.trim().to_string()))
+                .unwrap_or_else(|_| {
+                    eprintln!("Unknown scope: {}", scope);
+                    std::process::exit(2);
+                })
+        })
+        .collect();
+
+    let request = handlers::api_keys::CreateApiKeyRequest {
+        name,
+        scopes,
+        collections: collections.map(|collections| {
+            collections
+                .split(',')
+                .map(|slug| slug.trim().to_string())
+                .collect()
+        }),
+        expires_in_days: None,
+    };
+    match handlers::api_keys::create_api_key(state, request, "cli").await {
+        Ok(response) => println!("{}", serde_json::to_string_pretty(&response).unwrap()),
+        Err(e) => {
+            eprintln!("Failed to create api key: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
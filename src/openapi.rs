@@ -1,54 +1,68 @@
 use std::sync::Arc;
 
-
 use utoipa::openapi::security::ApiKey;
 use utoipa::openapi::security::ApiKeyValue;
 use utoipa::openapi::security::SecurityScheme;
 use utoipa::Modify;
 use utoipa::OpenApi;
 
-use warp::{
-    hyper::Response,
-    Filter, Reply,
-};
 use utoipa_swagger_ui::Config;
+use warp::{hyper::Response, Filter, Reply};
 
-use crate::db;
+use crate::auth;
 use crate::chain;
+use crate::db;
+use crate::error;
 use crate::handlers;
 use crate::routes::{EchoRequest, EchoResponse};
 
-
-
 // struct OpenAPIRoutes {
 //     openapi_json: warp::filters::BoxedFilter<(impl warp::Reply,)>,
 //     swagger_ui: warp::filters::BoxedFilter<(impl warp::Reply,)>,
 // }
 
-pub struct OpenAPIRoutes{}
+pub struct OpenAPIRoutes {}
 
 impl OpenAPIRoutes {
-
-    pub fn openapi_json() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    pub fn openapi_json() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+    {
         #[derive(OpenApi)]
         #[openapi(
             paths(handlers::get_owner_tokens::get_owner_tokens_handler,
                 handlers::get_nft::get_nft_handler,
                 handlers::get_nft_sales::get_nft_sales_handler,
-                handlers::mint_nft::mint_nft_handler ),
+                handlers::mint_nft::mint_nft_handler,
+                handlers::mint_batch::mint_batch_handler,
+                handlers::simulate_mint::simulate_mint_handler,
+                handlers::get_mint_job::get_mint_job_handler,
+                handlers::get_transaction::get_transaction_handler,
+                handlers::replace_transaction::speed_up_transaction_handler,
+                handlers::replace_transaction::cancel_transaction_handler,
+                handlers::get_signers::get_signers_handler,
+                handlers::events::events_handler,
+                handlers::api_keys::create_api_key_handler,
+                handlers::api_keys::list_api_keys_handler,
+                handlers::api_keys::revoke_api_key_handler,
+                handlers::siwe::siwe_nonce_handler,
+                handlers::siwe::siwe_verify_handler ),
             components(
-                schemas(EchoRequest, EchoResponse, 
-                    handlers::mint_nft::MintNFTSuccessResponse, handlers::mint_nft::MintUniqueTokenRequest,
-                    handlers::get_nft::GetNFTResult, 
+                schemas(EchoRequest, EchoResponse,
+                    handlers::mint_nft::MintJobResponse, db::mint_jobs::MintJobStatus, handlers::mint_nft::MintUniqueTokenRequest,
+                    handlers::mint_batch::MintBatchRequest, handlers::mint_batch::MintBatchResponse, handlers::mint_batch::MintBatchItemResult,
+                    handlers::simulate_mint::SimulateMintRequest, chain::mint::MintSimulation, chain::mint::MintData,
+                    handlers::get_nft::GetNFTResult,
                     db::mongo::Metadata, db::mongo::AddNFTInput, db::mongo::MetadataAttribute,
-                    chain::chain::SendTransactionResult, chain::chain::TxHashSchema, chain::chain::TransactionReceiptSchema)
+                    chain::chain::TxHashSchema, chain::chain::TransactionReceiptSchema,
+                    handlers::get_transaction::TransactionResponse, handlers::get_signers::SignerInfo, chain::events::ChainEvent, chain::events::ChainEventKind, db::transactions::TxPurpose, db::transactions::TxStatus,
+                    error::ProblemDetails, auth::Scope,
+                    handlers::api_keys::CreateApiKeyRequest, handlers::api_keys::CreateApiKeyResponse, handlers::api_keys::ApiKeyInfo,
+                    handlers::siwe::SiweNonceResponse, handlers::siwe::SiweVerifyRequest, handlers::siwe::SiweVerifyResponse)
             ),
             modifiers(&SecurityAddon),
             // tags(
             //     (name = "todo", description = "Todo items management API")
             // )
         )]
-
         struct ApiDoc;
 
         struct SecurityAddon;
@@ -58,18 +72,22 @@ impl OpenAPIRoutes {
                 let components = openapi.components.as_mut().unwrap(); // we can unwrap safely since there already is components registered.
                 components.add_security_scheme(
                     "api_key",
-                    SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description("Authorization", r#" Use "Bearer APITEST" for testing."#))),
+                    SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                        "Authorization",
+                        r#" Use "Bearer <api key>". "Bearer APITEST" gives sandbox access where enabled."#,
+                    ))),
                 )
             }
         }
-    
+
         warp::path!("api" / "docs" / "openapi.json").map(|| {
             let openapi: utoipa::openapi::OpenApi = ApiDoc::openapi();
             warp::reply::json(&openapi)
         })
     }
 
-    pub fn swagger_ui() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    pub fn swagger_ui() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+    {
         let api_doc_config = Arc::new(Config::from("/api/docs/openapi.json"));
 
         warp::get()
@@ -112,7 +130,3 @@ async fn serve_swagger(
         )),
     }
 }
-
-
-
-
@@ -1,33 +1,40 @@
-use mongodb::Client;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use warp::{self, Filter};
 
-use crate::auth::with_auth;
-use crate::chain::chain::{with_ethers_client, EthersProvider};
-use crate::constants::{with_config, Constants};
-
-use crate::db::mongo::with_mongo_client;
+use crate::auth::{with_auth, Scope};
+use crate::collections::with_collection;
 use crate::error::handle_rejection;
 use crate::openapi::OpenAPIRoutes;
+use crate::state::{with_state, writable, AppState};
 use std::convert::Infallible;
 use std::sync::Arc;
 
+use crate::handlers::api_keys::{
+    create_api_key_handler, list_api_keys_handler, revoke_api_key_handler,
+};
+use crate::handlers::events::events_handler;
 use crate::handlers::get_auction::{get_auction, GetAuctionQueryParams};
+use crate::handlers::get_mint_job::get_mint_job_handler;
 use crate::handlers::get_nft::{get_nft_handler, GetNftQueryParams};
 use crate::handlers::get_nft_sales::{get_nft_sales_handler, GetNFTMarketSalesQueryParams};
 use crate::handlers::get_owner_tokens::{get_owner_tokens_handler, GetOwnerTokensQueryParams};
+use crate::handlers::get_signers::get_signers_handler;
+use crate::handlers::get_transaction::get_transaction_handler;
+use crate::handlers::metrics::metrics_handler;
+use crate::handlers::mint_batch::mint_batch_handler;
 use crate::handlers::mint_nft::mint_nft_handler;
+use crate::handlers::replace_transaction::{
+    cancel_transaction_handler, speed_up_transaction_handler,
+};
+use crate::handlers::simulate_mint::simulate_mint_handler;
+use crate::handlers::siwe::{siwe_nonce_handler, siwe_verify_handler};
 
 // Define a function that constructs and returns all routes
 pub fn routes(
-    config: Arc<Constants>,
-    mongo_client: Arc<Client>,
-    ethers_client: EthersProvider,
+    state: Arc<AppState>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Infallible> + Clone {
-    let config_filter = with_config(config);
-    let mongo_client_filter = with_mongo_client(mongo_client);
-    let ethers_client_filter = with_ethers_client(ethers_client);
+    let state_filter = with_state(state.clone());
     // GET endpoint at /
     let get_route = warp::get()
         .and(warp::path::end())
@@ -44,67 +51,176 @@ pub fn routes(
             })
         });
 
+    // The collection routes answer under `/api/collections/{slug}/...` and, for the
+    // default collection, under `/api/...`.
     let mint_nft_route = warp::post()
-        .and(warp::path("api"))
-        .and(warp::path("mint"))
+        .and(with_collection(state.clone()))
+        .and(warp::path!("mint"))
+        .and(writable(state.clone()))
         .and(warp::body::json())
-        .and(mongo_client_filter.clone())
-        .and(config_filter.clone())
-        .and(with_auth())
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Mint))
         .and_then(mint_nft_handler);
 
+    let mint_batch_route = warp::post()
+        .and(with_collection(state.clone()))
+        .and(warp::path!("mint" / "batch"))
+        .and(writable(state.clone()))
+        .and(warp::body::json())
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Mint))
+        .and_then(mint_batch_handler);
+
+    let simulate_mint_route = warp::post()
+        .and(with_collection(state.clone()))
+        .and(warp::path!("mint" / "simulate"))
+        .and(writable(state.clone()))
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Mint))
+        .and_then(simulate_mint_handler);
+
+    let get_mint_job_route = warp::get()
+        .and(warp::path!("api" / "mint" / "jobs" / String))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Read))
+        .and_then(get_mint_job_handler);
+
+    let get_transaction_route = warp::get()
+        .and(warp::path!("api" / "tx" / String))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Read))
+        .and_then(get_transaction_handler);
+
+    let speed_up_transaction_route = warp::post()
+        .and(warp::path!("api" / "admin" / "tx" / String / "speed-up"))
+        .and(writable(state.clone()))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Admin))
+        .and_then(speed_up_transaction_handler);
+
+    let cancel_transaction_route = warp::post()
+        .and(warp::path!("api" / "admin" / "tx" / String / "cancel"))
+        .and(writable(state.clone()))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Admin))
+        .and_then(cancel_transaction_handler);
+
     let get_nft_route = warp::get()
-        .and(mongo_client_filter.clone())
-        .and(warp::path("api"))
+        .and(with_collection(state.clone()))
+        .and(state_filter.clone())
         .and(warp::path("token"))
         .and(warp::path::param::<String>()) // Capture {id}.json as a String
         .and(warp::query::<GetNftQueryParams>()) // Use query to capture with_owner
-        .and(config_filter.clone())
-        .and(with_auth())
+        .and(with_auth(state.clone(), Scope::Read))
         .and_then(get_nft_handler);
 
     let get_owner_tokens_route = warp::get()
-        .and(mongo_client_filter.clone())
-        .and(warp::path("api"))
+        .and(with_collection(state.clone()))
+        .and(state_filter.clone())
         .and(warp::path("owner-tokens"))
         .and(warp::query::<GetOwnerTokensQueryParams>())
-        .and(config_filter.clone())
-        .and(with_auth())
+        .and(with_auth(state.clone(), Scope::Read))
         .and_then(get_owner_tokens_handler);
 
     let get_auction_route = warp::get()
         .and(warp::path("api"))
         .and(warp::path("auction"))
-        .and(ethers_client_filter.clone())
+        .and(state_filter.clone())
         .and(warp::query::<GetAuctionQueryParams>()) // Use query to capture with_owner
-        .and(config_filter.clone())
-        .and(with_auth())
+        .and(with_auth(state.clone(), Scope::Read))
         .and_then(get_auction);
 
+    let events_route = warp::get()
+        .and(warp::path!("api" / "events"))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Read))
+        .and_then(events_handler);
+
     let get_nft_sales_route = warp::get()
         .and(warp::path("api"))
         .and(warp::path("nft-sales"))
+        .and(state_filter.clone())
         .and(warp::query::<GetNFTMarketSalesQueryParams>()) // Use query to capture with_owner
-        .and(config_filter.clone())
-        .and(with_auth())
+        .and(with_auth(state.clone(), Scope::Read))
         .and_then(get_nft_sales_handler);
 
+    let create_api_key_route = warp::post()
+        .and(warp::path!("api" / "admin" / "api-keys"))
+        .and(writable(state.clone()))
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Admin))
+        .and_then(create_api_key_handler);
+
+    let list_api_keys_route = warp::get()
+        .and(warp::path!("api" / "admin" / "api-keys"))
+        .and(writable(state.clone()))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Admin))
+        .and_then(list_api_keys_handler);
+
+    let revoke_api_key_route = warp::delete()
+        .and(warp::path!("api" / "admin" / "api-keys" / String))
+        .and(writable(state.clone()))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Admin))
+        .and_then(revoke_api_key_handler);
+
+    let siwe_nonce_route = warp::get()
+        .and(warp::path!("api" / "auth" / "nonce"))
+        .and(state_filter.clone())
+        .and_then(siwe_nonce_handler);
+
+    let siwe_verify_route = warp::post()
+        .and(warp::path!("api" / "auth" / "siwe"))
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and_then(siwe_verify_handler);
+
+    let get_signers_route = warp::get()
+        .and(warp::path!("api" / "admin" / "signers"))
+        .and(writable(state.clone()))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Admin))
+        .and_then(get_signers_handler);
+
+    let metrics_route = warp::get()
+        .and(warp::path!("metrics"))
+        .and(state_filter.clone())
+        .and(with_auth(state.clone(), Scope::Admin))
+        .and_then(metrics_handler);
+
     let openapi_json_route = OpenAPIRoutes::openapi_json();
     let swagger_ui_route = OpenAPIRoutes::swagger_ui();
 
     // Combine the routes
-    let routes = get_route
+    get_route
         .or(post_route)
         .or(mint_nft_route)
+        .or(mint_batch_route)
+        .or(simulate_mint_route)
+        .or(get_mint_job_route)
+        .or(get_transaction_route)
+        .or(speed_up_transaction_route)
+        .or(cancel_transaction_route)
         .or(get_owner_tokens_route)
         .or(get_nft_route)
         .or(get_nft_sales_route)
         .or(get_auction_route)
+        .or(events_route)
+        .or(create_api_key_route)
+        .or(list_api_keys_route)
+        .or(revoke_api_key_route)
+        .or(siwe_nonce_route)
+        .or(siwe_verify_route)
+        .or(get_signers_route)
+        .or(metrics_route)
         .or(openapi_json_route)
         .or(swagger_ui_route)
-        .recover(handle_rejection);
-
-    routes
+        .recover(handle_rejection)
 }
 
 #[derive(Deserialize, Serialize, ToSchema)]
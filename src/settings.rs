@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+/// Every setting with the environment variables that override it, preferred name
+/// first. The later names are the ones older deployments set. A `*` segment stands
+/// for any name; those settings are overridden by the variable named after the full
+/// key, e.g. `CHAINS_SEPOLIA_RPC_URLS`.
+const SETTINGS: &[(&str, &[&str])] = &[
+    ("bind_address", &["BIND_ADDRESS"]),
+    ("read_only", &["READ_ONLY"]),
+    ("chains.*.chain_id", &[]),
+    ("chains.*.rpc_urls", &[]),
+    ("chains.*.rpc_timeout_secs", &[]),
+    ("chains.*.rpc_retries", &[]),
+    ("chains.*.rpc_quorum", &[]),
+    ("chains.*.ws_url", &[]),
+    ("chains.*.confirmations", &[]),
+    ("chains.*.explorer_url", &[]),
+    ("chains.*.graph_urls.*", &[]),
+    ("collections.*.nft_address", &[]),
+    ("collections.*.chain", &[]),
+    ("collections.*.graph_url", &[]),
+    ("collections.*.mongo_collection", &[]),
+    ("default_collection", &["DEFAULT_COLLECTION"]),
+    ("nft_chain", &["NFT_CHAIN"]),
+    ("auction_chain", &["AUCTION_CHAIN"]),
+    ("chain_id", &["CHAIN_ID", "ETH_SEPOLIA_CHAIN_ID"]),
+    ("chain_url", &["CHAIN_URL", "ETH_SEPOLIA_CHAIN_URL"]),
+    ("chain_ws_url", &["CHAIN_WS_URL"]),
+    ("nft_address", &["NFT_ADDRESS", "ETH_SEPOLIA_NFT_ADDRESS"]),
+    (
+        "auction_address",
+        &["AUCTION_ADDRESS", "BSC_TEST_AUCTION_ADDRESS"],
+    ),
+    ("graph_url_nft", &["GRAPH_URL_NFT"]),
+    ("graph_url_auction", &["GRAPH_URL_AUCTION"]),
+    ("alchemy_api_key", &["ALCHEMY_API_KEY"]),
+    ("jwt_secret", &["JWT_SECRET"]),
+    ("sandbox_api_key_enabled", &["SANDBOX_API_KEY_ENABLED"]),
+    ("siwe_domain", &["SIWE_DOMAIN"]),
+    ("mongo.uri", &["MONGO_URI"]),
+    ("mongo.host", &["MONGO_HOST"]),
+    ("mongo.app_name", &["MONGO_APP_NAME"]),
+    (
+        "mongo.username",
+        &["MONGO_USERNAME", "MONGO_ATLAS_USERNAME"],
+    ),
+    (
+        "mongo.password",
+        &["MONGO_PASSWORD", "MONGO_ATLAS_PASSWORD"],
+    ),
+    ("mongo.nft_collection", &["MONGO_NFT_COLLECTION"]),
+    ("signers.selection", &["SIGNER_SELECTION"]),
+    ("signers.private_keys", &["PRIVATE_KEYS", "PRIVATE_KEY"]),
+    ("signers.keystore_paths", &["KEYSTORE_PATHS"]),
+    ("signers.keystore_password", &["KEYSTORE_PASSWORD"]),
+    ("signers.remote_url", &["REMOTE_SIGNER_URL"]),
+    ("signers.remote_public_keys", &["REMOTE_SIGNER_PUBLIC_KEYS"]),
+    ("gas.max_fee_gwei", &["GAS_MAX_FEE_GWEI"]),
+    ("gas.priority_fee_gwei", &["GAS_PRIORITY_FEE_GWEI"]),
+    ("gas.fee_cap_action", &["GAS_FEE_CAP_ACTION"]),
+    (
+        "gas.escalation_coefficient",
+        &["GAS_ESCALATION_COEFFICIENT"],
+    ),
+    (
+        "gas.escalation_interval_secs",
+        &["GAS_ESCALATION_INTERVAL_SECS"],
+    ),
+    ("gas.stuck_after_minutes", &["GAS_STUCK_AFTER_MINUTES"]),
+    ("gas.limit_mint", &["GAS_LIMIT_MINT"]),
+    ("gas.limit_cancel", &["GAS_LIMIT_CANCEL"]),
+    ("low_balance.threshold_eth", &["LOW_BALANCE_THRESHOLD_ETH"]),
+    ("low_balance.webhook_url", &["LOW_BALANCE_WEBHOOK_URL"]),
+];
+
+/// Used when `SNAPIT_CONFIG` is unset. The server also starts without it.
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Everything wrong with the configuration, reported together so a deployment can be
+/// fixed in one go.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Raw settings merged from, by increasing priority, the top level of the config
+/// file, the selected profile of the file, and the environment. Any setting can be
+/// read from a file instead: `<key>_file` in the config, `<VAR>_FILE` in the
+/// environment. The typed getters record what is missing or malformed instead of
+/// failing on the first problem.
+pub struct Settings {
+    values: HashMap<String, String>,
+    problems: Vec<String>,
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let mut settings = Settings {
+            values: HashMap::new(),
+            problems: Vec::new(),
+        };
+
+        let (path, explicit) = match env::var("SNAPIT_CONFIG") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_PATH.to_string(), false),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => settings.merge_file(&path, &contents),
+            Err(e) if explicit || e.kind() != std::io::ErrorKind::NotFound => {
+                settings.problem(format!("Failed to read {}: {}", path, e))
+            }
+            Err(_) => {}
+        }
+        settings.merge_env();
+        settings
+    }
+
+    fn merge_file(&mut self, path: &str, contents: &str) {
+        let mut table = match contents.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => return self.problem(format!("{} is not valid TOML: {}", path, e)),
+        };
+
+        let profile_name = match env::var("SNAPIT_PROFILE") {
+            Ok(name) => Some(name),
+            Err(_) => match table.get("profile") {
+                Some(toml::Value::String(name)) => Some(name.clone()),
+                Some(_) => {
+                    self.problem(format!("profile in {} should be a string", path));
+                    None
+                }
+                None => None,
+            },
+        };
+        table.remove("profile");
+        let mut profiles = match table.remove("profiles") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => {
+                self.problem(format!("profiles in {} should be a table", path));
+                toml::Table::new()
+            }
+            None => toml::Table::new(),
+        };
+
+        self.merge_table("", table);
+        let Some(profile_name) = profile_name else {
+            return;
+        };
+        match profiles.remove(&profile_name) {
+            Some(toml::Value::Table(profile)) => self.merge_table("", profile),
+            Some(_) => self.problem(format!("profile {} should be a table", profile_name)),
+            None => {
+                let available: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                self.problem(format!(
+                    "Unknown profile {}; {} defines {}",
+                    profile_name,
+                    path,
+                    available.join(", ")
+                ));
+            }
+        }
+    }
+
+    fn merge_table(&mut self, prefix: &str, table: toml::Table) {
+        for (name, value) in table {
+            let key = format!("{}{}", prefix, name);
+            let value = match value {
+                toml::Value::Table(table) => {
+                    self.merge_table(&format!("{}.", key), table);
+                    continue;
+                }
+                toml::Value::String(value) => value,
+                toml::Value::Array(items) => items
+                    .into_iter()
+                    .map(|item| match item {
+                        toml::Value::String(item) => item,
+                        item => item.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+                value => value.to_string(),
+            };
+
+            if let Some(setting) = key.strip_suffix("_file").filter(|k| is_setting(k)) {
+                if let Some(value) = self.read_secret(&value) {
+                    self.values.insert(setting.to_string(), value);
+                }
+            } else if is_setting(&key) {
+                self.values.insert(key, value);
+            } else {
+                self.problem(format!("Unknown setting {}", key));
+            }
+        }
+    }
+
+    fn merge_env(&mut self) {
+        let mut keys: Vec<String> = Vec::new();
+        for (pattern, _) in SETTINGS {
+            let Some((prefix, rest)) = pattern.split_once('*') else {
+                keys.push(pattern.to_string());
+                continue;
+            };
+            // Only names the config file declared can be overridden.
+            for name in self.names(prefix.trim_end_matches('.')) {
+                let key = format!("{}{}{}", prefix, name, rest);
+                if let Some(inner) = key.strip_suffix('*') {
+                    // Inner names come from the file or from the variables themselves,
+                    // e.g. `CHAINS_SEPOLIA_GRAPH_URLS_NFT` for `graph_urls.nft`.
+                    let var_prefix = inner.to_uppercase().replace(['.', '-'], "_");
+                    keys.extend(self.values.keys().filter(|k| matches(&key, k)).cloned());
+                    keys.extend(env::vars().filter_map(|(var, _)| {
+                        let name = var.strip_prefix(&var_prefix)?.trim_end_matches("_FILE");
+                        if name.is_empty() {
+                            return None;
+                        }
+                        Some(format!("{}{}", inner, name.to_lowercase()))
+                    }));
+                } else {
+                    keys.push(key);
+                }
+            }
+        }
+
+        for key in keys {
+            for var in env_vars(&key) {
+                if let Ok(value) = env::var(&var) {
+                    self.values.insert(key.clone(), value);
+                    break;
+                }
+                if let Ok(path) = env::var(format!("{}_FILE", var)) {
+                    if let Some(value) = self.read_secret(&path) {
+                        self.values.insert(key.clone(), value);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    fn read_secret(&mut self, path: &str) -> Option<String> {
+        match std::fs::read_to_string(path) {
+            Ok(value) => Some(value.trim_end_matches(['\r', '\n']).to_string()),
+            Err(e) => {
+                self.problem(format!("Failed to read {}: {}", path, e));
+                None
+            }
+        }
+    }
+
+    pub fn problem(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+
+    /// The value of `key`, with empty values treated as unset.
+    pub fn optional(&self, key: &str) -> Option<String> {
+        self.values
+            .get(key)
+            .filter(|value| !value.is_empty())
+            .cloned()
+    }
+
+    pub fn required(&mut self, key: &str) -> Option<String> {
+        let value = self.optional(key);
+        if value.is_none() {
+            self.problem(format!("{} is not set", describe(key)));
+        }
+        value
+    }
+
+    /// Parses `key` if it is set. `expected` completes "should be ...".
+    pub fn parse<T: FromStr>(&mut self, key: &str, expected: &str) -> Option<T> {
+        let value = self.optional(key)?;
+        self.check(key, &value, expected, value.parse().ok())
+    }
+
+    pub fn parse_required<T: FromStr>(&mut self, key: &str, expected: &str) -> Option<T> {
+        let value = self.required(key)?;
+        self.check(key, &value, expected, value.parse().ok())
+    }
+
+    /// Parses `key` if it is set, rejecting zero.
+    pub fn parse_positive<T: FromStr + Default + PartialOrd>(&mut self, key: &str) -> Option<T> {
+        self.convert(key, "a positive integer", |value| {
+            value.parse().ok().filter(|parsed| *parsed > T::default())
+        })
+    }
+
+    /// Converts `key` with `convert` if it is set.
+    pub fn convert<T>(
+        &mut self,
+        key: &str,
+        expected: &str,
+        convert: impl FnOnce(&str) -> Option<T>,
+    ) -> Option<T> {
+        let value = self.optional(key)?;
+        let converted = convert(&value);
+        self.check(key, &value, expected, converted)
+    }
+
+    pub fn convert_required<T>(
+        &mut self,
+        key: &str,
+        expected: &str,
+        convert: impl FnOnce(&str) -> Option<T>,
+    ) -> Option<T> {
+        let value = self.required(key)?;
+        let converted = convert(&value);
+        self.check(key, &value, expected, converted)
+    }
+
+    /// The names under `prefix`, e.g. the chains for `chains`, in order.
+    pub fn names(&self, prefix: &str) -> Vec<String> {
+        let prefix = format!("{}.", prefix);
+        let mut names: Vec<String> = self
+            .values
+            .keys()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .filter_map(|rest| rest.split('.').next())
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// A comma separated list, skipping empty entries.
+    pub fn list(&self, key: &str) -> Option<Vec<String>> {
+        let value = self.optional(key)?;
+        Some(
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect(),
+        )
+    }
+
+    /// Records a malformed value. Secrets are not echoed back.
+    fn check<T>(&mut self, key: &str, value: &str, expected: &str, parsed: Option<T>) -> Option<T> {
+        if parsed.is_none() {
+            let shown = if is_secret(key) {
+                String::new()
+            } else {
+                format!(", got {:?}", value)
+            };
+            self.problem(format!("{} should be {}{}", describe(key), expected, shown));
+        }
+        parsed
+    }
+
+    /// Fails with every problem recorded so far.
+    pub fn finish(self) -> Result<(), ConfigError> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError {
+                problems: self.problems,
+            })
+        }
+    }
+}
+
+/// Whether `key` fits `pattern`, where a `*` segment matches any one segment.
+fn matches(pattern: &str, key: &str) -> bool {
+    let mut key_segments = key.split('.');
+    pattern.split('.').all(|segment| {
+        key_segments
+            .next()
+            .is_some_and(|k| segment == "*" || segment == k)
+    }) && key_segments.next().is_none()
+}
+
+fn is_setting(key: &str) -> bool {
+    SETTINGS.iter().any(|(pattern, _)| matches(pattern, key))
+}
+
+/// The environment variables of `key`, preferred first.
+fn env_vars(key: &str) -> Vec<String> {
+    match SETTINGS.iter().find(|(pattern, _)| matches(pattern, key)) {
+        Some((pattern, vars)) if !pattern.contains('*') => {
+            vars.iter().map(|var| var.to_string()).collect()
+        }
+        Some(_) => vec![key.to_uppercase().replace(['.', '-'], "_")],
+        None => Vec::new(),
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    // RPC URLs often carry an API key.
+    matches!(
+        key,
+        "jwt_secret"
+            | "alchemy_api_key"
+            | "mongo.uri"
+            | "mongo.password"
+            | "signers.private_keys"
+            | "signers.keystore_password"
+            | "chain_url"
+            | "chain_ws_url"
+    ) || matches("chains.*.rpc_urls", key)
+        || matches("chains.*.ws_url", key)
+}
+
+/// `key (ENV_VAR)`, so the message says how to fix it either way.
+pub fn describe(key: &str) -> String {
+    match env_vars(key).first() {
+        Some(var) => format!("{} ({})", key, var),
+        None => key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(values: &[(&str, &str)]) -> Settings {
+        Settings {
+            values: values
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            problems: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn rejects_zero_where_a_positive_integer_is_expected() {
+        let mut settings = settings(&[
+            ("chains.polygon.rpc_quorum", "0"),
+            ("chains.polygon.rpc_timeout_secs", "5"),
+        ]);
+        assert_eq!(
+            settings.parse_positive::<usize>("chains.polygon.rpc_quorum"),
+            None
+        );
+        assert_eq!(
+            settings.parse_positive::<u64>("chains.polygon.rpc_timeout_secs"),
+            Some(5)
+        );
+        assert_eq!(
+            settings.parse_positive::<usize>("chains.polygon.confirmations"),
+            None
+        );
+        assert_eq!(
+            settings.problems,
+            vec![format!(
+                "{} should be a positive integer, got \"0\"",
+                describe("chains.polygon.rpc_quorum")
+            )]
+        );
+    }
+}
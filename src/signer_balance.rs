@@ -0,0 +1,142 @@
+use chrono::{DateTime, Utc};
+use ethers::providers::Middleware;
+use ethers::types::{Address, U256};
+use futures::future::join_all;
+
+use crate::chain::chain::suggest_fees;
+use crate::db::mint_jobs::count_active_mint_jobs;
+use crate::error::ServerError;
+use crate::state::AppState;
+
+/// Gas assumed per mint when `GAS_LIMIT_MINT` is not configured.
+const DEFAULT_MINT_GAS: u64 = 150_000;
+
+/// Last known balance of a pool wallet.
+#[derive(Debug, Clone, Copy)]
+pub struct SignerBalance {
+    pub address: Address,
+    pub balance: U256,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl SignerBalance {
+    /// `true` when a threshold is configured and the balance is below it.
+    pub fn is_low(&self, threshold: Option<U256>) -> bool {
+        threshold.is_some_and(|threshold| self.balance < threshold)
+    }
+}
+
+/// Reads the balance of every pool wallet from the node and caches them in the state.
+pub async fn refresh_signer_balances(state: &AppState) -> Result<Vec<SignerBalance>, ServerError> {
+    let signers = state.signers()?.signers();
+    let balances = join_all(signers.iter().map(|signer| {
+        state
+            .chains
+            .nft()
+            .provider
+            .get_balance(signer.address, None)
+    }))
+    .await;
+
+    let checked_at = Utc::now();
+    let signer_balances = signers
+        .iter()
+        .zip(balances)
+        .map(|(signer, balance)| {
+            Ok(SignerBalance {
+                address: signer.address,
+                balance: balance?,
+                checked_at,
+            })
+        })
+        .collect::<Result<Vec<_>, ServerError>>()?;
+    *state.signer_balances.write().unwrap() = signer_balances.clone();
+    Ok(signer_balances)
+}
+
+/// The cached balances, or fresh ones if none were read yet.
+pub async fn signer_balances(state: &AppState) -> Result<Vec<SignerBalance>, ServerError> {
+    let cached = state.signer_balances.read().unwrap().clone();
+    if cached.is_empty() {
+        return refresh_signer_balances(state).await;
+    }
+    Ok(cached)
+}
+
+/// Fails with `InsufficientFunds` when the pool cannot pay the gas of `mints` mints on
+/// top of the jobs already queued or in flight, at the current fees. A mint is paid by
+/// a single wallet, so what a wallet holds beyond its last affordable mint does not
+/// count.
+pub async fn ensure_signer_can_afford(state: &AppState, mints: usize) -> Result<(), ServerError> {
+    let policy = &state.config.gas_policy;
+    let fees = suggest_fees(&state.chains.nft().provider, policy).await?;
+    let gas = policy
+        .mint_gas_limit
+        .unwrap_or_else(|| U256::from(DEFAULT_MINT_GAS));
+    let cost = gas * fees.max_fee_per_gas;
+
+    // The balance monitor keeps these fresh; jobs mined since its last read are not
+    // counted as outstanding, so they are paid out of the cached balance once.
+    let balances = signer_balances(state).await?;
+    let outstanding = count_active_mint_jobs(state.mongo_client.clone()).await?;
+    check_affordable(&balances, cost, outstanding, mints)
+}
+
+fn check_affordable(
+    balances: &[SignerBalance],
+    cost: U256,
+    outstanding: u64,
+    mints: usize,
+) -> Result<(), ServerError> {
+    let affordable: U256 = balances
+        .iter()
+        .map(|signer_balance| signer_balance.balance / cost.max(U256::one()))
+        .fold(U256::zero(), |total, mints| total + mints);
+    let required = U256::from(outstanding) + U256::from(mints);
+    if affordable < required {
+        return Err(ServerError::InsufficientFunds {
+            balance: balances.iter().fold(U256::zero(), |total, signer_balance| {
+                total + signer_balance.balance
+            }),
+            required: cost * required,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balances(wei: &[u64]) -> Vec<SignerBalance> {
+        wei.iter()
+            .enumerate()
+            .map(|(i, balance)| SignerBalance {
+                address: Address::from_low_u64_be(i as u64 + 1),
+                balance: U256::from(*balance),
+                checked_at: Utc::now(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_only_whole_mints_per_wallet() {
+        // 2 + 1 mints, though the 250 wei together would pay for a fourth.
+        let balances = balances(&[250, 150]);
+        assert!(check_affordable(&balances, U256::from(100), 0, 3).is_ok());
+        assert!(check_affordable(&balances, U256::from(100), 0, 4).is_err());
+    }
+
+    #[test]
+    fn reserves_the_cost_of_outstanding_jobs() {
+        let balances = balances(&[300]);
+        assert!(check_affordable(&balances, U256::from(100), 2, 1).is_ok());
+        match check_affordable(&balances, U256::from(100), 2, 2) {
+            Err(ServerError::InsufficientFunds { balance, required }) => {
+                assert_eq!(balance, U256::from(300));
+                assert_eq!(required, U256::from(400));
+            }
+            other => panic!("unexpected result {:?}", other.err()),
+        }
+    }
+}
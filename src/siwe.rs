@@ -0,0 +1,305 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use ethers::types::{Address, Signature};
+
+use crate::error::ServerError;
+
+const PREAMBLE_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+
+/// Clock skew tolerated when checking `Issued At` and `Not Before`.
+const ALLOWED_CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// An EIP-4361 (Sign-In With Ethereum) message.
+#[derive(Debug, Clone)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<FixedOffset>,
+    pub expiration_time: Option<DateTime<FixedOffset>>,
+    pub not_before: Option<DateTime<FixedOffset>>,
+}
+
+impl FromStr for SiweMessage {
+    type Err = ServerError;
+
+    fn from_str(message: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |reason: &str| ServerError::Validation(format!("Invalid SIWE message: {}", reason));
+
+        let mut lines = message.lines();
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(PREAMBLE_SUFFIX))
+            .ok_or_else(|| invalid("missing preamble"))?;
+        // The domain may be prefixed with a scheme, which is not part of the authority.
+        let domain = domain
+            .split_once("://")
+            .map_or(domain, |(_, authority)| authority)
+            .to_string();
+
+        let address = lines
+            .next()
+            .and_then(|line| Address::from_str(line.trim()).ok())
+            .ok_or_else(|| invalid("missing or malformed address"))?;
+
+        let mut has_uri = false;
+        let mut version = None;
+        let mut chain_id = None;
+        let mut nonce = None;
+        let mut issued_at = None;
+        let mut expiration_time = None;
+        let mut not_before = None;
+
+        // The optional statement and resources are not needed for verification.
+        for line in lines {
+            let Some((key, value)) = line.split_once(": ") else {
+                continue;
+            };
+            match key {
+                "URI" => has_uri = true,
+                "Version" => version = Some(value.to_string()),
+                "Chain ID" => {
+                    chain_id = Some(value.parse::<u64>().map_err(|_| invalid("bad chain id"))?)
+                }
+                "Nonce" => nonce = Some(value.to_string()),
+                "Issued At" => issued_at = Some(parse_timestamp(value)?),
+                "Expiration Time" => expiration_time = Some(parse_timestamp(value)?),
+                "Not Before" => not_before = Some(parse_timestamp(value)?),
+                _ => {}
+            }
+        }
+
+        if !has_uri {
+            return Err(invalid("missing URI"));
+        }
+
+        Ok(SiweMessage {
+            domain,
+            address,
+            version: version.ok_or_else(|| invalid("missing Version"))?,
+            chain_id: chain_id.ok_or_else(|| invalid("missing Chain ID"))?,
+            nonce: nonce.ok_or_else(|| invalid("missing Nonce"))?,
+            issued_at: issued_at.ok_or_else(|| invalid("missing Issued At"))?,
+            expiration_time,
+            not_before,
+        })
+    }
+}
+
+impl SiweMessage {
+    /// Checks everything except the nonce, which needs the database.
+    pub fn verify(
+        &self,
+        raw_message: &str,
+        signature: &str,
+        expected_domain: &str,
+        expected_chain_id: u64,
+    ) -> Result<(), ServerError> {
+        if self.version != "1" {
+            return Err(ServerError::Validation(format!(
+                "Unsupported SIWE version {}",
+                self.version
+            )));
+        }
+        if self.domain != expected_domain {
+            return Err(ServerError::Unauthorized(format!(
+                "SIWE domain {} does not match {}",
+                self.domain, expected_domain
+            )));
+        }
+        if self.chain_id != expected_chain_id {
+            return Err(ServerError::Unauthorized(format!(
+                "SIWE chain id {} does not match {}",
+                self.chain_id, expected_chain_id
+            )));
+        }
+
+        let now = Utc::now();
+        let skew = chrono::Duration::seconds(ALLOWED_CLOCK_SKEW_SECONDS);
+        if self.issued_at > now + skew {
+            return Err(ServerError::Unauthorized(
+                "SIWE message is issued in the future".to_string(),
+            ));
+        }
+        if matches!(self.expiration_time, Some(expiration) if expiration <= now) {
+            return Err(ServerError::Unauthorized(
+                "SIWE message has expired".to_string(),
+            ));
+        }
+        if matches!(self.not_before, Some(not_before) if not_before > now + skew) {
+            return Err(ServerError::Unauthorized(
+                "SIWE message is not valid yet".to_string(),
+            ));
+        }
+
+        let signature = Signature::from_str(signature.trim_start_matches("0x"))
+            .map_err(|_| ServerError::Validation("Malformed signature".to_string()))?;
+        signature
+            .verify(raw_message, self.address)
+            .map_err(|_| ServerError::Unauthorized("SIWE signature is not valid".to_string()))
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<FixedOffset>, ServerError> {
+    DateTime::parse_from_rfc3339(value)
+        .map_err(|_| ServerError::Validation(format!("Invalid SIWE timestamp: {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::utils::hex;
+
+    const DOMAIN: &str = "app.snapit.test";
+
+    fn wallet() -> LocalWallet {
+        "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse()
+            .unwrap()
+    }
+
+    fn message(
+        domain: &str,
+        chain_id: u64,
+        issued_at: DateTime<Utc>,
+        expiration_time: Option<DateTime<Utc>>,
+    ) -> String {
+        let mut message = format!(
+            "{} wants you to sign in with your Ethereum account:\n\
+             {:?}\n\
+             \n\
+             Sign in to Snapit\n\
+             \n\
+             URI: https://{}\n\
+             Version: 1\n\
+             Chain ID: {}\n\
+             Nonce: 32891756\n\
+             Issued At: {}",
+            domain,
+            wallet().address(),
+            domain,
+            chain_id,
+            issued_at.to_rfc3339()
+        );
+        if let Some(expiration_time) = expiration_time {
+            message.push_str(&format!(
+                "\nExpiration Time: {}",
+                expiration_time.to_rfc3339()
+            ));
+        }
+        message
+    }
+
+    async fn sign(message: &str) -> String {
+        let signature = wallet().sign_message(message).await.unwrap();
+        format!("0x{}", hex::encode(signature.to_vec()))
+    }
+
+    fn verify(message: &str, signature: &str) -> Result<(), ServerError> {
+        SiweMessage::from_str(message)?.verify(message, signature, DOMAIN, 137)
+    }
+
+    #[test]
+    fn parses_the_fields_needed_for_verification() {
+        let issued_at = Utc::now();
+        let raw = message(&format!("https://{}", DOMAIN), 137, issued_at, None);
+        let message = SiweMessage::from_str(&raw).unwrap();
+        assert_eq!(message.domain, DOMAIN);
+        assert_eq!(message.address, wallet().address());
+        assert_eq!(message.version, "1");
+        assert_eq!(message.chain_id, 137);
+        assert_eq!(message.nonce, "32891756");
+        assert_eq!(message.issued_at.timestamp(), issued_at.timestamp());
+        assert!(message.expiration_time.is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let valid = message(DOMAIN, 137, Utc::now(), None);
+        let cases = [
+            (
+                valid.replacen(" wants you", " would like you", 1),
+                "missing preamble",
+            ),
+            (
+                valid.replace(&format!("{:?}", wallet().address()), "0x1234"),
+                "missing or malformed address",
+            ),
+            (valid.replace("URI: ", "Url: "), "missing URI"),
+            (
+                valid.replace("Chain ID: 137", "Chain ID: polygon"),
+                "bad chain id",
+            ),
+            (valid.replace("Nonce: 32891756\n", ""), "missing Nonce"),
+        ];
+        for (message, reason) in cases {
+            match SiweMessage::from_str(&message) {
+                Err(ServerError::Validation(error)) => {
+                    assert_eq!(error, format!("Invalid SIWE message: {}", reason))
+                }
+                other => panic!("expected {:?}, got {:?}", reason, other),
+            }
+        }
+        let bad_timestamp = valid.replace("Issued At: ", "Issued At: yesterday ");
+        assert!(matches!(
+            SiweMessage::from_str(&bad_timestamp),
+            Err(ServerError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn accepts_a_message_signed_by_its_address() {
+        let message = message(DOMAIN, 137, Utc::now(), None);
+        assert!(verify(&message, &sign(&message).await).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_other_domains_and_chains() {
+        let other_domain = message("evil.test", 137, Utc::now(), None);
+        assert!(matches!(
+            verify(&other_domain, &sign(&other_domain).await),
+            Err(ServerError::Unauthorized(_))
+        ));
+        let other_chain = message(DOMAIN, 1, Utc::now(), None);
+        assert!(matches!(
+            verify(&other_chain, &sign(&other_chain).await),
+            Err(ServerError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_and_future_messages() {
+        let hour = chrono::Duration::hours(1);
+        let expired = message(DOMAIN, 137, Utc::now() - hour, Some(Utc::now() - hour / 2));
+        assert!(matches!(
+            verify(&expired, &sign(&expired).await),
+            Err(ServerError::Unauthorized(_))
+        ));
+        let future = message(DOMAIN, 137, Utc::now() + hour, None);
+        assert!(matches!(
+            verify(&future, &sign(&future).await),
+            Err(ServerError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_signatures() {
+        let message = message(DOMAIN, 137, Utc::now(), None);
+        let other = self::message(DOMAIN, 137, Utc::now() - chrono::Duration::seconds(5), None);
+        // Signed by the right key, but over a different message.
+        assert!(matches!(
+            verify(&message, &sign(&other).await),
+            Err(ServerError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            verify(&message, "0x1234"),
+            Err(ServerError::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result};
+use mongodb::bson::oid::ObjectId;
+use mongodb::Client;
+use tokio::sync::{broadcast, Mutex, Notify};
+use warp::{Filter, Rejection};
+
+use crate::chain::chain::EthersClient;
+use crate::chain::events::ChainEvent;
+use crate::chain::registry::ChainRegistry;
+use crate::chain::signer_pool::SignerPool;
+use crate::collections::CollectionRegistry;
+use crate::constants::Constants;
+use crate::db::api_keys::create_api_key_indexes;
+use crate::db::idempotency_keys::create_idempotency_key_indexes;
+use crate::db::mint_jobs::{assign_legacy_mint_jobs, create_mint_job_indexes};
+use crate::db::mongo::{assign_legacy_contract_metadata, create_nft_indexes, init_db};
+use crate::db::siwe_nonces::create_siwe_nonce_indexes;
+use crate::db::token_ids::{
+    assign_legacy_token_ids, create_token_id_reservation_indexes, seed_token_id_counter,
+};
+use crate::db::transactions::{assign_legacy_transactions, create_transaction_indexes};
+use crate::error::ServerError;
+use crate::signer_balance::SignerBalance;
+
+/// Events a slow subscriber can fall behind by before it misses some.
+const EVENT_BUFFER: usize = 1024;
+
+/// Long-lived application state, built once at startup and shared by every route.
+pub struct AppState {
+    pub config: Arc<Constants>,
+    pub mongo_client: Arc<Client>,
+    /// Configured networks with their read clients.
+    pub chains: ChainRegistry,
+    /// NFT contracts served by this deployment.
+    pub collections: CollectionRegistry,
+    /// Wallets that send mints, each with its own nonce manager. `None` when read-only.
+    signers: Option<SignerPool>,
+    /// Pooled HTTP client for Graph and Alchemy requests.
+    pub http_client: reqwest::Client,
+    /// Wakes the mint worker when a job is enqueued.
+    pub mint_job_notify: Notify,
+    /// Names this process in the leases it takes on mint jobs.
+    pub instance_id: String,
+    /// Held while a stuck transaction is being replaced.
+    pub tx_replacement_lock: Mutex<()>,
+    /// Balances of the pool wallets, refreshed by the balance monitor.
+    pub signer_balances: RwLock<Vec<SignerBalance>>,
+    /// Live events of the watched contracts, published by the event listener.
+    pub events: broadcast::Sender<ChainEvent>,
+}
+
+impl AppState {
+    pub async fn new(config: Constants) -> Result<Self> {
+        let config = Arc::new(config);
+
+        let chains = ChainRegistry::from_config(&config)?;
+        let collections = CollectionRegistry::from_config(&config, &chains)?;
+
+        let mongo_client = Arc::new(init_db(config.clone()).await?);
+        // Documents from before collections existed belong to the default collection.
+        let default_collection = collections.default().slug();
+        assign_legacy_mint_jobs(mongo_client.clone(), default_collection).await?;
+        assign_legacy_token_ids(mongo_client.clone(), default_collection).await?;
+        assign_legacy_contract_metadata(mongo_client.clone(), default_collection).await?;
+        assign_legacy_transactions(mongo_client.clone(), default_collection).await?;
+        create_api_key_indexes(mongo_client.clone()).await?;
+        create_siwe_nonce_indexes(mongo_client.clone()).await?;
+        create_mint_job_indexes(mongo_client.clone()).await?;
+        create_idempotency_key_indexes(mongo_client.clone()).await?;
+        create_token_id_reservation_indexes(mongo_client.clone()).await?;
+        create_transaction_indexes(mongo_client.clone()).await?;
+        for collection in collections.all() {
+            // Existing duplicate tokens make this fail; they have to be removed first.
+            create_nft_indexes(mongo_client.clone(), &collection.config.mongo_collection)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to create the unique token_id index of {}",
+                        collection.slug()
+                    )
+                })?;
+            seed_token_id_counter(
+                mongo_client.clone(),
+                collection.slug(),
+                &collection.config.mongo_collection,
+            )
+            .await?;
+        }
+        let signers = if config.read_only {
+            None
+        } else {
+            Some(SignerPool::from_config(&config, &chains.nft().provider).await?)
+        };
+        let http_client = reqwest::Client::new();
+
+        Ok(AppState {
+            config,
+            mongo_client,
+            chains,
+            collections,
+            signers,
+            http_client,
+            mint_job_notify: Notify::new(),
+            instance_id: ObjectId::new().to_hex(),
+            tx_replacement_lock: Mutex::new(()),
+            signer_balances: RwLock::new(Vec::new()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        })
+    }
+
+    /// The signer pool, unavailable on a read-only server.
+    pub fn signers(&self) -> Result<&SignerPool, ServerError> {
+        self.signers
+            .as_ref()
+            .ok_or_else(|| ServerError::Unavailable("This server is read-only".to_string()))
+    }
+
+    /// Client of the primary signer, for simulations.
+    pub fn ethers_client(&self) -> Result<&Arc<EthersClient>, ServerError> {
+        Ok(&self.signers()?.primary().client)
+    }
+}
+
+pub fn with_state(
+    state: Arc<AppState>,
+) -> impl Filter<Extract = (Arc<AppState>,), Error = Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
+
+/// Rejects with 503 on a read-only server. Guards the routes that send transactions
+/// or administer the server.
+pub fn writable(state: Arc<AppState>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let result = state.signers().map(|_| ()).map_err(warp::reject::custom);
+            async move { result }
+        })
+        .untuple_one()
+}
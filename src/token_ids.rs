@@ -0,0 +1,116 @@
+use chrono::Utc;
+use ethers::utils::to_checksum;
+use mongodb::bson::DateTime;
+
+use crate::chain::mint::owner_of;
+use crate::collections::Collection;
+use crate::db::mint_jobs::has_active_mint_job;
+use crate::db::mongo::find_known_token_ids;
+use crate::db::token_ids::{
+    delete_token_id_reservation, insert_token_id_reservation, next_token_id,
+    reclaim_expired_token_id_reservation, TokenIdReservation,
+};
+use crate::error::ServerError;
+use crate::state::AppState;
+
+/// How long an allocated id is held for its mint before it can be handed out again.
+const TOKEN_ID_RESERVATION_TTL_MINUTES: i64 = 60;
+
+/// Gives up after this many allocated ids turn out to be taken.
+const MAX_ALLOCATION_ATTEMPTS: usize = 20;
+
+/// Reserves a token id of `collection` that has no metadata, no mint in flight and no
+/// owner on chain. Expired reservations are reused before the counter is advanced.
+pub async fn allocate_token_id(
+    state: &AppState,
+    collection: &Collection,
+    reserved_by: &str,
+) -> Result<u64, ServerError> {
+    for _ in 0..MAX_ALLOCATION_ATTEMPTS {
+        let expires_at = Utc::now() + chrono::Duration::minutes(TOKEN_ID_RESERVATION_TTL_MINUTES);
+        let expires_at = DateTime::from_millis(expires_at.timestamp_millis());
+
+        let reclaimed = reclaim_expired_token_id_reservation(
+            state.mongo_client.clone(),
+            collection.slug(),
+            reserved_by,
+            expires_at,
+        )
+        .await?;
+        let token_id = match reclaimed {
+            Some(reservation) => reservation.token_id,
+            None => {
+                let token_id = next_token_id(state.mongo_client.clone(), collection.slug()).await?;
+                let reservation = TokenIdReservation {
+                    collection: collection.slug().to_string(),
+                    token_id,
+                    reserved_by: reserved_by.to_string(),
+                    expires_at,
+                };
+                insert_token_id_reservation(state.mongo_client.clone(), &reservation).await?;
+                token_id
+            }
+        };
+
+        // A job that outlived its reservation still owns the id; the reclaim above has
+        // pushed the expiry back, so just move on.
+        if has_active_mint_job(state.mongo_client.clone(), collection.slug(), token_id).await? {
+            continue;
+        }
+
+        let known = find_known_token_ids(
+            state.mongo_client.clone(),
+            &collection.config.mongo_collection,
+            &[token_id],
+        )
+        .await?;
+        let taken = !known.is_empty() || owner_of(collection, token_id).await?.is_some();
+        if !taken {
+            return Ok(token_id);
+        }
+
+        // Minted outside of this API; never hand it out again.
+        delete_token_id_reservation(state.mongo_client.clone(), collection.slug(), token_id)
+            .await?;
+    }
+
+    Err(ServerError::Unavailable(
+        "Could not allocate a free token id".to_string(),
+    ))
+}
+
+/// Fails with `TokenAlreadyMinted` when `token_id` has an owner on chain.
+pub async fn ensure_not_owned(collection: &Collection, token_id: u64) -> Result<(), ServerError> {
+    match owner_of(collection, token_id).await? {
+        Some(owner) => Err(ServerError::TokenAlreadyMinted {
+            token_id,
+            owner: to_checksum(&owner, None),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Rejects a requested id that is owned on chain, has metadata or has a mint in flight.
+pub async fn ensure_token_id_available(
+    state: &AppState,
+    collection: &Collection,
+    token_id: u64,
+) -> Result<(), ServerError> {
+    ensure_not_owned(collection, token_id).await?;
+
+    let known = find_known_token_ids(
+        state.mongo_client.clone(),
+        &collection.config.mongo_collection,
+        &[token_id],
+    )
+    .await?;
+    if !known.is_empty()
+        || has_active_mint_job(state.mongo_client.clone(), collection.slug(), token_id).await?
+    {
+        return Err(ServerError::Conflict(format!(
+            "Token {} is already being minted",
+            token_id
+        )));
+    }
+    Ok(())
+}
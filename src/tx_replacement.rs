@@ -0,0 +1,120 @@
+use chrono::Utc;
+use ethers::providers::Middleware;
+use ethers::types::H256;
+
+use crate::chain::chain::{replace_transaction, Replacement, TransactionReceiptSchema};
+use crate::db::mint_jobs::update_mint_job_tx_hash;
+use crate::db::transactions::{
+    find_transaction, insert_transaction, mark_transaction_dropped, mark_transaction_mined,
+    mark_transaction_replaced, TransactionRecord, TxPurpose, TxStatus,
+};
+use crate::error::ServerError;
+use crate::state::AppState;
+
+/// Replaces a pending server transaction with a higher-fee copy or a cancellation and
+/// returns the record of the new transaction. Replacements are serialized, so the
+/// monitor and an admin never race on the same nonce.
+pub async fn replace_stuck_transaction(
+    state: &AppState,
+    tx_hash: H256,
+    replacement: Replacement,
+) -> Result<TransactionRecord, ServerError> {
+    let _guard = state.tx_replacement_lock.lock().await;
+
+    let tx_hash_hex = format!("{:?}", tx_hash);
+    let record = find_transaction(state.mongo_client.clone(), &tx_hash_hex)
+        .await?
+        .ok_or_else(|| ServerError::NotFound(format!("Transaction {} not found", tx_hash_hex)))?;
+    if record.status != TxStatus::Pending {
+        return Err(ServerError::Conflict(format!(
+            "Transaction {} is no longer pending",
+            tx_hash_hex
+        )));
+    }
+
+    let provider = &state.chains.nft().provider;
+    if let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? {
+        let receipt = TransactionReceiptSchema::from(receipt);
+        mark_transaction_mined(state.mongo_client.clone(), &tx_hash_hex, &receipt, 1).await?;
+        return Err(ServerError::Conflict(format!(
+            "Transaction {} is already mined",
+            tx_hash_hex
+        )));
+    }
+    let Some(original) = provider.get_transaction(tx_hash).await? else {
+        // Neither mined nor in the mempool, so it will not be mined or replaced anymore.
+        if let Err(e) = mark_transaction_dropped(state.mongo_client.clone(), &tx_hash_hex).await {
+            eprintln!(
+                "Failed to mark transaction {} as dropped: {}",
+                tx_hash_hex, e
+            );
+        }
+        return Err(ServerError::Conflict(format!(
+            "Transaction {} is no longer known to the node",
+            tx_hash_hex
+        )));
+    };
+
+    let signer = state.signers()?.by_address(original.from).ok_or_else(|| {
+        ServerError::Conflict(format!(
+            "Transaction {} was not sent by a configured signer",
+            tx_hash_hex
+        ))
+    })?;
+
+    let pending_secs =
+        (Utc::now().timestamp_millis() - record.created_at.timestamp_millis()).max(0) as u64 / 1000;
+    let sent = replace_transaction(
+        &signer.client,
+        &state.config.gas_policy,
+        &original,
+        replacement,
+        pending_secs,
+    )
+    .await?;
+
+    let purpose = match replacement {
+        Replacement::SpeedUp => record.purpose,
+        Replacement::Cancel => TxPurpose::Cancel,
+    };
+    let mut new_record = TransactionRecord::new(
+        sent.hash,
+        purpose,
+        &record.collection,
+        sent.transaction.as_ref(),
+    );
+    new_record.token_id = record.token_id;
+    new_record.mint_job_id = record.mint_job_id;
+    new_record.replaces = Some(tx_hash_hex.clone());
+    new_record.nonce_sent_at = Some(record.nonce_pending_since());
+
+    // The replacement is out already, so keep going and only report failed bookkeeping.
+    if let Err(e) = insert_transaction(state.mongo_client.clone(), &new_record).await {
+        eprintln!("Failed to record transaction {}: {}", new_record.tx_hash, e);
+    }
+    if let Err(e) = mark_transaction_replaced(
+        state.mongo_client.clone(),
+        &tx_hash_hex,
+        &new_record.tx_hash,
+    )
+    .await
+    {
+        eprintln!(
+            "Failed to mark transaction {} as replaced: {}",
+            tx_hash_hex, e
+        );
+    }
+    if let Some(job_id) = record.mint_job_id {
+        if let Err(e) =
+            update_mint_job_tx_hash(state.mongo_client.clone(), job_id, &new_record.tx_hash).await
+        {
+            eprintln!("Failed to update the tx hash of mint job {}: {}", job_id, e);
+        }
+    }
+
+    println!(
+        "Replaced transaction {} with {} ({:?})",
+        tx_hash_hex, new_record.tx_hash, replacement
+    );
+    Ok(new_record)
+}